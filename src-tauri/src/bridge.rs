@@ -1,18 +1,27 @@
 use crate::{
-    config::ConnectionConfig,
+    config::{ConnectionConfig, Protocol},
+    discovery::{self, BeaconHandle, MdnsHandle},
     input::InputManager,
-    network::{Client, Server, ServerHandle, ClientHandle},
+    layout::{ClientScreen, ScreenPlacement},
+    network::{self, Client, ClientHandle, LinkState, Server, ServerHandle},
+    relay::{self, RelayHandle, RelayStats},
+    signaling::{self, SignalingHandle},
+    state::{ConnectionState, StateMachine, StateTransition},
+    transport::QuicTransport,
 };
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{broadcast, mpsc, Mutex};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BridgeMode {
     Server,
     Client,
+    // Pairs and forwards for other peers; does not share this machine's input
+    Relay,
     Disconnected,
 }
 
@@ -23,6 +32,13 @@ pub struct MouseBridgeService {
     input_manager: Arc<InputManager>,
     config: Arc<Mutex<ConnectionConfig>>,
     server_info: Arc<Mutex<Option<ServerInfo>>>,
+    beacon: Arc<Mutex<Option<BeaconHandle>>>,
+    mdns: Arc<Mutex<Option<MdnsHandle>>>,
+    relay: Arc<Mutex<Option<RelayHandle>>>,
+    // Runs independently of the server/client mode
+    signaling: Arc<Mutex<Option<SignalingHandle>>>,
+    // Shared with whichever server, client or relay is running
+    states: Arc<StateMachine>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +47,9 @@ pub struct ServerInfo {
     pub ip: String,
     pub port: u16,
     pub fingerprint: String,
+    // Hex SHA-256 of the certificate a QUIC server presents, for clients to pin
+    #[serde(default)]
+    pub quic_cert_sha256: Option<String>,
 }
 
 impl MouseBridgeService {
@@ -42,6 +61,11 @@ impl MouseBridgeService {
             input_manager: Arc::new(InputManager::new()),
             config: Arc::new(Mutex::new(ConnectionConfig::default())),
             server_info: Arc::new(Mutex::new(None)),
+            beacon: Arc::new(Mutex::new(None)),
+            mdns: Arc::new(Mutex::new(None)),
+            relay: Arc::new(Mutex::new(None)),
+            signaling: Arc::new(Mutex::new(None)),
+            states: StateMachine::new(),
         })
     }
 
@@ -52,11 +76,10 @@ impl MouseBridgeService {
         }
 
         // Stop any existing connections
-        self.stop_server().await?;
-        self.disconnect_client().await?;
+        self.shut_down(&mut mode).await?;
 
         // Create server info
-        let server_info = ServerInfo {
+        let mut server_info = ServerInfo {
             hostname: hostname::get()
                 .unwrap_or_default()
                 .to_string_lossy()
@@ -64,11 +87,52 @@ impl MouseBridgeService {
             ip: local_ipaddress::get().unwrap_or_else(|| "127.0.0.1".to_string()),
             port: config.port,
             fingerprint: Uuid::new_v4().to_string(),
+            quic_cert_sha256: None,
         };
 
         // Start server
-        let server = Server::new(config.clone(), self.input_manager.clone()).await?;
-        let server_handle = server.start().await?;
+        let server = Server::new(
+            config.clone(),
+            self.input_manager.clone(),
+            server_info.fingerprint.clone(),
+        )
+        .await;
+        let server_handle = match server {
+            // Made here rather than by the server, so its certificate can be announced
+            Ok(server) if is_quic(&config) => match QuicTransport::server() {
+                Ok(quic) => {
+                    server_info.quic_cert_sha256 = quic.certificate_sha256();
+                    server
+                        .with_transport(Arc::new(quic))
+                        .with_states(self.states.clone())
+                        .start()
+                        .await
+                }
+                Err(e) => Err(e),
+            },
+            Ok(server) => server.with_states(self.states.clone()).start().await,
+            Err(e) => Err(e),
+        }
+        .inspect_err(|e| self.states.fail(e))?;
+        let bind_ip = server_handle.local_addr().ip();
+        server_info.port = server_handle.local_addr().port();
+        if !bind_ip.is_unspecified() {
+            server_info.ip = bind_ip.to_string();
+        }
+
+        // Announce the server on the LAN
+        if config.discovery.enabled {
+            match discovery::start_beacon(&server_info, &config.discovery, bind_ip).await {
+                Ok(beacon) => *self.beacon.lock().await = Some(beacon),
+                Err(e) => log::warn!("Failed to start discovery beacon: {}", e),
+            }
+        }
+        if config.discovery.enabled && config.discovery.mdns {
+            match discovery::advertise_mdns(&server_info, bind_ip) {
+                Ok(mdns) => *self.mdns.lock().await = Some(mdns),
+                Err(e) => log::warn!("Failed to advertise via mDNS: {}", e),
+            }
+        }
 
         // Update state
         *mode = BridgeMode::Server;
@@ -84,17 +148,41 @@ impl MouseBridgeService {
         if !matches!(*mode, BridgeMode::Server) {
             return Ok(());
         }
+        self.shut_down(&mut mode).await
+    }
 
-        // Stop server
-        if let Some(server) = self.server.lock().await.take() {
-            server.stop().await?;
+    // Stops whatever the current mode runs. Takes the caller's guard rather than locking the
+    // mode itself, because the lock isn't reentrant and every caller already holds it.
+    async fn shut_down(&self, mode: &mut BridgeMode) -> Result<()> {
+        match *mode {
+            BridgeMode::Server => {
+                if let Some(server) = self.server.lock().await.take() {
+                    server.stop().await?;
+                }
+                if let Some(beacon) = self.beacon.lock().await.take() {
+                    beacon.stop().await?;
+                }
+                if let Some(mdns) = self.mdns.lock().await.take() {
+                    mdns.stop().await?;
+                }
+                *self.server_info.lock().await = None;
+            }
+            BridgeMode::Client => {
+                if let Some(client) = self.client.lock().await.take() {
+                    client.disconnect().await?;
+                }
+            }
+            BridgeMode::Relay => {
+                if let Some(relay) = self.relay.lock().await.take() {
+                    relay.stop().await?;
+                }
+            }
+            BridgeMode::Disconnected => {}
         }
 
         // Update state
         *mode = BridgeMode::Disconnected;
-        *self.server_info.lock().await = None;
-
-        Ok(())
+        self.states.transition(ConnectionState::Idle)
     }
 
     pub async fn connect_client(&self, config: ConnectionConfig) -> Result<()> {
@@ -104,12 +192,15 @@ impl MouseBridgeService {
         }
 
         // Stop any existing connections
-        self.stop_server().await?;
-        self.disconnect_client().await?;
+        self.shut_down(&mut mode).await?;
 
         // Start client
-        let client = Client::new(config.clone(), self.input_manager.clone()).await?;
-        let client_handle = client.connect().await?;
+        let client = Client::new(config.clone(), self.input_manager.clone()).await;
+        let client_handle = match client {
+            Ok(client) => client.with_states(self.states.clone()).connect().await,
+            Err(e) => Err(e),
+        }
+        .inspect_err(|e| self.states.fail(e))?;
 
         // Update state
         *mode = BridgeMode::Client;
@@ -124,33 +215,164 @@ impl MouseBridgeService {
         if !matches!(*mode, BridgeMode::Client) {
             return Ok(());
         }
+        self.shut_down(&mut mode).await
+    }
 
-        // Stop client
-        if let Some(client) = self.client.lock().await.take() {
-            client.disconnect().await?;
+    // Listens on config.port (and config.bind) for peers that dial in through the relay
+    pub async fn start_relay(&self, config: ConnectionConfig) -> Result<SocketAddr> {
+        let mut mode = self.mode.lock().await;
+        if matches!(*mode, BridgeMode::Relay) {
+            if let Some(relay) = self.relay.lock().await.as_ref() {
+                return Ok(relay.local_addr());
+            }
         }
 
-        // Update state
-        *mode = BridgeMode::Disconnected;
+        // Stop any existing connections
+        self.shut_down(&mut mode).await?;
+
+        let bind_ip = network::resolve_bind_address(config.bind.as_deref())?;
+        let relay = relay::start_relay_server(SocketAddr::new(bind_ip, config.port))
+            .await
+            .inspect_err(|e| self.states.fail(e))?;
+        let local_addr = relay.local_addr();
+        self.states.transition(ConnectionState::Listening)?;
+
+        *mode = BridgeMode::Relay;
+        *self.config.lock().await = config;
+        *self.relay.lock().await = Some(relay);
 
+        Ok(local_addr)
+    }
+
+    pub async fn stop_relay(&self) -> Result<()> {
+        let mut mode = self.mode.lock().await;
+        if !matches!(*mode, BridgeMode::Relay) {
+            return Ok(());
+        }
+        self.shut_down(&mut mode).await
+    }
+
+    pub async fn get_relay_stats(&self) -> Result<RelayStats> {
+        match self.relay.lock().await.as_ref() {
+            Some(relay) => Ok(relay.stats().await),
+            None => Err(anyhow::anyhow!("Relay not running")),
+        }
+    }
+
+    // Standalone signaling server for WebRTC peers that are configured to meet through it
+    pub async fn start_signaling_server(
+        &self,
+        port: u16,
+        bind: Option<String>,
+    ) -> Result<SocketAddr> {
+        let mut signaling = self.signaling.lock().await;
+        if let Some(server) = signaling.as_ref() {
+            return Ok(server.local_addr());
+        }
+
+        let bind_ip = network::resolve_bind_address(bind.as_deref())?;
+        let server = signaling::start_signaling_server(SocketAddr::new(bind_ip, port)).await?;
+        let local_addr = server.local_addr();
+        *signaling = Some(server);
+        Ok(local_addr)
+    }
+
+    pub async fn stop_signaling_server(&self) -> Result<()> {
+        if let Some(server) = self.signaling.lock().await.take() {
+            server.stop().await?;
+        }
         Ok(())
     }
 
+    pub fn connection_state(&self) -> ConnectionState {
+        self.states.current()
+    }
+
+    // Every transition from now on, in order
+    pub fn subscribe_state(&self) -> broadcast::Receiver<StateTransition> {
+        self.states.subscribe()
+    }
+
     pub async fn get_connection_status(&self) -> Result<crate::ConnectionStatus> {
         let mode = self.mode.lock().await;
         let config = self.config.lock().await;
 
+        let link_state = match *mode {
+            BridgeMode::Client => match self.client.lock().await.as_ref() {
+                Some(client) => Some(client.state().await),
+                None => None,
+            },
+            _ => None,
+        };
+
+        let input_latency = match *mode {
+            BridgeMode::Client => match self.client.lock().await.as_ref() {
+                Some(client) => client.input_latency().await,
+                None => None,
+            },
+            _ => None,
+        };
+
+        let link = match *mode {
+            BridgeMode::Client => match self.client.lock().await.as_ref() {
+                Some(client) => Some(client.link_stats().await),
+                None => None,
+            },
+            // With several clients attached, report the slowest one
+            BridgeMode::Server => match self.server.lock().await.as_ref() {
+                Some(server) => server
+                    .link_stats()
+                    .await
+                    .into_iter()
+                    .map(|(_, stats)| stats)
+                    .max_by(|a, b| a.rtt_ms.unwrap_or(0.0).total_cmp(&b.rtt_ms.unwrap_or(0.0))),
+                None => None,
+            },
+            BridgeMode::Relay | BridgeMode::Disconnected => None,
+        };
+
         Ok(crate::ConnectionStatus {
-            connected: !matches!(*mode, BridgeMode::Disconnected),
+            connected: match *mode {
+                BridgeMode::Client => {
+                    matches!(link_state, Some(LinkState::Connected))
+                        && link.is_some_and(|link| link.alive)
+                }
+                BridgeMode::Server | BridgeMode::Relay => true,
+                BridgeMode::Disconnected => false,
+            },
             mode: format!("{:?}", *mode),
+            state: self.states.current(),
             remote_address: match *mode {
                 BridgeMode::Client => Some(format!("{}:{}", config.host, config.port)),
                 _ => None,
             },
-            latency_ms: None, // TODO: Implement latency measurement
+            latency_ms: link.and_then(|link| link.rtt_ms).map(|rtt| rtt.round() as u64),
+            jitter_ms: link.and_then(|link| link.jitter_ms),
+            last_seen_ms: link.map(|link| link.last_seen_ms),
+            link_state,
+            clock_offset_ms: link.and_then(|link| link.clock_offset_ms),
+            input_latency,
         })
     }
 
+    pub async fn get_connected_clients(&self) -> Result<Vec<ClientScreen>> {
+        match self.server.lock().await.as_ref() {
+            Some(server) => Ok(server.clients().await),
+            None => Err(anyhow::anyhow!("Server not running")),
+        }
+    }
+
+    pub async fn set_client_placement(
+        &self,
+        fingerprint: String,
+        placement: ScreenPlacement,
+    ) -> Result<()> {
+        match self.server.lock().await.as_ref() {
+            Some(server) => server.set_placement(&fingerprint, placement).await,
+            None => Err(anyhow::anyhow!("Server not running")),
+        }
+    }
+
     pub async fn get_server_info(&self) -> Result<crate::ServerInfo> {
         let server_info = self.server_info.lock().await;
         server_info
@@ -160,7 +382,13 @@ impl MouseBridgeService {
                 ip: info.ip.clone(),
                 port: info.port,
                 fingerprint: info.fingerprint.clone(),
+                quic_cert_sha256: info.quic_cert_sha256.clone(),
             })
             .ok_or_else(|| anyhow::anyhow!("Server not running"))
     }
-} 
\ No newline at end of file
+}
+
+// Relayed links are carried by the relay whatever the protocol
+fn is_quic(config: &ConnectionConfig) -> bool {
+    config.relay.is_none() && matches!(config.protocol, Protocol::QUIC)
+}
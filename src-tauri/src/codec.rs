@@ -0,0 +1,424 @@
+use crate::input::{MouseEvent, Positioning};
+use crate::network::NetworkMessage;
+use anyhow::Result;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+// Compact encoding for the messages sent many times a second. Every message starts with a
+// fixed header:
+//
+//   byte 0     codec version
+//   byte 1     message type
+//   bytes 2-3  payload length, big-endian u16
+//
+// Integers in the payload are LEB128 varints; signed ones (coordinates, deltas, wheel steps)
+// are zigzag-encoded first, so small moves in either direction take a single byte. A mouse
+// event is:
+//
+//   flags      u8: 0x01 relative, 0x02 pressed, 0x04 wheel follows, 0x08 timestamp follows
+//   buttons    u8 bitfield: 0x01 left, 0x02 right, 0x04 middle; at most one bit is set
+//   x, y       zigzag varints
+//   wheel      zigzag varints wheel_x, wheel_y, if flagged
+//   timestamp  varint captured_at_us, if flagged
+//
+// Decoders ignore unknown flag bits and anything left over at the end of a payload, so
+// fields can be appended without a new version. Everything else, and any event this layout
+// can't express, stays JSON.
+//
+// Payloads of COMPRESSION_THRESHOLD bytes or more, in either encoding, are compressed with
+// the negotiated algorithm when that makes them smaller, and wrapped as:
+//
+//   byte 0     0x10
+//   byte 1     algorithm: 0x01 LZ4 block, 0x02 raw deflate
+//   bytes 2-5  uncompressed length, big-endian u32
+//   rest       compressed payload
+//
+// JSON always starts with `{` or `"`, the envelope with 0x10 and the version byte stays below
+// that, so a receiver tells the three apart by the first byte.
+//
+// tests/codec_vectors.json holds reference encodings for other implementations to check
+// against.
+pub const CODEC_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 4;
+
+// Input events never get near this; clipboard contents and files often do
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+// Bound on what a compressed payload may expand to, so a small frame can't exhaust memory
+pub const MAX_DECOMPRESSED_LEN: usize = 16 * 1024 * 1024;
+
+const COMPRESSED: u8 = 0x10;
+const COMPRESSED_HEADER_LEN: usize = 6;
+
+const TYPE_MOUSE_EVENT: u8 = 0x01;
+const TYPE_MOTION: u8 = 0x02;
+const TYPE_HEARTBEAT: u8 = 0x03;
+const TYPE_HEARTBEAT_ACK: u8 = 0x04;
+const TYPE_INPUT_ACK: u8 = 0x05;
+const TYPE_GOODBYE: u8 = 0x06;
+
+const FLAG_RELATIVE: u8 = 0x01;
+const FLAG_PRESSED: u8 = 0x02;
+const FLAG_WHEEL: u8 = 0x04;
+const FLAG_TIMESTAMP: u8 = 0x08;
+
+const BUTTONS: [(&str, u8); 3] = [("left", 0x01), ("right", 0x02), ("middle", 0x04)];
+
+// How a connection encodes what it sends; the default is plain JSON. Every combination is
+// always accepted on receipt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WireFormat {
+    // Negotiated through Capabilities::binary_codec
+    pub binary: bool,
+    // Negotiated through Capabilities::compression
+    pub compression: Option<Compression>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    // Fast enough to leave on for every bulk payload
+    Lz4,
+    // Smaller output, for peers without LZ4
+    Deflate,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::Lz4 => 0x01,
+            Compression::Deflate => 0x02,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0x01 => Ok(Compression::Lz4),
+            0x02 => Ok(Compression::Deflate),
+            other => Err(anyhow::anyhow!(
+                "Unknown compression algorithm {:#04x}",
+                other
+            )),
+        }
+    }
+}
+
+pub fn encode(message: &NetworkMessage, format: WireFormat) -> Result<Vec<u8>> {
+    let plain = encode_plain(message, format)?;
+    match format.compression {
+        Some(algorithm) => compress(plain, algorithm),
+        None => Ok(plain),
+    }
+}
+
+// The encoding before compression
+pub fn encode_plain(message: &NetworkMessage, format: WireFormat) -> Result<Vec<u8>> {
+    if format.binary {
+        if let Some(encoded) = encode_binary(message) {
+            return Ok(encoded);
+        }
+    }
+    Ok(serde_json::to_vec(message)?)
+}
+
+pub fn decode(payload: &[u8]) -> Result<NetworkMessage> {
+    match payload.first() {
+        Some(&COMPRESSED) => decode_plain(&decompress(payload)?),
+        _ => decode_plain(payload),
+    }
+}
+
+fn decode_plain(payload: &[u8]) -> Result<NetworkMessage> {
+    if is_binary(payload) {
+        decode_binary(payload)
+    } else {
+        Ok(serde_json::from_slice(payload)?)
+    }
+}
+
+// A payload's size before compression, read from the envelope header without decompressing.
+// Anything not in the envelope went out as it was.
+pub fn plain_len(payload: &[u8]) -> usize {
+    match payload {
+        [COMPRESSED, _, a, b, c, d, ..] => u32::from_be_bytes([*a, *b, *c, *d]) as usize,
+        _ => payload.len(),
+    }
+}
+
+// True for the binary encoding and the compressed envelope, false for JSON
+pub fn is_binary(payload: &[u8]) -> bool {
+    payload.first().is_some_and(|&first| first < 0x20)
+}
+
+// Wraps a payload in the compressed envelope, or hands it back untouched if it is too small
+// to bother with or doesn't shrink, which is common for images that are compressed already
+pub fn compress(plain: Vec<u8>, algorithm: Compression) -> Result<Vec<u8>> {
+    if plain.len() < COMPRESSION_THRESHOLD || plain.len() > MAX_DECOMPRESSED_LEN {
+        return Ok(plain);
+    }
+
+    let mut compressed = Vec::with_capacity(COMPRESSED_HEADER_LEN + plain.len() / 2);
+    compressed.push(COMPRESSED);
+    compressed.push(algorithm.id());
+    compressed.extend_from_slice(&(plain.len() as u32).to_be_bytes());
+    match algorithm {
+        Compression::Lz4 => compressed.extend_from_slice(&lz4_flex::block::compress(&plain)),
+        Compression::Deflate => {
+            let mut encoder = DeflateEncoder::new(compressed, flate2::Compression::default());
+            encoder.write_all(&plain)?;
+            compressed = encoder.finish()?;
+        }
+    }
+
+    if compressed.len() >= plain.len() {
+        return Ok(plain);
+    }
+    Ok(compressed)
+}
+
+fn decompress(envelope: &[u8]) -> Result<Vec<u8>> {
+    if envelope.len() < COMPRESSED_HEADER_LEN {
+        return Err(anyhow::anyhow!("Truncated compression header"));
+    }
+    let algorithm = Compression::from_id(envelope[1])?;
+    let len = u32::from_be_bytes([envelope[2], envelope[3], envelope[4], envelope[5]]) as usize;
+    if len > MAX_DECOMPRESSED_LEN {
+        return Err(anyhow::anyhow!(
+            "Compressed message expands to {} bytes, over the limit",
+            len
+        ));
+    }
+
+    let data = &envelope[COMPRESSED_HEADER_LEN..];
+    let plain = match algorithm {
+        Compression::Lz4 => lz4_flex::block::decompress(data, len)
+            .map_err(|e| anyhow::anyhow!("Invalid LZ4 payload: {}", e))?,
+        Compression::Deflate => {
+            let mut plain = Vec::with_capacity(len);
+            // One byte past the declared length is enough to tell that it lied
+            DeflateDecoder::new(data)
+                .take(len as u64 + 1)
+                .read_to_end(&mut plain)?;
+            plain
+        }
+    };
+    if plain.len() != len {
+        return Err(anyhow::anyhow!(
+            "Decompressed {} bytes, header says {}",
+            plain.len(),
+            len
+        ));
+    }
+    Ok(plain)
+}
+
+// None if the message has no compact form
+fn encode_binary(message: &NetworkMessage) -> Option<Vec<u8>> {
+    let mut payload = Vec::new();
+    let message_type = match message {
+        NetworkMessage::MouseEvent(event) => {
+            write_event(&mut payload, event)?;
+            TYPE_MOUSE_EVENT
+        }
+        NetworkMessage::Motion { seq, event } => {
+            write_varint(&mut payload, *seq);
+            write_event(&mut payload, event)?;
+            TYPE_MOTION
+        }
+        NetworkMessage::Heartbeat { timestamp_us } => {
+            write_varint(&mut payload, *timestamp_us);
+            TYPE_HEARTBEAT
+        }
+        NetworkMessage::HeartbeatAck {
+            echo_timestamp_us,
+            receive_us,
+            transmit_us,
+        } => {
+            write_varint(&mut payload, *echo_timestamp_us);
+            write_varint(&mut payload, *receive_us);
+            write_varint(&mut payload, *transmit_us);
+            TYPE_HEARTBEAT_ACK
+        }
+        NetworkMessage::InputAck { received } => {
+            write_varint(&mut payload, *received);
+            TYPE_INPUT_ACK
+        }
+        NetworkMessage::Goodbye => TYPE_GOODBYE,
+        _ => return None,
+    };
+
+    let len = u16::try_from(payload.len()).ok()?;
+    let mut encoded = Vec::with_capacity(HEADER_LEN + payload.len());
+    encoded.push(CODEC_VERSION);
+    encoded.push(message_type);
+    encoded.extend_from_slice(&len.to_be_bytes());
+    encoded.extend_from_slice(&payload);
+    Some(encoded)
+}
+
+fn decode_binary(encoded: &[u8]) -> Result<NetworkMessage> {
+    if encoded.len() < HEADER_LEN {
+        return Err(anyhow::anyhow!("Truncated message header"));
+    }
+    let version = encoded[0];
+    if version == 0 || version > CODEC_VERSION {
+        return Err(anyhow::anyhow!("Unsupported codec version {}", version));
+    }
+    let len = u16::from_be_bytes([encoded[2], encoded[3]]) as usize;
+    if encoded.len() != HEADER_LEN + len {
+        return Err(anyhow::anyhow!(
+            "Message length {} does not match its header ({})",
+            encoded.len() - HEADER_LEN,
+            len
+        ));
+    }
+
+    let mut reader = Reader {
+        payload: &encoded[HEADER_LEN..],
+    };
+    let message = match encoded[1] {
+        TYPE_MOUSE_EVENT => NetworkMessage::MouseEvent(reader.event()?),
+        TYPE_MOTION => NetworkMessage::Motion {
+            seq: reader.varint()?,
+            event: reader.event()?,
+        },
+        TYPE_HEARTBEAT => NetworkMessage::Heartbeat {
+            timestamp_us: reader.varint()?,
+        },
+        TYPE_HEARTBEAT_ACK => NetworkMessage::HeartbeatAck {
+            echo_timestamp_us: reader.varint()?,
+            receive_us: reader.varint()?,
+            transmit_us: reader.varint()?,
+        },
+        TYPE_INPUT_ACK => NetworkMessage::InputAck {
+            received: reader.varint()?,
+        },
+        TYPE_GOODBYE => NetworkMessage::Goodbye,
+        other => return Err(anyhow::anyhow!("Unknown message type {:#04x}", other)),
+    };
+    Ok(message)
+}
+
+// None for a button name the bitfield has no bit for
+fn write_event(payload: &mut Vec<u8>, event: &MouseEvent) -> Option<()> {
+    let buttons = match &event.button {
+        Some(name) => BUTTONS.iter().find(|(button, _)| button == name)?.1,
+        None => 0,
+    };
+    let wheel = event.wheel_x != 0 || event.wheel_y != 0;
+
+    let mut flags = 0;
+    if event.is_relative() {
+        flags |= FLAG_RELATIVE;
+    }
+    if event.pressed {
+        flags |= FLAG_PRESSED;
+    }
+    if wheel {
+        flags |= FLAG_WHEEL;
+    }
+    if event.captured_at_us != 0 {
+        flags |= FLAG_TIMESTAMP;
+    }
+
+    payload.push(flags);
+    payload.push(buttons);
+    write_signed(payload, event.x);
+    write_signed(payload, event.y);
+    if wheel {
+        write_signed(payload, event.wheel_x);
+        write_signed(payload, event.wheel_y);
+    }
+    if event.captured_at_us != 0 {
+        write_varint(payload, event.captured_at_us);
+    }
+    Some(())
+}
+
+fn write_varint(payload: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        payload.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    payload.push(value as u8);
+}
+
+fn write_signed(payload: &mut Vec<u8>, value: i32) {
+    write_varint(payload, ((value << 1) ^ (value >> 31)) as u32 as u64);
+}
+
+struct Reader<'a> {
+    payload: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8> {
+        let (&byte, rest) = self
+            .payload
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("Truncated message payload"))?;
+        self.payload = rest;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow::anyhow!("Varint is too long"))
+    }
+
+    fn signed(&mut self) -> Result<i32> {
+        let zigzag = u32::try_from(self.varint()?)
+            .map_err(|_| anyhow::anyhow!("Signed varint out of range"))?;
+        Ok((zigzag >> 1) as i32 ^ -((zigzag & 1) as i32))
+    }
+
+    fn event(&mut self) -> Result<MouseEvent> {
+        let flags = self.byte()?;
+        let buttons = self.byte()?;
+        let button = match buttons {
+            0 => None,
+            bits => Some(
+                BUTTONS
+                    .iter()
+                    .find(|(_, bit)| *bit == bits)
+                    .map(|(name, _)| name.to_string())
+                    .ok_or_else(|| anyhow::anyhow!("Invalid button bits {:#04x}", bits))?,
+            ),
+        };
+        let x = self.signed()?;
+        let y = self.signed()?;
+        let (wheel_x, wheel_y) = if flags & FLAG_WHEEL != 0 {
+            (self.signed()?, self.signed()?)
+        } else {
+            (0, 0)
+        };
+        let captured_at_us = if flags & FLAG_TIMESTAMP != 0 {
+            self.varint()?
+        } else {
+            0
+        };
+
+        Ok(MouseEvent {
+            x,
+            y,
+            button,
+            pressed: flags & FLAG_PRESSED != 0,
+            wheel_x,
+            wheel_y,
+            positioning: if flags & FLAG_RELATIVE != 0 {
+                Positioning::Relative
+            } else {
+                Positioning::Absolute
+            },
+            captured_at_us,
+        })
+    }
+}
@@ -1,5 +1,7 @@
+use crate::layout::ScreenPlacement;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +17,104 @@ pub struct ConnectionConfig {
     pub port: u16,
     pub protocol: Protocol,
     pub timeout_ms: u64,
+    // Interface name or IP address the server listens on; None listens everywhere
+    #[serde(default)]
+    pub bind: Option<String>,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    // Preferred screen position of each client, keyed by device name
+    #[serde(default)]
+    pub placements: HashMap<String, ScreenPlacement>,
+    #[serde(default)]
+    pub signaling: SignalingConfig,
+    // When set, both peers dial out to this relay instead of to each other, whatever the protocol
+    #[serde(default)]
+    pub relay: Option<RelayConfig>,
+    // Server side: how much a single peer may send before it is throttled or banned
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    // Client side: hex SHA-256 of the QUIC server's certificate, from its discovery beacon.
+    // QUIC refuses to connect without it.
+    #[serde(default)]
+    pub quic_cert_sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconnectConfig {
+    pub enabled: bool,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub max_attempts: Option<u32>,
+    // How long the server holds a dropped client's session for it to resume
+    #[serde(default = "default_resume_grace_ms")]
+    pub resume_grace_ms: u64,
+}
+
+fn default_resume_grace_ms() -> u64 {
+    10000
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    pub enabled: bool,
+    pub port: u16,
+    pub interval_ms: u64,
+    pub mdns: bool,
+}
+
+// Only used by the WebRTC protocol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SignalingConfig {
+    // host:port of a standalone signaling server; None means the server takes offers on its
+    // own port
+    pub server: Option<String>,
+    pub room: String,
+    // Not needed on one LAN, where host candidates connect directly
+    pub stun_servers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayConfig {
+    // host:port of the relay
+    pub address: String,
+    // Shared by the two peers and never sent to the relay. It pairs them and keys the
+    // encryption, so it should be long and random.
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    // New connections from one address
+    pub connections: BucketConfig,
+    // Connection and resume requests
+    pub handshake: BucketConfig,
+    // Heartbeats, acks and other small protocol messages
+    pub control: BucketConfig,
+    pub input: BucketConfig,
+    // Clipboard contents and files
+    pub bulk: BucketConfig,
+    // Connections that have yet to finish their handshake, across all peers
+    pub max_pending_handshakes: usize,
+    // Largest message a peer may send, measured before compression. The transports refuse
+    // anything bigger as its length is read, which on most of them drops the connection.
+    // Capped at MAX_FRAME_LEN, which is also the default.
+    pub max_message_bytes: usize,
+    // Limit hits within a minute that get an address banned
+    pub violations_before_ban: u32,
+    pub ban_ms: u64,
+}
+
+// A token bucket: `burst` messages at once, refilled at `per_second`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BucketConfig {
+    pub per_second: f64,
+    pub burst: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,6 +136,8 @@ pub enum Protocol {
     WebRTC,
     UDP,
     TCP,
+    WebSocket,
+    QUIC,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -62,6 +164,79 @@ impl Default for ConnectionConfig {
             port: 4242,
             protocol: Protocol::WebRTC,
             timeout_ms: 5000,
+            bind: None,
+            reconnect: ReconnectConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            placements: HashMap::new(),
+            signaling: SignalingConfig::default(),
+            relay: None,
+            rate_limits: RateLimitConfig::default(),
+            quic_cert_sha256: None,
+        }
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            initial_delay_ms: 500,
+            max_delay_ms: 30000,
+            max_attempts: None,
+            resume_grace_ms: default_resume_grace_ms(),
+        }
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            port: 4243,
+            interval_ms: 1000,
+            mdns: true,
+        }
+    }
+}
+
+impl Default for SignalingConfig {
+    fn default() -> Self {
+        Self {
+            server: None,
+            room: "mousebridge".to_string(),
+            stun_servers: Vec::new(),
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            connections: BucketConfig {
+                per_second: 2.0,
+                burst: 10,
+            },
+            handshake: BucketConfig {
+                per_second: 1.0,
+                burst: 5,
+            },
+            control: BucketConfig {
+                per_second: 50.0,
+                burst: 100,
+            },
+            input: BucketConfig {
+                per_second: 500.0,
+                burst: 1000,
+            },
+            bulk: BucketConfig {
+                per_second: 5.0,
+                burst: 10,
+            },
+            max_pending_handshakes: 16,
+            max_message_bytes: crate::network::MAX_FRAME_LEN,
+            violations_before_ban: 50,
+            ban_ms: 60000,
         }
     }
 }
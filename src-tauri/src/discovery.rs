@@ -0,0 +1,343 @@
+use crate::{bridge::ServerInfo, config::DiscoveryConfig, protocol};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+// Lets listeners ignore unrelated broadcast traffic that happens to hit the discovery port
+const BEACON_SERVICE: &str = "mousebridge";
+const MAX_BEACON_LEN: usize = 1024;
+const MDNS_SERVICE_TYPE: &str = "_mousebridge._tcp.local.";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Beacon {
+    pub service: String,
+    pub hostname: String,
+    pub port: u16,
+    pub fingerprint: String,
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+    #[serde(default)]
+    pub quic_cert_sha256: Option<String>,
+}
+
+impl Beacon {
+    pub fn from_server_info(info: &ServerInfo) -> Self {
+        Self {
+            service: BEACON_SERVICE.to_string(),
+            hostname: info.hostname.clone(),
+            port: info.port,
+            fingerprint: info.fingerprint.clone(),
+            protocol_version: protocol::PROTOCOL_VERSION,
+            min_protocol_version: protocol::MIN_PROTOCOL_VERSION,
+            quic_cert_sha256: info.quic_cert_sha256.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveredServer {
+    pub hostname: String,
+    pub ip: String,
+    pub port: u16,
+    pub fingerprint: String,
+    pub protocol_version: u32,
+    pub compatible: bool,
+    // Only servers speaking QUIC announce one; pass it on as ConnectionConfig::quic_cert_sha256
+    pub quic_cert_sha256: Option<String>,
+    pub last_seen: DateTime<Utc>,
+}
+
+pub struct BeaconHandle {
+    stop_tx: mpsc::Sender<()>,
+}
+
+impl BeaconHandle {
+    pub async fn stop(&self) -> Result<()> {
+        let _ = self.stop_tx.send(()).await;
+        Ok(())
+    }
+}
+
+// Announces the server every interval to the LAN broadcast address, and to loopback so a
+// client on the same machine finds it even when broadcast is filtered. A server bound to one
+// IPv4 address sends its beacons from that address only.
+pub async fn start_beacon(
+    info: &ServerInfo,
+    config: &DiscoveryConfig,
+    bind_ip: IpAddr,
+) -> Result<BeaconHandle> {
+    let payload = serde_json::to_vec(&Beacon::from_server_info(info))?;
+    let source = match bind_ip {
+        IpAddr::V4(address) => address,
+        IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+    };
+    let socket = UdpSocket::bind((source, 0)).await?;
+    socket.set_broadcast(true)?;
+
+    let targets = [
+        SocketAddrV4::new(Ipv4Addr::BROADCAST, config.port),
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, config.port),
+    ];
+    let interval = Duration::from_millis(config.interval_ms.max(100));
+    let (stop_tx, mut stop_rx) = mpsc::channel::<()>(1);
+
+    log::info!(
+        "Announcing {} on discovery port {}",
+        info.fingerprint,
+        config.port
+    );
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = stop_rx.recv() => break,
+                _ = ticker.tick() => {
+                    for target in targets {
+                        if let Err(e) = socket.send_to(&payload, target).await {
+                            log::debug!("Failed to send beacon to {}: {}", target, e);
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(BeaconHandle { stop_tx })
+}
+
+pub struct MdnsHandle {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl MdnsHandle {
+    pub async fn stop(&self) -> Result<()> {
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            log::debug!("Failed to unregister {}: {}", self.fullname, e);
+        }
+        let _ = self.daemon.shutdown();
+        Ok(())
+    }
+}
+
+// Registers the server as _mousebridge._tcp so avahi-browse, dns-sd and friends can see it.
+// The fingerprint suffix keeps instance names unique when one host runs several servers.
+pub fn advertise_mdns(info: &ServerInfo, bind_ip: IpAddr) -> Result<MdnsHandle> {
+    let daemon = ServiceDaemon::new()?;
+    let instance = format!(
+        "{} ({})",
+        info.hostname,
+        info.fingerprint.chars().take(8).collect::<String>()
+    );
+    let mut properties = HashMap::from([
+        ("hostname".to_string(), info.hostname.clone()),
+        ("fingerprint".to_string(), info.fingerprint.clone()),
+        (
+            "version".to_string(),
+            protocol::PROTOCOL_VERSION.to_string(),
+        ),
+        (
+            "min_version".to_string(),
+            protocol::MIN_PROTOCOL_VERSION.to_string(),
+        ),
+    ]);
+    if let Some(sha256) = &info.quic_cert_sha256 {
+        properties.insert("quic_cert_sha256".to_string(), sha256.clone());
+    }
+
+    // Only publish the bound address when the server doesn't listen everywhere
+    let service = if bind_ip.is_unspecified() {
+        ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &instance,
+            &format!("{}.local.", info.hostname),
+            "",
+            info.port,
+            properties,
+        )?
+        .enable_addr_auto()
+    } else {
+        ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &instance,
+            &format!("{}.local.", info.hostname),
+            bind_ip,
+            info.port,
+            properties,
+        )?
+    };
+    let fullname = service.get_fullname().to_string();
+    daemon.register(service)?;
+
+    log::info!("Advertising {} via mDNS", fullname);
+    Ok(MdnsHandle { daemon, fullname })
+}
+
+// Beacons and mDNS are queried side by side; either one failing (no multicast route, port
+// taken) only loses that source
+pub async fn discover_servers(port: u16, timeout: Duration) -> Result<Vec<DiscoveredServer>> {
+    let (beacons, mdns) = tokio::join!(
+        discover_servers_on(port, timeout),
+        browse_mdns(timeout)
+    );
+
+    let mut servers: HashMap<String, DiscoveredServer> = HashMap::new();
+    match (beacons, mdns) {
+        (Err(beacon_error), Err(mdns_error)) => {
+            return Err(anyhow::anyhow!(
+                "Server discovery failed: {}; {}",
+                beacon_error,
+                mdns_error
+            ))
+        }
+        (beacons, mdns) => {
+            for result in [mdns, beacons] {
+                match result {
+                    Ok(found) => {
+                        for server in found {
+                            servers.insert(server.fingerprint.clone(), server);
+                        }
+                    }
+                    Err(e) => log::warn!("Partial server discovery: {}", e),
+                }
+            }
+        }
+    }
+
+    Ok(sorted(servers))
+}
+
+pub async fn browse_mdns(timeout: Duration) -> Result<Vec<DiscoveredServer>> {
+    let daemon = ServiceDaemon::new()?;
+    let events = daemon.browse(MDNS_SERVICE_TYPE)?;
+    let mut servers: HashMap<String, DiscoveredServer> = HashMap::new();
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        let event = match tokio::time::timeout_at(deadline, events.recv_async()).await {
+            Ok(Ok(event)) => event,
+            Ok(Err(_)) | Err(_) => break,
+        };
+
+        if let ServiceEvent::ServiceResolved(service) = event {
+            match server_from_mdns(&service) {
+                Some(server) => {
+                    servers.insert(server.fingerprint.clone(), server);
+                }
+                None => log::debug!("Ignoring incomplete mDNS record {}", service.get_fullname()),
+            }
+        }
+    }
+
+    let _ = daemon.stop_browse(MDNS_SERVICE_TYPE);
+    let _ = daemon.shutdown();
+    Ok(sorted(servers))
+}
+
+fn server_from_mdns(service: &ServiceInfo) -> Option<DiscoveredServer> {
+    let fingerprint = service.get_property_val_str("fingerprint")?.to_string();
+    let protocol_version: u32 = service.get_property_val_str("version")?.parse().ok()?;
+    let min_protocol_version: u32 = service
+        .get_property_val_str("min_version")
+        .and_then(|version| version.parse().ok())
+        .unwrap_or(protocol_version);
+    let addresses = service.get_addresses();
+    let ip = addresses
+        .iter()
+        .find(|address| address.is_ipv4())
+        .or_else(|| addresses.iter().next())?;
+    let hostname = service
+        .get_property_val_str("hostname")
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+            service
+                .get_hostname()
+                .trim_end_matches(".local.")
+                .to_string()
+        });
+
+    Some(DiscoveredServer {
+        hostname,
+        ip: ip.to_string(),
+        port: service.get_port(),
+        fingerprint,
+        protocol_version,
+        compatible: is_compatible(min_protocol_version, protocol_version),
+        quic_cert_sha256: service
+            .get_property_val_str("quic_cert_sha256")
+            .map(str::to_string),
+        last_seen: Utc::now(),
+    })
+}
+
+// Collects beacons for the whole timeout and returns one entry per server fingerprint
+pub async fn discover_servers_on(port: u16, timeout: Duration) -> Result<Vec<DiscoveredServer>> {
+    let socket = bind_listener(port)
+        .with_context(|| format!("Failed to listen for beacons on port {}", port))?;
+    let mut servers: HashMap<String, DiscoveredServer> = HashMap::new();
+    let mut buffer = vec![0u8; MAX_BEACON_LEN];
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        let (len, from) =
+            match tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+                Ok(received) => received?,
+                Err(_) => break,
+            };
+
+        let beacon = match serde_json::from_slice::<Beacon>(&buffer[..len]) {
+            Ok(beacon) if beacon.service == BEACON_SERVICE => beacon,
+            _ => {
+                log::debug!("Ignoring unrecognised datagram from {}", from);
+                continue;
+            }
+        };
+
+        servers.insert(
+            beacon.fingerprint.clone(),
+            DiscoveredServer {
+                hostname: beacon.hostname,
+                ip: from.ip().to_string(),
+                port: beacon.port,
+                fingerprint: beacon.fingerprint,
+                protocol_version: beacon.protocol_version,
+                compatible: is_compatible(beacon.min_protocol_version, beacon.protocol_version),
+                quic_cert_sha256: beacon.quic_cert_sha256,
+                last_seen: Utc::now(),
+            },
+        );
+    }
+
+    Ok(sorted(servers))
+}
+
+fn is_compatible(min_protocol_version: u32, protocol_version: u32) -> bool {
+    min_protocol_version <= protocol::PROTOCOL_VERSION
+        && protocol_version >= protocol::MIN_PROTOCOL_VERSION
+}
+
+fn sorted(servers: HashMap<String, DiscoveredServer>) -> Vec<DiscoveredServer> {
+    let mut servers: Vec<DiscoveredServer> = servers.into_values().collect();
+    servers.sort_by(|a, b| a.hostname.cmp(&b.hostname).then(a.ip.cmp(&b.ip)));
+    servers
+}
+
+// Address reuse lets several clients (or the app and a test) listen on the port at once
+fn bind_listener(port: u16) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
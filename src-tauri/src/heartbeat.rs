@@ -0,0 +1,359 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+const MIN_HEARTBEAT_INTERVAL_MS: u64 = 250;
+const MAX_HEARTBEAT_INTERVAL_MS: u64 = 1000;
+
+// RFC 6298 smoothing factors for the RTT and RTT variance estimates
+const RTT_ALPHA: f64 = 0.125;
+const RTT_BETA: f64 = 0.25;
+
+// Clock offset samples kept; the one that crossed the link fastest is trusted, as in NTP's
+// clock filter, since queueing delay on either leg skews the offset by up to half of it
+const CLOCK_SAMPLES: usize = 8;
+// Capture-to-inject latencies kept for percentiles
+const LATENCY_SAMPLES: usize = 1024;
+
+// Send several heartbeats per timeout window so a single lost one never trips dead-peer detection
+pub fn interval_for_timeout(timeout_ms: u64) -> Duration {
+    Duration::from_millis(
+        (timeout_ms / 4).clamp(MIN_HEARTBEAT_INTERVAL_MS, MAX_HEARTBEAT_INTERVAL_MS),
+    )
+}
+
+// Microseconds on a process-local monotonic clock. Each peer's clock starts at its own
+// arbitrary epoch; LinkMonitor estimates the offset between the two from heartbeats.
+pub fn monotonic_us() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct LinkStats {
+    pub alive: bool,
+    pub rtt_ms: Option<f64>,
+    pub jitter_ms: Option<f64>,
+    pub last_rtt_ms: Option<f64>,
+    pub last_seen_ms: u64,
+    // Peer clock minus ours
+    pub clock_offset_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClockSample {
+    offset_us: i64,
+    delay_us: u64,
+}
+
+// Liveness and round-trip tracking for one connection, fed by the session loop
+#[derive(Debug)]
+pub struct LinkMonitor {
+    timeout: Duration,
+    last_seen: Instant,
+    srtt_us: Option<f64>,
+    rttvar_us: f64,
+    last_rtt_us: Option<u64>,
+    clock: VecDeque<ClockSample>,
+    closed: bool,
+}
+
+impl LinkMonitor {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            last_seen: Instant::now(),
+            srtt_us: None,
+            rttvar_us: 0.0,
+            last_rtt_us: None,
+            clock: VecDeque::with_capacity(CLOCK_SAMPLES),
+            closed: false,
+        }
+    }
+
+    // Any traffic from the peer counts as proof of life, not only heartbeats
+    pub fn record_activity(&mut self) {
+        self.last_seen = Instant::now();
+    }
+
+    // `receive_us` and `transmit_us` are when the peer got our heartbeat and sent the ack, on
+    // its own clock. Peers that predate clock sync leave both at zero.
+    pub fn record_echo(&mut self, echo_timestamp_us: u64, receive_us: u64, transmit_us: u64) {
+        self.record_echo_at(echo_timestamp_us, receive_us, transmit_us, monotonic_us());
+    }
+
+    fn record_echo_at(
+        &mut self,
+        echo_timestamp_us: u64,
+        receive_us: u64,
+        transmit_us: u64,
+        now: u64,
+    ) {
+        if echo_timestamp_us > now {
+            // Not one of ours; a confused or malicious peer
+            return;
+        }
+        let rtt_us = now - echo_timestamp_us;
+        self.record_rtt(rtt_us);
+
+        if receive_us == 0 || transmit_us < receive_us {
+            return;
+        }
+        // NTP: t0 sent and t3 received here, t1 received and t2 sent by the peer
+        let held_us = transmit_us - receive_us;
+        let offset_us = ((receive_us as i64 - echo_timestamp_us as i64)
+            + (transmit_us as i64 - now as i64))
+            / 2;
+        if self.clock.len() >= CLOCK_SAMPLES {
+            self.clock.pop_front();
+        }
+        self.clock.push_back(ClockSample {
+            offset_us,
+            delay_us: rtt_us.saturating_sub(held_us),
+        });
+    }
+
+    // Peer clock minus ours, from the least delayed recent sample
+    pub fn clock_offset_us(&self) -> Option<i64> {
+        self.clock
+            .iter()
+            .min_by_key(|sample| sample.delay_us)
+            .map(|sample| sample.offset_us)
+    }
+
+    pub fn record_rtt(&mut self, sample_us: u64) {
+        let sample = sample_us as f64;
+        match self.srtt_us {
+            None => {
+                self.srtt_us = Some(sample);
+                self.rttvar_us = sample / 2.0;
+            }
+            Some(srtt) => {
+                self.rttvar_us =
+                    (1.0 - RTT_BETA) * self.rttvar_us + RTT_BETA * (srtt - sample).abs();
+                self.srtt_us = Some((1.0 - RTT_ALPHA) * srtt + RTT_ALPHA * sample);
+            }
+        }
+        self.last_rtt_us = Some(sample_us);
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.last_seen.elapsed() > self.timeout
+    }
+
+    pub fn mark_closed(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        LinkStats {
+            alive: !self.closed && !self.is_timed_out(),
+            rtt_ms: self.srtt_us.map(|us| us / 1000.0),
+            jitter_ms: self.srtt_us.map(|_| self.rttvar_us / 1000.0),
+            last_rtt_ms: self.last_rtt_us.map(|us| us as f64 / 1000.0),
+            last_seen_ms: self.last_seen.elapsed().as_millis() as u64,
+            clock_offset_ms: self.clock_offset_us().map(|us| us as f64 / 1000.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub samples: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+// The most recent capture-to-inject latencies
+#[derive(Debug, Default)]
+pub struct LatencyWindow {
+    samples_us: VecDeque<u64>,
+}
+
+impl LatencyWindow {
+    pub fn record(&mut self, latency_us: u64) {
+        if self.samples_us.len() >= LATENCY_SAMPLES {
+            self.samples_us.pop_front();
+        }
+        self.samples_us.push_back(latency_us);
+    }
+
+    pub fn stats(&self) -> Option<LatencyStats> {
+        let mut sorted: Vec<u64> = self.samples_us.iter().copied().collect();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_unstable();
+        let percentile = |p: f64| {
+            let rank = ((sorted.len() - 1) as f64 * p).round() as usize;
+            sorted[rank] as f64 / 1000.0
+        };
+        Some(LatencyStats {
+            samples: sorted.len(),
+            p50_ms: percentile(0.50),
+            p95_ms: percentile(0.95),
+            p99_ms: percentile(0.99),
+            max_ms: percentile(1.0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_rtt_sample_seeds_the_estimates() {
+        let mut link = LinkMonitor::new(Duration::from_secs(5));
+        assert!(link.stats().rtt_ms.is_none());
+        assert!(link.stats().jitter_ms.is_none());
+
+        link.record_rtt(100_000);
+        let stats = link.stats();
+        assert_eq!(stats.rtt_ms, Some(100.0));
+        assert_eq!(stats.jitter_ms, Some(50.0));
+        assert_eq!(stats.last_rtt_ms, Some(100.0));
+    }
+
+    #[test]
+    fn smooths_rtt_and_variance_as_rfc_6298_does() {
+        let mut link = LinkMonitor::new(Duration::from_secs(5));
+        link.record_rtt(100_000);
+
+        // RTTVAR takes a quarter of the deviation from the old SRTT, SRTT an eighth of the sample
+        link.record_rtt(120_000);
+        let stats = link.stats();
+        assert_eq!(stats.rtt_ms, Some(102.5));
+        assert_eq!(stats.jitter_ms, Some(42.5));
+        assert_eq!(stats.last_rtt_ms, Some(120.0));
+
+        link.record_rtt(80_000);
+        let stats = link.stats();
+        assert_eq!(stats.rtt_ms, Some(99.6875));
+        assert_eq!(stats.jitter_ms, Some(37.5));
+        assert_eq!(stats.last_rtt_ms, Some(80.0));
+    }
+
+    #[test]
+    fn steady_rtt_settles_the_jitter() {
+        let mut link = LinkMonitor::new(Duration::from_secs(5));
+        for _ in 0..100 {
+            link.record_rtt(20_000);
+        }
+        let stats = link.stats();
+        assert_eq!(stats.rtt_ms, Some(20.0));
+        assert!(stats.jitter_ms.unwrap() < 0.001);
+    }
+
+    #[test]
+    fn times_out_without_traffic() {
+        let mut link = LinkMonitor::new(Duration::from_millis(20));
+        assert!(!link.is_timed_out());
+        assert!(link.stats().alive);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(link.is_timed_out());
+        assert!(!link.stats().alive);
+
+        link.record_activity();
+        assert!(!link.is_timed_out());
+        assert!(link.stats().alive);
+    }
+
+    #[test]
+    fn closed_links_are_not_alive() {
+        let mut link = LinkMonitor::new(Duration::from_secs(5));
+        link.mark_closed();
+        assert!(!link.is_timed_out());
+        assert!(!link.stats().alive);
+    }
+
+    // Their clock runs 4 s ahead of ours; the heartbeat takes 400 us out, is held for 100 us
+    // and takes 500 us back
+    #[test]
+    fn estimates_the_clock_offset_from_an_echo() {
+        let mut link = LinkMonitor::new(Duration::from_secs(5));
+        link.record_echo_at(1_000_000, 5_000_400, 5_000_500, 1_001_000);
+
+        // Off by half the difference between the two legs, as NTP is
+        assert_eq!(link.clock_offset_us(), Some(3_999_950));
+        assert_eq!(link.stats().clock_offset_ms, Some(3999.95));
+        assert_eq!(link.stats().last_rtt_ms, Some(1.0));
+    }
+
+    #[test]
+    fn trusts_the_least_delayed_recent_sample() {
+        let mut link = LinkMonitor::new(Duration::from_secs(5));
+        // 200 us on the wire against 5 ms for the others, which queued on the way back
+        link.record_echo_at(1_000_000, 5_000_100, 5_000_100, 1_000_200);
+        for sent in 1..CLOCK_SAMPLES as u64 {
+            let t0 = 1_000_000 + sent * 100_000;
+            link.record_echo_at(t0, t0 + 4_000_100, t0 + 4_000_100, t0 + 5000);
+        }
+        assert_eq!(link.clock_offset_us(), Some(4_000_000));
+
+        // Until it is pushed out of the window
+        let t0 = 2_000_000;
+        link.record_echo_at(t0, t0 + 4_000_100, t0 + 4_000_100, t0 + 5000);
+        assert_eq!(link.clock_offset_us(), Some(3_997_600));
+    }
+
+    #[test]
+    fn old_peers_give_an_rtt_but_no_offset() {
+        let mut link = LinkMonitor::new(Duration::from_secs(5));
+        link.record_echo_at(1_000_000, 0, 0, 1_002_000);
+        assert_eq!(link.stats().last_rtt_ms, Some(2.0));
+        assert_eq!(link.clock_offset_us(), None);
+    }
+
+    #[test]
+    fn ignores_echoes_of_heartbeats_we_never_sent() {
+        let mut link = LinkMonitor::new(Duration::from_secs(5));
+        link.record_echo_at(2_000_000, 5_000_000, 5_000_100, 1_000_000);
+        assert!(link.stats().rtt_ms.is_none());
+        assert_eq!(link.clock_offset_us(), None);
+
+        // Held for less than no time
+        link.record_echo_at(1_000_000, 5_000_100, 5_000_000, 1_001_000);
+        assert_eq!(link.clock_offset_us(), None);
+    }
+
+    #[test]
+    fn latency_percentiles_pick_the_nearest_rank() {
+        let mut window = LatencyWindow::default();
+        assert!(window.stats().is_none());
+
+        // 1 to 100 ms, recorded out of order
+        for ms in (1..=100).rev() {
+            window.record(ms * 1000);
+        }
+        let stats = window.stats().unwrap();
+        assert_eq!(stats.samples, 100);
+        assert_eq!(stats.p50_ms, 51.0);
+        assert_eq!(stats.p95_ms, 95.0);
+        assert_eq!(stats.p99_ms, 99.0);
+        assert_eq!(stats.max_ms, 100.0);
+    }
+
+    #[test]
+    fn latency_window_keeps_the_latest_samples() {
+        let mut window = LatencyWindow::default();
+        window.record(60_000_000);
+        for _ in 0..LATENCY_SAMPLES {
+            window.record(3000);
+        }
+        let stats = window.stats().unwrap();
+        assert_eq!(stats.samples, LATENCY_SAMPLES);
+        assert_eq!(stats.max_ms, 3.0);
+    }
+
+    #[test]
+    fn heartbeats_fit_several_times_into_the_timeout() {
+        assert_eq!(interval_for_timeout(2000), Duration::from_millis(500));
+        assert_eq!(interval_for_timeout(100), Duration::from_millis(250));
+        assert_eq!(interval_for_timeout(60_000), Duration::from_millis(1000));
+    }
+}
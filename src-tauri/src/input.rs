@@ -1,3 +1,5 @@
+use crate::heartbeat;
+use crate::layout::Rect;
 use anyhow::Result;
 use device_query::{DeviceQuery, DeviceState, MouseState};
 use enigo::{Enigo, MouseButton, MouseControllable};
@@ -5,6 +7,14 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Positioning {
+    #[default]
+    Absolute,
+    // x/y are deltas to apply to wherever the receiver's cursor currently is
+    Relative,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MouseEvent {
     pub x: i32,
@@ -13,6 +23,35 @@ pub struct MouseEvent {
     pub pressed: bool,
     pub wheel_x: i32,
     pub wheel_y: i32,
+    #[serde(default)]
+    pub positioning: Positioning,
+    // When the sender captured it, on the sender's heartbeat::monotonic_us clock; zero if unknown
+    #[serde(default)]
+    pub captured_at_us: u64,
+}
+
+impl MouseEvent {
+    pub fn relative(dx: i32, dy: i32) -> Self {
+        Self {
+            x: dx,
+            y: dy,
+            button: None,
+            pressed: false,
+            wheel_x: 0,
+            wheel_y: 0,
+            positioning: Positioning::Relative,
+            captured_at_us: 0,
+        }
+    }
+
+    // Pure pointer movement; these may be dropped or reordered without leaving stuck state behind
+    pub fn is_motion(&self) -> bool {
+        self.button.is_none() && self.wheel_x == 0 && self.wheel_y == 0
+    }
+
+    pub fn is_relative(&self) -> bool {
+        self.positioning == Positioning::Relative
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -93,8 +132,13 @@ impl GestureTracker {
     }
 }
 
+// DeviceState holds a raw X11 display handle on Linux and is neither Send nor Sync, so each
+// worker thread opens its own connection lazily instead of sharing one across the runtime.
+thread_local! {
+    static DEVICE_STATE: DeviceState = DeviceState::new();
+}
+
 pub struct InputManager {
-    device_state: Arc<DeviceState>,
     last_mouse_state: Arc<Mutex<MouseState>>,
     config: Arc<Mutex<InputConfig>>,
     gesture_tracker: Arc<Mutex<GestureTracker>>,
@@ -103,7 +147,6 @@ pub struct InputManager {
 impl InputManager {
     pub fn new() -> Self {
         Self {
-            device_state: Arc::new(DeviceState::new()),
             last_mouse_state: Arc::new(Mutex::new(MouseState::default())),
             config: Arc::new(Mutex::new(InputConfig {
                 cursor_speed: 1.0,
@@ -119,7 +162,8 @@ impl InputManager {
     }
 
     pub async fn capture_mouse_events(&self) -> Result<Vec<MouseEvent>> {
-        let current_mouse = self.device_state.get_mouse();
+        let current_mouse = DEVICE_STATE.with(|state| state.get_mouse());
+        let captured_at_us = heartbeat::monotonic_us();
         let mut last_mouse = self.last_mouse_state.lock().await;
         
         let mut events = Vec::new();
@@ -133,6 +177,8 @@ impl InputManager {
                 pressed: false,
                 wheel_x: 0,
                 wheel_y: 0,
+                positioning: Positioning::Absolute,
+                captured_at_us,
             });
         }
         
@@ -156,6 +202,8 @@ impl InputManager {
                         pressed,
                         wheel_x: 0,
                         wheel_y: 0,
+                        positioning: Positioning::Absolute,
+                        captured_at_us,
                     });
                 }
             }
@@ -169,11 +217,29 @@ impl InputManager {
     }
 
     pub async fn emulate_mouse_event(&self, event: MouseEvent) -> Result<()> {
+        let bounds = if event.is_relative() {
+            Rect::bounding(&self.get_screen_bounds().await?)
+        } else {
+            None
+        };
+
         // Create a new Enigo instance for this operation (not shared between threads)
         let mut enigo = Enigo::new();
         
-        // Move mouse
-        enigo.mouse_move_to(event.x, event.y);
+        // Move mouse; deltas are applied to our own cursor and kept on our own screens
+        if event.is_relative() {
+            if event.x != 0 || event.y != 0 {
+                let (x, y) = DEVICE_STATE.with(|state| state.get_mouse()).coords;
+                let target = (x + event.x, y + event.y);
+                let target = match bounds {
+                    Some(bounds) => bounds.clamp(target),
+                    None => target,
+                };
+                enigo.mouse_move_to(target.0, target.1);
+            }
+        } else {
+            enigo.mouse_move_to(event.x, event.y);
+        }
         
         // Handle button events
         if let Some(button_str) = &event.button {
@@ -202,7 +268,7 @@ impl InputManager {
     }
 
     pub async fn get_mouse_position(&self) -> Result<(i32, i32)> {
-        let mouse = self.device_state.get_mouse();
+        let mouse = DEVICE_STATE.with(|state| state.get_mouse());
         Ok(mouse.coords)
     }
 
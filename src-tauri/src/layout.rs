@@ -0,0 +1,613 @@
+use crate::input::{MouseEvent, ScreenBounds};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+// While a client owns the cursor it is sent deltas, with an absolute position this often so
+// lost datagrams or clamping differences can't make the two cursors drift apart for long
+const RESYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScreenPlacement {
+    LeftOf,
+    RightOf,
+    Above,
+    Below,
+    // Top-left corner in the server's desktop coordinates
+    Custom { x: i32, y: i32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn right(&self) -> i32 {
+        self.x + self.width as i32
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height as i32
+    }
+
+    pub fn contains(&self, (x, y): (i32, i32)) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    pub fn center(&self) -> (i32, i32) {
+        (
+            self.x + self.width as i32 / 2,
+            self.y + self.height as i32 / 2,
+        )
+    }
+
+    pub fn clamp(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        (
+            x.clamp(self.x, self.right() - 1),
+            y.clamp(self.y, self.bottom() - 1),
+        )
+    }
+
+    // Smallest rectangle covering every monitor
+    pub fn bounding(screens: &[ScreenBounds]) -> Option<Rect> {
+        let left = screens.iter().map(|screen| screen.x).min()?;
+        let top = screens.iter().map(|screen| screen.y).min()?;
+        let right = screens
+            .iter()
+            .map(|screen| screen.x + screen.width as i32)
+            .max()?;
+        let bottom = screens
+            .iter()
+            .map(|screen| screen.y + screen.height as i32)
+            .max()?;
+        Some(Rect {
+            x: left,
+            y: top,
+            width: (right - left).max(1) as u32,
+            height: (bottom - top).max(1) as u32,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientScreen {
+    pub fingerprint: String,
+    pub device_name: String,
+    pub placement: ScreenPlacement,
+    // The client's own desktop, in its coordinates
+    pub screen: Rect,
+    // Where that desktop sits in the server's coordinates
+    pub area: Rect,
+}
+
+impl ClientScreen {
+    fn to_client(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        (
+            x - self.area.x + self.screen.x,
+            y - self.area.y + self.screen.y,
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RouteOutcome {
+    // Events to deliver, keyed by client fingerprint, already in that client's coordinates
+    pub sends: Vec<(String, MouseEvent)>,
+    // Where to move the local cursor afterwards
+    pub warp: Option<(i32, i32)>,
+}
+
+// The server's screen plus every client screen around it. While a client owns the cursor the
+// local pointer is parked in the middle of the server screen and only its movement is used.
+#[derive(Debug)]
+pub struct DesktopLayout {
+    local: Rect,
+    clients: Vec<ClientScreen>,
+    active: Option<String>,
+    cursor: (i32, i32),
+    pressed: HashSet<String>,
+    pending_warp: Option<(i32, i32)>,
+    last_absolute: Option<Instant>,
+}
+
+impl DesktopLayout {
+    pub fn new(local: Rect) -> Self {
+        Self {
+            local,
+            clients: Vec::new(),
+            active: None,
+            cursor: local.center(),
+            pressed: HashSet::new(),
+            pending_warp: None,
+            last_absolute: None,
+        }
+    }
+
+    pub fn set_local(&mut self, local: Rect) {
+        self.local = local;
+        self.relayout();
+    }
+
+    pub fn local(&self) -> Rect {
+        self.local
+    }
+
+    pub fn clients(&self) -> Vec<ClientScreen> {
+        self.clients.clone()
+    }
+
+    pub fn active(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    pub fn cursor(&self) -> (i32, i32) {
+        self.cursor
+    }
+
+    // Without an explicit placement clients fill right, left, above, below, then keep going
+    // round in that order
+    pub fn add_client(
+        &mut self,
+        fingerprint: &str,
+        device_name: &str,
+        screen: Rect,
+        placement: Option<ScreenPlacement>,
+    ) -> ScreenPlacement {
+        const DEFAULT_ORDER: [ScreenPlacement; 4] = [
+            ScreenPlacement::RightOf,
+            ScreenPlacement::LeftOf,
+            ScreenPlacement::Above,
+            ScreenPlacement::Below,
+        ];
+
+        self.remove_client(fingerprint);
+        let placement =
+            placement.unwrap_or(DEFAULT_ORDER[self.clients.len() % DEFAULT_ORDER.len()]);
+        self.clients.push(ClientScreen {
+            fingerprint: fingerprint.to_string(),
+            device_name: device_name.to_string(),
+            placement,
+            screen,
+            area: screen,
+        });
+        self.relayout();
+        placement
+    }
+
+    pub fn remove_client(&mut self, fingerprint: &str) {
+        self.clients
+            .retain(|client| client.fingerprint != fingerprint);
+        if self.active.as_deref() == Some(fingerprint) {
+            self.return_to_local(self.local.center());
+        }
+        self.relayout();
+    }
+
+    pub fn set_placement(&mut self, fingerprint: &str, placement: ScreenPlacement) -> bool {
+        let Some(client) = self
+            .clients
+            .iter_mut()
+            .find(|client| client.fingerprint == fingerprint)
+        else {
+            return false;
+        };
+        client.placement = placement;
+        self.relayout();
+
+        // The cursor may now be outside the moved screen
+        if let Some(client) = self.active_client() {
+            self.cursor = client.area.clamp(self.cursor);
+        }
+        true
+    }
+
+    pub fn take_warp(&mut self) -> Option<(i32, i32)> {
+        self.pending_warp.take()
+    }
+
+    // `event` carries the local cursor position as captured on the server
+    pub fn route(&mut self, mut event: MouseEvent) -> RouteOutcome {
+        let mut outcome = RouteOutcome {
+            warp: self.pending_warp.take(),
+            ..Default::default()
+        };
+
+        if !event.is_motion() {
+            if let Some(button) = &event.button {
+                if event.pressed {
+                    self.pressed.insert(button.clone());
+                } else {
+                    self.pressed.remove(button);
+                }
+            }
+            if let Some(client) = self.active_client() {
+                (event.x, event.y) = client.to_client(self.cursor);
+                outcome.sends.push((client.fingerprint.clone(), event));
+                self.last_absolute = Some(Instant::now());
+            }
+            return outcome;
+        }
+
+        let position = (event.x, event.y);
+        match self.active.clone() {
+            None => {
+                self.cursor = position;
+                if !self.pressed.is_empty() {
+                    return outcome;
+                }
+                let Some(entry) = self.edge_probe(position) else {
+                    return outcome;
+                };
+                if let Some(index) = self.client_at(entry) {
+                    let client = &self.clients[index];
+                    log::debug!("Cursor entered {}", client.device_name);
+                    self.active = Some(client.fingerprint.clone());
+                    self.cursor = entry;
+                    (event.x, event.y) = client.to_client(entry);
+                    outcome.sends.push((client.fingerprint.clone(), event));
+                    outcome.warp = Some(self.local.center());
+                    self.last_absolute = Some(Instant::now());
+                }
+            }
+            Some(active) => {
+                let anchor = self.local.center();
+                let delta = (position.0 - anchor.0, position.1 - anchor.1);
+                if delta == (0, 0) {
+                    return outcome;
+                }
+                outcome.warp = Some(anchor);
+
+                let Some(current) = self
+                    .clients
+                    .iter()
+                    .position(|client| client.fingerprint == active)
+                else {
+                    self.return_to_local(anchor);
+                    outcome.warp = self.pending_warp.take();
+                    return outcome;
+                };
+                let target = (self.cursor.0 + delta.0, self.cursor.1 + delta.1);
+
+                // Dragging never crosses screens, so a held button can't get stuck elsewhere
+                let next =
+                    if !self.pressed.is_empty() || self.clients[current].area.contains(target) {
+                        Some(current)
+                    } else if self.local.contains(target) {
+                        // Land a pixel inside so the edge doesn't immediately send it back out
+                        let target = (
+                            target.0.clamp(self.local.x + 1, self.local.right() - 2),
+                            target.1.clamp(self.local.y + 1, self.local.bottom() - 2),
+                        );
+                        log::debug!("Cursor returned to the server screen");
+                        self.active = None;
+                        self.cursor = target;
+                        outcome.warp = Some(target);
+                        return outcome;
+                    } else {
+                        self.client_at(target)
+                    };
+
+                let index = next.unwrap_or(current);
+                let client = &self.clients[index];
+                let cursor = client.area.clamp(target);
+                if index == current && cursor == self.cursor {
+                    return outcome;
+                }
+                if index != current {
+                    log::debug!("Cursor moved to {}", client.device_name);
+                    self.active = Some(client.fingerprint.clone());
+                }
+
+                // Entering a screen always carries the absolute position
+                let resync = index != current
+                    || self
+                        .last_absolute
+                        .is_none_or(|sent| sent.elapsed() >= RESYNC_INTERVAL);
+                let event = if resync {
+                    self.last_absolute = Some(Instant::now());
+                    (event.x, event.y) = client.to_client(cursor);
+                    event
+                } else {
+                    MouseEvent {
+                        captured_at_us: event.captured_at_us,
+                        ..MouseEvent::relative(cursor.0 - self.cursor.0, cursor.1 - self.cursor.1)
+                    }
+                };
+                self.cursor = cursor;
+                outcome.sends.push((client.fingerprint.clone(), event));
+            }
+        }
+
+        outcome
+    }
+
+    fn active_client(&self) -> Option<&ClientScreen> {
+        let active = self.active.as_deref()?;
+        self.clients
+            .iter()
+            .find(|client| client.fingerprint == active)
+    }
+
+    fn client_at(&self, point: (i32, i32)) -> Option<usize> {
+        self.clients
+            .iter()
+            .position(|client| client.area.contains(point))
+    }
+
+    fn return_to_local(&mut self, position: (i32, i32)) {
+        self.active = None;
+        self.pressed.clear();
+        self.cursor = position;
+        self.pending_warp = Some(position);
+    }
+
+    // The local cursor can't leave the screen, so pinning it to an edge stands for pushing
+    // one pixel past it
+    fn edge_probe(&self, (x, y): (i32, i32)) -> Option<(i32, i32)> {
+        if x <= self.local.x {
+            Some((self.local.x - 1, y))
+        } else if x >= self.local.right() - 1 {
+            Some((self.local.right(), y))
+        } else if y <= self.local.y {
+            Some((x, self.local.y - 1))
+        } else if y >= self.local.bottom() - 1 {
+            Some((x, self.local.bottom()))
+        } else {
+            None
+        }
+    }
+
+    // Screens on the same side are chained outwards in connection order, lined up with the
+    // server's top or left edge
+    fn relayout(&mut self) {
+        let mut left = self.local.x;
+        let mut right = self.local.right();
+        let mut top = self.local.y;
+        let mut bottom = self.local.bottom();
+
+        for client in &mut self.clients {
+            let width = client.screen.width;
+            let height = client.screen.height;
+            let (x, y) = match client.placement {
+                ScreenPlacement::RightOf => {
+                    let origin = (right, self.local.y);
+                    right += width as i32;
+                    origin
+                }
+                ScreenPlacement::LeftOf => {
+                    left -= width as i32;
+                    (left, self.local.y)
+                }
+                ScreenPlacement::Above => {
+                    top -= height as i32;
+                    (self.local.x, top)
+                }
+                ScreenPlacement::Below => {
+                    let origin = (self.local.x, bottom);
+                    bottom += height as i32;
+                    origin
+                }
+                ScreenPlacement::Custom { x, y } => (x, y),
+            };
+            client.area = Rect {
+                x,
+                y,
+                width,
+                height,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Positioning;
+
+    const LOCAL: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 1920,
+        height: 1080,
+    };
+    const LAPTOP: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 1280,
+        height: 720,
+    };
+
+    fn motion(x: i32, y: i32) -> MouseEvent {
+        MouseEvent {
+            positioning: Positioning::Absolute,
+            ..MouseEvent::relative(x, y)
+        }
+    }
+
+    fn button(name: &str, pressed: bool) -> MouseEvent {
+        MouseEvent {
+            button: Some(name.to_string()),
+            pressed,
+            ..motion(0, 0)
+        }
+    }
+
+    fn layout_with_laptop() -> DesktopLayout {
+        let mut layout = DesktopLayout::new(LOCAL);
+        layout.add_client("laptop", "Laptop", LAPTOP, Some(ScreenPlacement::RightOf));
+        layout
+    }
+
+    // Pushes the cursor against the server's right edge
+    fn enter_laptop(layout: &mut DesktopLayout) -> RouteOutcome {
+        layout.route(motion(LOCAL.right() - 1, 500))
+    }
+
+    #[test]
+    fn places_clients_round_the_server_screen_in_order() {
+        let mut layout = DesktopLayout::new(LOCAL);
+        let placements: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| layout.add_client(name, name, LAPTOP, None))
+            .collect();
+        assert_eq!(
+            placements,
+            [
+                ScreenPlacement::RightOf,
+                ScreenPlacement::LeftOf,
+                ScreenPlacement::Above,
+                ScreenPlacement::Below
+            ]
+        );
+
+        let areas: Vec<_> = layout.clients().iter().map(|client| client.area).collect();
+        assert_eq!((areas[0].x, areas[0].y), (1920, 0));
+        assert_eq!((areas[1].x, areas[1].y), (-1280, 0));
+        assert_eq!((areas[2].x, areas[2].y), (0, -720));
+        assert_eq!((areas[3].x, areas[3].y), (0, 1080));
+    }
+
+    #[test]
+    fn chains_screens_on_the_same_side() {
+        let mut layout = layout_with_laptop();
+        layout.add_client("tablet", "Tablet", LAPTOP, Some(ScreenPlacement::RightOf));
+        let tablet = &layout.clients()[1];
+        assert_eq!(tablet.area.x, LOCAL.right() + LAPTOP.width as i32);
+    }
+
+    #[test]
+    fn crossing_an_edge_hands_the_cursor_to_the_client() {
+        let mut layout = layout_with_laptop();
+        let outcome = enter_laptop(&mut layout);
+
+        assert_eq!(layout.active(), Some("laptop"));
+        assert_eq!(outcome.warp, Some(LOCAL.center()));
+        let (fingerprint, event) = &outcome.sends[0];
+        assert_eq!(fingerprint, "laptop");
+        assert_eq!((event.x, event.y), (0, 500));
+        assert!(!event.is_relative());
+    }
+
+    #[test]
+    fn edges_without_a_client_keep_the_cursor() {
+        let mut layout = layout_with_laptop();
+        let outcome = layout.route(motion(0, 500));
+        assert!(outcome.sends.is_empty());
+        assert_eq!(layout.active(), None);
+        assert_eq!(layout.cursor(), (0, 500));
+    }
+
+    #[test]
+    fn movement_on_a_client_is_sent_as_deltas() {
+        let mut layout = layout_with_laptop();
+        enter_laptop(&mut layout);
+
+        let (x, y) = LOCAL.center();
+        let outcome = layout.route(motion(x + 10, y - 5));
+        assert_eq!(outcome.warp, Some(LOCAL.center()));
+        let (_, event) = &outcome.sends[0];
+        assert!(event.is_relative());
+        assert_eq!((event.x, event.y), (10, -5));
+        assert_eq!(layout.cursor(), (LOCAL.right() + 10, 495));
+    }
+
+    #[test]
+    fn deltas_add_up_on_the_client() {
+        let mut layout = layout_with_laptop();
+        enter_laptop(&mut layout);
+
+        let (x, y) = LOCAL.center();
+        let mut moved = (0, 0);
+        for (dx, dy) in [(10, 0), (5, 7), (-3, 2)] {
+            let outcome = layout.route(motion(x + dx, y + dy));
+            let (_, event) = &outcome.sends[0];
+            assert!(event.is_relative());
+            moved = (moved.0 + event.x, moved.1 + event.y);
+        }
+        assert_eq!(moved, (12, 9));
+        assert_eq!(layout.cursor(), (LOCAL.right() + 12, 509));
+    }
+
+    #[test]
+    fn deltas_stop_at_the_far_edges_of_the_client() {
+        let mut layout = layout_with_laptop();
+        enter_laptop(&mut layout);
+
+        // Past the laptop's bottom right corner, with no screen beyond it
+        let (x, y) = LOCAL.center();
+        let outcome = layout.route(motion(x + 5000, y + 5000));
+        let (_, event) = &outcome.sends[0];
+        assert_eq!((event.x, event.y), (1279, 219));
+        assert_eq!(layout.cursor(), (LOCAL.right() + 1279, 719));
+
+        // Pushing on into the corner sends nothing, but still recentres the local pointer
+        let outcome = layout.route(motion(x + 10, y + 10));
+        assert!(outcome.sends.is_empty());
+        assert_eq!(outcome.warp, Some(LOCAL.center()));
+        assert_eq!(layout.cursor(), (LOCAL.right() + 1279, 719));
+    }
+
+    #[test]
+    fn resends_the_absolute_position_every_interval() {
+        let mut layout = layout_with_laptop();
+        enter_laptop(&mut layout);
+        let (x, y) = LOCAL.center();
+        assert!(layout.route(motion(x + 10, y)).sends[0].1.is_relative());
+
+        // As if the last absolute position went out a whole interval ago
+        layout.last_absolute = Some(Instant::now() - RESYNC_INTERVAL);
+        let outcome = layout.route(motion(x + 10, y));
+        let (_, event) = &outcome.sends[0];
+        assert!(!event.is_relative());
+        assert_eq!((event.x, event.y), (20, 500));
+
+        assert!(layout.route(motion(x + 10, y)).sends[0].1.is_relative());
+    }
+
+    #[test]
+    fn moving_back_over_the_edge_returns_to_the_server() {
+        let mut layout = layout_with_laptop();
+        enter_laptop(&mut layout);
+
+        let (x, y) = LOCAL.center();
+        let outcome = layout.route(motion(x - 20, y));
+        assert!(outcome.sends.is_empty());
+        assert_eq!(layout.active(), None);
+        assert_eq!(outcome.warp, Some((LOCAL.right() - 20, 500)));
+    }
+
+    #[test]
+    fn a_held_button_keeps_the_cursor_on_its_screen() {
+        let mut layout = layout_with_laptop();
+        layout.route(button("left", true));
+        assert!(enter_laptop(&mut layout).sends.is_empty());
+        assert_eq!(layout.active(), None);
+
+        layout.route(button("left", false));
+        enter_laptop(&mut layout);
+        assert_eq!(layout.active(), Some("laptop"));
+
+        // Dragging on the client can't carry it back to the server either
+        let outcome = layout.route(button("left", true));
+        assert_eq!(outcome.sends.len(), 1);
+        let (x, y) = LOCAL.center();
+        let outcome = layout.route(motion(x - 100, y));
+        assert!(outcome.sends.is_empty());
+        assert_eq!(layout.active(), Some("laptop"));
+    }
+
+    #[test]
+    fn removing_the_active_client_recentres_the_cursor() {
+        let mut layout = layout_with_laptop();
+        enter_laptop(&mut layout);
+
+        layout.remove_client("laptop");
+        assert_eq!(layout.active(), None);
+        assert_eq!(layout.take_warp(), Some(LOCAL.center()));
+        assert!(layout.clients().is_empty());
+    }
+}
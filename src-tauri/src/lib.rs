@@ -1,7 +1,21 @@
 pub mod bridge;
+pub mod codec;
 pub mod config;
+pub mod discovery;
 pub mod input;
+pub mod heartbeat;
+pub mod layout;
 pub mod network;
+pub mod protocol;
+pub mod queue;
+pub mod ratelimit;
+pub mod reconnect;
+pub mod relay;
+pub mod resume;
+pub mod signaling;
+pub mod state;
+pub mod traffic;
+pub mod transport;
 pub mod platform;
 pub mod service;
 pub mod clipboard;
@@ -21,14 +35,24 @@ pub struct ServerInfo {
     pub ip: String,
     pub port: u16,
     pub fingerprint: String,
+    // Hex SHA-256 of the certificate a QUIC server presents, for clients to pin
+    #[serde(default)]
+    pub quic_cert_sha256: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ConnectionStatus {
     pub connected: bool,
     pub mode: String,
+    pub state: state::ConnectionState,
     pub remote_address: Option<String>,
     pub latency_ms: Option<u64>,
+    pub jitter_ms: Option<f64>,
+    pub last_seen_ms: Option<u64>,
+    pub link_state: Option<network::LinkState>,
+    pub clock_offset_ms: Option<f64>,
+    // Client only: capture on the server to injection on this machine
+    pub input_latency: Option<heartbeat::LatencyStats>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -5,7 +5,9 @@
 
 use mousebridge_lib::{
     bridge::MouseBridgeService,
-    config::{Config, ConnectionConfig},
+    config::{Config, ConnectionConfig, Protocol},
+    state::ConnectionState,
+    traffic::ConnectionTraffic,
     ClipboardData, HotkeyConfig, AnalyticsData, ServerInfo, ConnectionStatus, PlatformInfo,
 };
 use tauri::Manager;
@@ -20,6 +22,28 @@ fn main() {
             
             // Initialize the mouse bridge service
             let bridge_service = Arc::new(MouseBridgeService::new());
+
+            // Restores which plugins were enabled, auto-reconnect included
+            tauri::async_runtime::spawn(async {
+                if let Err(e) = mousebridge_lib::plugins::load_plugins().await {
+                    log::warn!("Failed to load plugins: {}", e);
+                }
+            });
+
+            // Push every connection state change to the frontend as it happens
+            let mut transitions = bridge_service.subscribe_state();
+            let emitter = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    match transitions.recv().await {
+                        Ok(transition) => {
+                            let _ = emitter.emit_all("connection-state", transition);
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            });
             
             // Store the service in the app state
             app_handle.manage(bridge_service);
@@ -32,7 +56,10 @@ fn main() {
             connect_client,
             disconnect_client,
             get_connection_status,
+            get_connection_state,
             get_server_info,
+            get_connected_clients,
+            set_client_placement,
             save_config,
             load_config,
             get_platform_info,
@@ -45,6 +72,7 @@ fn main() {
             get_registered_hotkeys,
             get_analytics_data,
             reset_analytics,
+            get_connection_stats,
             list_plugins,
             enable_plugin,
             disable_plugin,
@@ -54,6 +82,12 @@ fn main() {
             enable_mouse_acceleration,
             test_network_connectivity,
             get_network_interfaces,
+            discover_servers,
+            start_relay,
+            stop_relay,
+            get_relay_stats,
+            start_signaling_server,
+            stop_signaling_server,
             get_system_resources,
             check_permissions,
             request_permissions
@@ -111,6 +145,35 @@ async fn get_connection_status(
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn get_connection_state(
+    service: tauri::State<'_, Arc<MouseBridgeService>>,
+) -> Result<ConnectionState, String> {
+    Ok(service.connection_state())
+}
+
+#[tauri::command]
+async fn get_connected_clients(
+    service: tauri::State<'_, Arc<MouseBridgeService>>,
+) -> Result<Vec<mousebridge_lib::layout::ClientScreen>, String> {
+    service
+        .get_connected_clients()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_client_placement(
+    service: tauri::State<'_, Arc<MouseBridgeService>>,
+    fingerprint: String,
+    placement: mousebridge_lib::layout::ScreenPlacement,
+) -> Result<(), String> {
+    service
+        .set_client_placement(fingerprint, placement)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn get_server_info(
     service: tauri::State<'_, Arc<MouseBridgeService>>,
@@ -204,6 +267,11 @@ async fn reset_analytics() -> Result<(), String> {
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn get_connection_stats() -> Result<Vec<ConnectionTraffic>, String> {
+    Ok(mousebridge_lib::traffic::connection_stats())
+}
+
 // Plugin system
 #[tauri::command]
 async fn list_plugins() -> Result<Vec<String>, String> {
@@ -257,19 +325,118 @@ async fn enable_mouse_acceleration(enable: bool) -> Result<(), String> {
 
 // Network diagnostics
 #[tauri::command]
-async fn test_network_connectivity(host: String, port: u16) -> Result<u64, String> {
-    mousebridge_lib::network::test_connectivity(host, port)
+async fn test_network_connectivity(
+    host: String,
+    port: u16,
+    protocol: Option<Protocol>,
+    timeout_ms: Option<u64>,
+    handshake: Option<bool>,
+) -> Result<mousebridge_lib::network::ConnectivityReport, String> {
+    // Relay and signaling settings come from the saved config, like they would for a client
+    let mut config = Config::load()
+        .await
+        .map(|config| config.connection)
+        .unwrap_or_default();
+    config.host = host;
+    config.port = port;
+    if let Some(protocol) = protocol {
+        config.protocol = protocol;
+    }
+    if let Some(timeout_ms) = timeout_ms {
+        config.timeout_ms = timeout_ms;
+    }
+
+    mousebridge_lib::network::test_connectivity(&config, handshake.unwrap_or(true))
         .await
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn get_network_interfaces() -> Result<Vec<String>, String> {
+async fn get_network_interfaces() -> Result<Vec<mousebridge_lib::network::NetworkInterface>, String>
+{
     mousebridge_lib::network::get_available_interfaces()
         .await
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn discover_servers(
+    port: Option<u16>,
+    timeout_ms: Option<u64>,
+) -> Result<Vec<mousebridge_lib::discovery::DiscoveredServer>, String> {
+    // Listen where servers announce themselves, which is the saved config's discovery port
+    // unless the caller says otherwise
+    let port = match port {
+        Some(port) => port,
+        None => Config::load()
+            .await
+            .map(|config| config.connection.discovery.port)
+            .unwrap_or_else(|_| mousebridge_lib::config::DiscoveryConfig::default().port),
+    };
+    mousebridge_lib::discovery::discover_servers(
+        port,
+        std::time::Duration::from_millis(timeout_ms.unwrap_or(2000)),
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn start_relay(
+    service: tauri::State<'_, Arc<MouseBridgeService>>,
+    config: ConnectionConfig,
+) -> Result<String, String> {
+    service
+        .start_relay(config)
+        .await
+        .map(|address| address.to_string())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_relay(service: tauri::State<'_, Arc<MouseBridgeService>>) -> Result<(), String> {
+    service
+        .stop_relay()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_relay_stats(
+    service: tauri::State<'_, Arc<MouseBridgeService>>,
+) -> Result<mousebridge_lib::relay::RelayStats, String> {
+    service
+        .get_relay_stats()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn start_signaling_server(
+    service: tauri::State<'_, Arc<MouseBridgeService>>,
+    port: Option<u16>,
+    bind: Option<String>,
+) -> Result<String, String> {
+    service
+        .start_signaling_server(
+            port.unwrap_or(mousebridge_lib::signaling::DEFAULT_SIGNALING_PORT),
+            bind,
+        )
+        .await
+        .map(|address| address.to_string())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_signaling_server(
+    service: tauri::State<'_, Arc<MouseBridgeService>>,
+) -> Result<(), String> {
+    service
+        .stop_signaling_server()
+        .await
+        .map_err(|e| e.to_string())
+}
+
 // System utilities
 #[tauri::command]
 async fn get_system_resources() -> Result<serde_json::Value, String> {
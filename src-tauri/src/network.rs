@@ -1,109 +1,1624 @@
-use crate::{config::ConnectionConfig, input::{InputManager, MouseEvent}};
+use crate::{
+    codec::{self, WireFormat},
+    config::ConnectionConfig,
+    heartbeat::{self, LatencyStats, LatencyWindow, LinkMonitor, LinkStats},
+    input::{InputManager, MouseEvent, Positioning},
+    layout::{ClientScreen, DesktopLayout, Rect, ScreenPlacement},
+    protocol::{self, Capabilities, Negotiated},
+    queue::{OutboundQueue, QueueStats, OUTBOUND_QUEUE_CAPACITY},
+    ratelimit::{Banned, RateLimiter},
+    reconnect::Backoff,
+    resume::{self, InputTracker, ReplayBuffer},
+    state::{ConnectionState, StateMachine},
+    traffic::{self, ConnectionMeter, ConnectionSide},
+    transport::{self, Connection, MessageSize, Transport},
+    ClipboardData,
+};
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex, OwnedSemaphorePermit};
+use tokio::time::{Duration, MissedTickBehavior};
+use uuid::Uuid;
 
-// Simplified network implementation for now
-// TODO: Implement full WebRTC functionality
+// Frames are a big-endian u32 length followed by a NetworkMessage, JSON-encoded until the
+// handshake settles on the binary codec and compression.
+// Anything larger than this is treated as a corrupt or hostile stream. The limit applies to
+// what is on the wire, so a compressed payload may expand past it.
+pub const MAX_FRAME_LEN: usize = 1024 * 1024;
+// Largest message, before compression, that a connection accepts unless its listener was given
+// a tighter limit
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = codec::MAX_DECOMPRESSED_LEN;
+
+const CAPTURE_INTERVAL_MS: u64 = 16;
+
+// How long a failed session looks for a goodbye that was already on its way
+const GOODBYE_GRACE_MS: u64 = 50;
+
+// Motion datagrams are tiny; anything bigger than this is not ours
+pub const MAX_DATAGRAM_LEN: usize = 2048;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NetworkMessage {
     MouseEvent(MouseEvent),
-    ConnectionRequest { fingerprint: String },
-    ConnectionResponse { accepted: bool, fingerprint: String },
-    Heartbeat,
+    // Fields added after the first release default to zero/empty so that a request from an
+    // older peer still decodes and can be refused with a readable reason
+    ConnectionRequest {
+        fingerprint: String,
+        #[serde(default)]
+        device_name: String,
+        #[serde(default)]
+        min_version: u32,
+        #[serde(default)]
+        max_version: u32,
+        #[serde(default)]
+        capabilities: Capabilities,
+        // Set by test_connectivity: the server answers the handshake and then hangs up
+        #[serde(default)]
+        probe: bool,
+        // Bounding box of the client's monitors, used to place it in the server's layout
+        #[serde(default)]
+        screen: Option<Rect>,
+    },
+    ConnectionResponse {
+        accepted: bool,
+        fingerprint: String,
+        #[serde(default)]
+        device_name: String,
+        #[serde(default)]
+        version: u32,
+        #[serde(default)]
+        capabilities: Capabilities,
+        #[serde(default)]
+        reason: Option<String>,
+        // Lets the client pick the session back up after a brief disconnect
+        #[serde(default)]
+        resume_token: Option<String>,
+    },
+    // Sent instead of a ConnectionRequest by a client reconnecting within the grace window.
+    // `received` counts the replayable events it has applied.
+    ResumeRequest {
+        token: String,
+        received: u64,
+        // Must match the session's; transports that file connections by client, like UDP,
+        // need it before the session is found
+        #[serde(default)]
+        fingerprint: String,
+    },
+    // Carries a fresh token on success; each token resumes at most once
+    ResumeResponse {
+        accepted: bool,
+        #[serde(default)]
+        resume_token: Option<String>,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    // Client to server, every heartbeat: replayable events applied so far
+    InputAck {
+        received: u64,
+    },
+    // Client is leaving on purpose; the server drops its session instead of holding it
+    Goodbye,
+    Heartbeat {
+        timestamp_us: u64,
+    },
+    HeartbeatAck {
+        echo_timestamp_us: u64,
+        // When the heartbeat arrived and when this ack left, on the sender's own clock; these
+        // let the other side estimate the offset between the two clocks
+        #[serde(default)]
+        receive_us: u64,
+        #[serde(default)]
+        transmit_us: u64,
+    },
+    // Unreliable channel only: pointer motion tagged with a per-session sequence number
+    Motion {
+        seq: u64,
+        event: MouseEvent,
+    },
+    // Sent by the client over UDP to bind its datagram address to its TCP session
+    UdpRegister {
+        fingerprint: String,
+    },
+    // Bulk payload; transports that can carry it apart from input traffic do so
+    Clipboard(ClipboardData),
+}
+
+impl NetworkMessage {
+    // Variant name, used to break traffic down by message type
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NetworkMessage::MouseEvent(_) => "MouseEvent",
+            NetworkMessage::ConnectionRequest { .. } => "ConnectionRequest",
+            NetworkMessage::ConnectionResponse { .. } => "ConnectionResponse",
+            NetworkMessage::ResumeRequest { .. } => "ResumeRequest",
+            NetworkMessage::ResumeResponse { .. } => "ResumeResponse",
+            NetworkMessage::InputAck { .. } => "InputAck",
+            NetworkMessage::Goodbye => "Goodbye",
+            NetworkMessage::Heartbeat { .. } => "Heartbeat",
+            NetworkMessage::HeartbeatAck { .. } => "HeartbeatAck",
+            NetworkMessage::Motion { .. } => "Motion",
+            NetworkMessage::UdpRegister { .. } => "UdpRegister",
+            NetworkMessage::Clipboard(_) => "Clipboard",
+        }
+    }
+}
+
+pub fn encode_datagram(message: &NetworkMessage, format: WireFormat) -> Result<Vec<u8>> {
+    let payload = codec::encode(message, format)?;
+    if payload.len() > MAX_DATAGRAM_LEN {
+        return Err(anyhow::anyhow!(
+            "Datagram of {} bytes exceeds limit",
+            payload.len()
+        ));
+    }
+    Ok(payload)
+}
+
+pub fn decode_datagram(payload: &[u8]) -> Result<NetworkMessage> {
+    codec::decode(payload)
+}
+
+// Receiver side of the unreliable motion channel. Motion only ever needs the latest
+// position, so anything that arrives after a newer sequence number is discarded.
+#[derive(Debug, Default)]
+pub struct MotionSequencer {
+    last_seq: Option<u64>,
+    dropped: u64,
+    lost: u64,
+}
+
+impl MotionSequencer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn accept(&mut self, seq: u64) -> bool {
+        match self.last_seq {
+            Some(last) if seq <= last => {
+                self.dropped += 1;
+                false
+            }
+            _ => {
+                if let Some(last) = self.last_seq {
+                    self.lost += seq - last - 1;
+                }
+                self.last_seq = Some(seq);
+                true
+            }
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    // Sequence numbers skipped over. One that turns up late still counts, since it is
+    // discarded as stale.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+}
+
+type LinkRegistry = Arc<Mutex<HashMap<SocketAddr, Arc<Mutex<LinkMonitor>>>>>;
+
+// Per-client queues for events routed to one client only, keyed by client fingerprint
+type RouteTable = Arc<Mutex<HashMap<String, Arc<OutboundQueue>>>>;
+
+// Sessions of clients that dropped and may resume, keyed by resume token
+type ParkedSessions = Arc<Mutex<HashMap<String, ClientSession>>>;
+
+// Sessions still streaming to a client, keyed by resume token. A resume that gets there before
+// the server has noticed the old connection is gone takes the session over through here.
+type LiveSessions = Arc<Mutex<HashMap<String, LiveSession>>>;
+
+struct LiveSession {
+    fingerprint: String,
+    takeover: oneshot::Sender<oneshot::Sender<ClientSession>>,
+}
+
+// Server-wide state handed to every client session
+#[derive(Clone)]
+struct SessionContext {
+    fingerprint: String,
+    timeout: Duration,
+    resume_grace: Duration,
+    parked: ParkedSessions,
+    live: LiveSessions,
+    links: LinkRegistry,
+    layout: Arc<Mutex<DesktopLayout>>,
+    routes: RouteTable,
+    placements: HashMap<String, ScreenPlacement>,
+    states: Arc<StateMachine>,
+}
+
+// Answers heartbeats and feeds acks into the link monitor. Anything that is not heartbeat
+// traffic is handed back to the caller.
+async fn handle_heartbeat(
+    connection: &mut dyn Connection,
+    link: &Mutex<LinkMonitor>,
+    message: NetworkMessage,
+) -> Result<Option<NetworkMessage>> {
+    let receive_us = heartbeat::monotonic_us();
+    link.lock().await.record_activity();
+
+    match message {
+        NetworkMessage::Heartbeat { timestamp_us } => {
+            connection
+                .send(&NetworkMessage::HeartbeatAck {
+                    echo_timestamp_us: timestamp_us,
+                    receive_us,
+                    transmit_us: heartbeat::monotonic_us(),
+                })
+                .await?;
+            Ok(None)
+        }
+        NetworkMessage::HeartbeatAck {
+            echo_timestamp_us,
+            receive_us,
+            transmit_us,
+        } => {
+            link.lock()
+                .await
+                .record_echo(echo_timestamp_us, receive_us, transmit_us);
+            Ok(None)
+        }
+        other => Ok(Some(other)),
+    }
+}
+
+// Sends the next heartbeat, or returns false if the peer has gone quiet for longer than the
+// configured timeout and should be treated as dead.
+async fn send_heartbeat(
+    connection: &mut dyn Connection,
+    link: &Mutex<LinkMonitor>,
+) -> Result<bool> {
+    if link.lock().await.is_timed_out() {
+        return Ok(false);
+    }
+
+    connection
+        .send(&NetworkMessage::Heartbeat {
+            timestamp_us: heartbeat::monotonic_us(),
+        })
+        .await?;
+    Ok(true)
+}
+
+// Returns the size of what was written; the length prefix counts towards `wire`
+pub async fn write_frame<W>(
+    writer: &mut W,
+    message: &NetworkMessage,
+    format: WireFormat,
+) -> Result<MessageSize>
+where
+    W: AsyncWrite + Unpin,
+{
+    let payload = codec::encode(message, format)?;
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(anyhow::anyhow!(
+            "Frame of {} bytes exceeds limit",
+            payload.len()
+        ));
+    }
+
+    writer.write_u32(payload.len() as u32).await?;
+    writer.write_all(&payload).await?;
+    writer.flush().await?;
+    Ok(MessageSize::new(&payload, payload.len() + 4))
+}
+
+// Returns Ok(None) when the peer closes the stream cleanly between frames.
+pub async fn read_frame<R>(reader: &mut R) -> Result<Option<NetworkMessage>>
+where
+    R: AsyncRead + Unpin,
+{
+    Ok(read_frame_sized(reader, DEFAULT_MAX_MESSAGE_LEN)
+        .await?
+        .map(|(message, _)| message))
+}
+
+// Like read_frame, but also reports the size of the frame. A frame that would hold a message
+// over `max_message_len` is refused before anything is allocated for it.
+pub async fn read_frame_sized<R>(
+    reader: &mut R,
+    max_message_len: usize,
+) -> Result<Option<(NetworkMessage, MessageSize)>>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    check_frame_len(len, max_message_len)?;
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+    let size = MessageSize::new(&payload, len + 4);
+    Ok(Some((decode_frame(&payload, max_message_len)?, size)))
+}
+
+// For a frame's length prefix, before it is read. Compression never makes a payload bigger,
+// so a frame longer than the message limit can't hold a message under it.
+pub fn check_frame_len(len: usize, max_message_len: usize) -> Result<()> {
+    if len > MAX_FRAME_LEN.min(max_message_len) {
+        return Err(anyhow::anyhow!("Frame of {} bytes exceeds limit", len));
+    }
+    Ok(())
+}
+
+// Checks what a compressed payload says it expands to before expanding it
+pub fn decode_frame(payload: &[u8], max_message_len: usize) -> Result<NetworkMessage> {
+    let len = codec::plain_len(payload);
+    if len > max_message_len {
+        return Err(anyhow::anyhow!("Message of {} bytes exceeds limit", len));
+    }
+    codec::decode(payload)
 }
 
 pub struct Server {
     config: ConnectionConfig,
     input_manager: Arc<InputManager>,
+    fingerprint: String,
+    transport: Arc<dyn Transport>,
     stop_tx: Arc<Mutex<Option<mpsc::Sender<()>>>>,
+    states: Arc<StateMachine>,
 }
 
 pub struct Client {
     config: ConnectionConfig,
     input_manager: Arc<InputManager>,
+    fingerprint: String,
+    transport: Arc<dyn Transport>,
     stop_tx: Arc<Mutex<Option<mpsc::Sender<()>>>>,
+    states: Arc<StateMachine>,
 }
 
 impl Server {
-    pub async fn new(config: ConnectionConfig, input_manager: Arc<InputManager>) -> Result<Self> {
+    pub async fn new(
+        config: ConnectionConfig,
+        input_manager: Arc<InputManager>,
+        fingerprint: String,
+    ) -> Result<Self> {
         Ok(Self {
+            transport: transport::for_config(&config).into(),
             config,
             input_manager,
+            fingerprint,
             stop_tx: Arc::new(Mutex::new(None)),
+            states: StateMachine::new(),
         })
     }
 
+    // Replaces the transport picked from config.protocol
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    // Reports lifecycle changes to a machine shared with the caller instead of a private one
+    pub fn with_states(mut self, states: Arc<StateMachine>) -> Self {
+        self.states = states;
+        self
+    }
+
     pub async fn start(&self) -> Result<ServerHandle> {
+        let handle = self.listen().await?;
+
+        // Route locally captured mouse events to whichever client's screen has the cursor
+        let input_manager = self.input_manager.clone();
+        let outgoing_tx = handle.outgoing_tx.clone();
+        let layout = handle.layout.clone();
+        let routes = handle.routes.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(CAPTURE_INTERVAL_MS));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+            while !outgoing_tx.is_closed() {
+                interval.tick().await;
+                let events = match input_manager.capture_mouse_events().await {
+                    Ok(events) => events,
+                    Err(e) => {
+                        log::warn!("Failed to capture mouse events: {}", e);
+                        continue;
+                    }
+                };
+
+                // Motion queued this tick is merged into whatever the session hasn't sent yet,
+                // so a client on a slow link gets fewer, larger moves rather than a backlog
+                let mut warp = layout.lock().await.take_warp();
+                for event in events {
+                    let outcome = layout.lock().await.route(event);
+                    warp = outcome.warp.or(warp);
+                    for (fingerprint, event) in outcome.sends {
+                        let route = routes.lock().await.get(&fingerprint).cloned();
+                        if let Some(route) = route {
+                            route.push(NetworkMessage::MouseEvent(event));
+                        }
+                    }
+                }
+
+                if let Some((x, y)) = warp {
+                    if let Err(e) = input_manager.set_mouse_position(x, y).await {
+                        log::warn!("Failed to move local cursor: {}", e);
+                    }
+                }
+            }
+        });
+
+        Ok(handle)
+    }
+
+    // Binds the listener and starts accepting clients without capturing local input.
+    // Messages are only sent when pushed through ServerHandle::send.
+    pub async fn listen(&self) -> Result<ServerHandle> {
         let (stop_tx, mut stop_rx) = mpsc::channel::<()>(1);
-        
+        let (outgoing_tx, mut outgoing_rx) = mpsc::channel::<NetworkMessage>(256);
+
         // Store stop channel
         *self.stop_tx.lock().await = Some(stop_tx.clone());
-        
-        // Start input capture loop (simplified for now)
-        let input_manager = self.input_manager.clone();
-        
+
+        let bind_ip = resolve_bind_address(self.config.bind.as_deref())?;
+        let transport = &self.transport;
+        let limiter = RateLimiter::new(self.config.rate_limits.clone());
+        let mut listener = transport
+            .listen_with_limit(
+                SocketAddr::new(bind_ip, self.config.port),
+                limiter.max_message_len(),
+            )
+            .await?;
+        let local_addr = listener.local_addr();
+        log::info!(
+            "Server listening on {} over {}",
+            local_addr,
+            transport.name()
+        );
+        self.states.transition(ConnectionState::Listening)?;
+
+        let local_screen = Rect::bounding(&self.input_manager.get_screen_bounds().await?)
+            .ok_or_else(|| anyhow::anyhow!("No screens found"))?;
+
+        let context = SessionContext {
+            fingerprint: self.fingerprint.clone(),
+            timeout: Duration::from_millis(self.config.timeout_ms),
+            resume_grace: Duration::from_millis(self.config.reconnect.resume_grace_ms),
+            parked: Arc::new(Mutex::new(HashMap::new())),
+            live: Arc::new(Mutex::new(HashMap::new())),
+            links: Arc::new(Mutex::new(HashMap::new())),
+            layout: Arc::new(Mutex::new(DesktopLayout::new(local_screen))),
+            routes: Arc::new(Mutex::new(HashMap::new())),
+            placements: self.config.placements.clone(),
+            states: self.states.clone(),
+        };
+        let links = context.links.clone();
+        let layout = context.layout.clone();
+        let routes = context.routes.clone();
+        let transport_name = transport.name();
+
         tokio::spawn(async move {
+            // Every connected client subscribes to this; dropping it on shutdown ends their sessions
+            let (broadcast_tx, _) = broadcast::channel::<NetworkMessage>(256);
+
             loop {
                 tokio::select! {
                     _ = stop_rx.recv() => break,
-                    _ = tokio::time::sleep(tokio::time::Duration::from_millis(16)) => {
-                        // Capture mouse events (simplified)
-                        let _ = input_manager.capture_mouse_events().await;
+                    accepted = listener.accept() => match accepted {
+                        Ok(mut connection) => {
+                            let Some(handshake) = limiter.admit(connection.peer_addr()) else {
+                                let _ = connection.close().await;
+                                continue;
+                            };
+                            let (connection, meter) =
+                                traffic::track(connection, transport_name, ConnectionSide::Server);
+                            let connection = limiter.wrap(connection);
+                            let context = context.clone();
+                            let messages = broadcast_tx.subscribe();
+                            tokio::spawn(async move {
+                                let peer = connection.peer_addr();
+                                if let Err(e) =
+                                    serve_client(connection, meter, handshake, context, messages)
+                                        .await
+                                {
+                                    log::warn!("Client {} disconnected with error: {}", peer, e);
+                                }
+                            });
+                        }
+                        Err(e) => log::warn!("Failed to accept connection: {}", e),
+                    },
+                    Some(message) = outgoing_rx.recv() => {
+                        // No subscribers just means nobody is connected yet
+                        let _ = broadcast_tx.send(message);
                     }
                 }
             }
+
+            log::info!("Server on {} stopped", local_addr);
         });
-        
-        Ok(ServerHandle { stop_tx })
+
+        Ok(ServerHandle {
+            stop_tx,
+            outgoing_tx,
+            local_addr,
+            links,
+            layout,
+            routes,
+        })
+    }
+}
+
+// Everything about a client that outlives one connection, so that a resumed session carries on
+// with the same screen, queue and button state
+struct ClientSession {
+    fingerprint: String,
+    device_name: String,
+    negotiated: Negotiated,
+    routed: Arc<OutboundQueue>,
+    link: Arc<Mutex<LinkMonitor>>,
+    replay: ReplayBuffer,
+    // Where we last put the client's cursor, to turn deltas back into positions for clients
+    // that can't apply them
+    position: Option<(i32, i32)>,
+}
+
+enum StreamEnd {
+    // The client said goodbye, or the server is shutting down
+    Closed,
+    // The connection dropped; the client may come back and resume
+    Lost,
+    // The client resumed on another connection, which wants the session handed over
+    Replaced(oneshot::Sender<ClientSession>),
+}
+
+// `handshake` holds the connection's place among the pending handshakes until it is answered
+async fn serve_client(
+    mut connection: Box<dyn Connection>,
+    meter: Arc<ConnectionMeter>,
+    handshake: OwnedSemaphorePermit,
+    context: SessionContext,
+    mut messages: broadcast::Receiver<NetworkMessage>,
+) -> Result<()> {
+    let fingerprint = context.fingerprint.clone();
+    let peer = connection.peer_addr();
+
+    // A peer that connects and says nothing would otherwise keep its place forever
+    let request = tokio::time::timeout(context.timeout, connection.receive())
+        .await
+        .map_err(|_| anyhow::anyhow!("No handshake from {} in time", peer))??;
+    let (token, mut session) = match request {
+        Some(NetworkMessage::ConnectionRequest {
+            fingerprint: client_fingerprint,
+            device_name,
+            min_version,
+            max_version,
+            capabilities,
+            probe,
+            screen,
+        }) => match protocol::negotiate(min_version, max_version, &capabilities) {
+            Ok(negotiated) if probe => {
+                log::info!(
+                    "Answered connectivity probe from {} ({})",
+                    peer,
+                    device_name
+                );
+                connection
+                    .send(&NetworkMessage::ConnectionResponse {
+                        accepted: true,
+                        fingerprint,
+                        device_name: protocol::local_device_name(),
+                        version: negotiated.version,
+                        capabilities: negotiated.capabilities,
+                        reason: None,
+                        resume_token: None,
+                    })
+                    .await?;
+                let _ = connection.close().await;
+                return Ok(());
+            }
+            Ok(negotiated) => {
+                log::info!(
+                    "Client {} ({}) connected with fingerprint {} using protocol v{}",
+                    peer,
+                    device_name,
+                    client_fingerprint,
+                    negotiated.version
+                );
+                let token = resume::new_token();
+                connection
+                    .send(&NetworkMessage::ConnectionResponse {
+                        accepted: true,
+                        fingerprint,
+                        device_name: protocol::local_device_name(),
+                        version: negotiated.version,
+                        capabilities: negotiated.capabilities,
+                        reason: None,
+                        resume_token: Some(token.clone()),
+                    })
+                    .await?;
+                connection.set_wire_format(negotiated.wire_format());
+                let session =
+                    start_session(&context, client_fingerprint, device_name, screen, negotiated)
+                        .await;
+                (token, session)
+            }
+            Err(reason) => {
+                log::warn!("Refusing client {} ({}): {}", peer, device_name, reason);
+                connection
+                    .send(&NetworkMessage::ConnectionResponse {
+                        accepted: false,
+                        fingerprint,
+                        device_name: protocol::local_device_name(),
+                        version: protocol::PROTOCOL_VERSION,
+                        capabilities: Capabilities::default(),
+                        reason: Some(reason),
+                        resume_token: None,
+                    })
+                    .await?;
+                return Ok(());
+            }
+        },
+        Some(NetworkMessage::ResumeRequest {
+            token,
+            received,
+            fingerprint: client_fingerprint,
+        }) => {
+            // A token presented under another fingerprint is treated as unknown and left parked
+            let parked = {
+                let mut parked = context.parked.lock().await;
+                match parked.get(&token) {
+                    Some(session) if session.fingerprint != client_fingerprint => None,
+                    _ => parked.remove(&token),
+                }
+            };
+            let parked = match parked {
+                Some(session) => Some(session),
+                None => take_over(&context, &token, &client_fingerprint).await,
+            };
+            let resumed = match parked {
+                Some(mut session) => match session.replay.unacked(received) {
+                    Ok(replay) => Ok((session, replay)),
+                    Err(reason) => {
+                        end_session(&context, &session).await;
+                        Err(reason)
+                    }
+                },
+                None => Err("Session expired or unknown".to_string()),
+            };
+
+            match resumed {
+                Ok((session, replay)) => {
+                    log::info!(
+                        "Client {} ({}) resumed its session, replaying {} events",
+                        peer,
+                        session.device_name,
+                        replay.len()
+                    );
+                    let token = resume::new_token();
+                    connection
+                        .send(&NetworkMessage::ResumeResponse {
+                            accepted: true,
+                            resume_token: Some(token.clone()),
+                            reason: None,
+                        })
+                        .await?;
+                    connection.set_wire_format(session.negotiated.wire_format());
+                    meter.record_replayed(replay.len());
+                    for message in replay {
+                        connection.send(&message).await?;
+                    }
+                    (token, session)
+                }
+                Err(reason) => {
+                    log::info!("Refusing resume from {}: {}", peer, reason);
+                    connection
+                        .send(&NetworkMessage::ResumeResponse {
+                            accepted: false,
+                            resume_token: None,
+                            reason: Some(reason),
+                        })
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        Some(other) => {
+            return Err(anyhow::anyhow!(
+                "Expected connection request, got {:?}",
+                other
+            ));
+        }
+        None => return Ok(()),
+    };
+    drop(handshake);
+
+    let (takeover_tx, mut takeover) = oneshot::channel();
+    context.live.lock().await.insert(
+        token.clone(),
+        LiveSession {
+            fingerprint: session.fingerprint.clone(),
+            takeover: takeover_tx,
+        },
+    );
+    session.link.lock().await.record_activity();
+    context.links.lock().await.insert(peer, session.link.clone());
+    // Only from Listening: refused once the server is stopped or this device has become a
+    // client, and already Active when another client got here first
+    let _ = context
+        .states
+        .transition_from(&ConnectionState::Listening, ConnectionState::Active);
+    meter.set_peer_fingerprint(&session.fingerprint);
+    meter.attach_queue(session.routed.clone());
+
+    let result = stream_to_client(
+        connection.as_mut(),
+        &mut session,
+        &mut messages,
+        &mut takeover,
+        heartbeat::interval_for_timeout(context.timeout.as_millis() as u64),
+    )
+    .await;
+    // Already gone if the session was taken over
+    context.live.lock().await.remove(&token);
+    // A client that says goodbye and hangs up at once can fail our next send before its
+    // goodbye has been read, and that is no reason to hold its session
+    let result = match result {
+        Err(e) if !e.is::<Banned>() && said_goodbye(connection.as_mut()).await => {
+            log::info!("Client {} disconnected", peer);
+            Ok(StreamEnd::Closed)
+        }
+        result => result,
+    };
+    let _ = connection.close().await;
+    log::debug!("Session with {} ended: {:?}", peer, connection.stats());
+    let remaining = {
+        let mut links = context.links.lock().await;
+        links.remove(&peer);
+        links.len()
+    };
+    // Not if the server was stopped meanwhile
+    if remaining == 0 {
+        let _ = context
+            .states
+            .transition_from(&ConnectionState::Active, ConnectionState::Listening);
+    }
+
+    let result = match result {
+        Ok(StreamEnd::Replaced(handoff)) => {
+            log::info!("Client {} resumed its session on another connection", peer);
+            // Refused if the new connection gave up waiting, and then nobody else will end it
+            if let Err(session) = handoff.send(session) {
+                session.link.lock().await.mark_closed();
+                end_session(&context, &session).await;
+            }
+            return Ok(());
+        }
+        result => result,
+    };
+    // A banned client has no business resuming
+    let banned = matches!(&result, Err(e) if e.is::<Banned>());
+    match &result {
+        Ok(StreamEnd::Lost) | Err(_) if !banned && !context.resume_grace.is_zero() => {
+            park_session(&context, token, session).await;
+        }
+        _ => {
+            session.link.lock().await.mark_closed();
+            end_session(&context, &session).await;
+        }
+    }
+
+    result.map(|_| ())
+}
+
+// Asks the connection still serving the session to hand it over, for a client that noticed
+// the drop before we did
+async fn take_over(
+    context: &SessionContext,
+    token: &str,
+    client_fingerprint: &str,
+) -> Option<ClientSession> {
+    let live = {
+        let mut live = context.live.lock().await;
+        match live.get(token) {
+            Some(session) if session.fingerprint == client_fingerprint => live.remove(token),
+            _ => None,
+        }
+    }?;
+    let (handoff, session) = oneshot::channel();
+    live.takeover.send(handoff).ok()?;
+    tokio::time::timeout(context.timeout, session)
+        .await
+        .ok()?
+        .ok()
+}
+
+async fn said_goodbye(connection: &mut dyn Connection) -> bool {
+    let grace = Duration::from_millis(GOODBYE_GRACE_MS);
+    while let Ok(Ok(Some(message))) = tokio::time::timeout(grace, connection.receive()).await {
+        if matches!(message, NetworkMessage::Goodbye) {
+            return true;
+        }
+    }
+    false
+}
+
+async fn start_session(
+    context: &SessionContext,
+    client_fingerprint: String,
+    device_name: String,
+    screen: Option<Rect>,
+    negotiated: Negotiated,
+) -> ClientSession {
+    let routed = Arc::new(OutboundQueue::new(OUTBOUND_QUEUE_CAPACITY));
+    context
+        .routes
+        .lock()
+        .await
+        .insert(client_fingerprint.clone(), routed.clone());
+    {
+        let mut layout = context.layout.lock().await;
+        // Clients too old to report their screen are assumed to match ours
+        let screen = screen.unwrap_or_else(|| layout.local());
+        let placement = layout.add_client(
+            &client_fingerprint,
+            &device_name,
+            screen,
+            context.placements.get(&device_name).copied(),
+        );
+        log::info!("Placed {} {:?} the server screen", device_name, placement);
+    }
+
+    ClientSession {
+        fingerprint: client_fingerprint,
+        device_name,
+        negotiated,
+        routed,
+        link: Arc::new(Mutex::new(LinkMonitor::new(context.timeout))),
+        replay: ReplayBuffer::default(),
+        position: None,
+    }
+}
+
+// Keeps the client's screen and queue in place for the grace window. Events routed to it in
+// the meantime wait in its queue.
+async fn park_session(context: &SessionContext, token: String, session: ClientSession) {
+    log::info!(
+        "Holding session of {} for {} ms",
+        session.device_name,
+        context.resume_grace.as_millis()
+    );
+    context.parked.lock().await.insert(token.clone(), session);
+
+    let context = context.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(context.resume_grace).await;
+        // Gone already if it was resumed, which also retired this token
+        let expired = context.parked.lock().await.remove(&token);
+        if let Some(session) = expired {
+            log::info!("Session of {} expired", session.device_name);
+            session.link.lock().await.mark_closed();
+            end_session(&context, &session).await;
+        }
+    });
+}
+
+// Routes and screens are filed by fingerprint, so once the client has handshaken again they
+// belong to its new session and an old one expiring must leave them alone. The route is held
+// locked until the screen is gone, so that a new session can't slip in between.
+async fn end_session(context: &SessionContext, session: &ClientSession) {
+    let mut routes = context.routes.lock().await;
+    match routes.get(&session.fingerprint) {
+        Some(routed) if Arc::ptr_eq(routed, &session.routed) => {
+            routes.remove(&session.fingerprint);
+        }
+        _ => return,
+    }
+    context
+        .layout
+        .lock()
+        .await
+        .remove_client(&session.fingerprint);
+}
+
+async fn stream_to_client(
+    connection: &mut dyn Connection,
+    session: &mut ClientSession,
+    messages: &mut broadcast::Receiver<NetworkMessage>,
+    takeover: &mut oneshot::Receiver<oneshot::Sender<ClientSession>>,
+    heartbeat_interval: Duration,
+) -> Result<StreamEnd> {
+    let peer = connection.peer_addr();
+    let negotiated = session.negotiated;
+    let link = session.link.clone();
+    let routed = session.routed.clone();
+    let mut heartbeat = tokio::time::interval(heartbeat_interval);
+
+    loop {
+        let batch = tokio::select! {
+            message = messages.recv() => match message {
+                Ok(message) => vec![message],
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    log::warn!("Client {} fell behind, skipped {} messages", peer, skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(StreamEnd::Closed),
+            },
+            batch = routed.pop_batch() => batch,
+            handoff = &mut *takeover => {
+                return Ok(handoff.map_or(StreamEnd::Lost, StreamEnd::Replaced));
+            },
+            message = connection.receive() => {
+                match message? {
+                    Some(message) => match handle_heartbeat(connection, &link, message).await? {
+                        Some(NetworkMessage::InputAck { received }) => session.replay.ack(received),
+                        Some(NetworkMessage::Goodbye) => {
+                            log::info!("Client {} disconnected", peer);
+                            return Ok(StreamEnd::Closed);
+                        }
+                        Some(message) => log::debug!("Ignoring message from {}: {:?}", peer, message),
+                        None => {}
+                    },
+                    None => {
+                        log::info!("Client {} dropped the connection", peer);
+                        return Ok(StreamEnd::Lost);
+                    }
+                }
+                continue;
+            },
+            _ = heartbeat.tick() => {
+                if !send_heartbeat(connection, &link).await? {
+                    log::warn!("Client {} stopped responding", peer);
+                    return Ok(StreamEnd::Lost);
+                }
+                continue;
+            }
+        };
+
+        for outgoing in batch {
+            let outgoing = match outgoing {
+                NetworkMessage::MouseEvent(mut event) => {
+                    if event.is_relative() {
+                        let Some((x, y)) = session.position else {
+                            continue;
+                        };
+                        session.position = Some((x + event.x, y + event.y));
+                        if !negotiated.capabilities.relative_motion {
+                            (event.x, event.y) = (x + event.x, y + event.y);
+                            event.positioning = Positioning::Absolute;
+                        }
+                    } else {
+                        session.position = Some((event.x, event.y));
+                    }
+                    NetworkMessage::MouseEvent(event)
+                }
+                message => message,
+            };
+
+            match outgoing {
+                NetworkMessage::MouseEvent(event)
+                    if !negotiated.capabilities.wheel
+                        && (event.wheel_x != 0 || event.wheel_y != 0) => {}
+                message => {
+                    // Recorded before sending, so an event lost with the connection is replayed
+                    session.replay.record(&message);
+                    connection.send(&message).await?;
+                }
+            }
+        }
     }
 }
 
 impl Client {
     pub async fn new(config: ConnectionConfig, input_manager: Arc<InputManager>) -> Result<Self> {
         Ok(Self {
+            transport: transport::for_config(&config).into(),
             config,
             input_manager,
+            fingerprint: Uuid::new_v4().to_string(),
             stop_tx: Arc::new(Mutex::new(None)),
+            states: StateMachine::new(),
         })
     }
 
+    // Replaces the transport picked from config.protocol
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    // Reports lifecycle changes to a machine shared with the caller instead of a private one
+    pub fn with_states(mut self, states: Arc<StateMachine>) -> Self {
+        self.states = states;
+        self
+    }
+
     pub async fn connect(&self) -> Result<ClientHandle> {
+        let (events_tx, mut events_rx) = mpsc::channel::<MouseEvent>(256);
+        let handle = self.connect_with_sink(events_tx).await?;
+
+        // Replay received events through the local input manager
+        let input_manager = self.input_manager.clone();
+        let status = handle.status.clone();
+        let latency = handle.latency.clone();
+
+        tokio::spawn(async move {
+            while let Some(event) = events_rx.recv().await {
+                let captured_at_us = event.captured_at_us;
+                if let Err(e) = input_manager.emulate_mouse_event(event).await {
+                    log::warn!("Failed to emulate mouse event: {}", e);
+                    continue;
+                }
+                if captured_at_us == 0 {
+                    continue;
+                }
+
+                // The capture time is on the server's clock; shift it onto ours
+                let link = status.lock().await.link.clone();
+                let Some(offset_us) = link.lock().await.clock_offset_us() else {
+                    continue;
+                };
+                let latency_us =
+                    heartbeat::monotonic_us() as i64 + offset_us - captured_at_us as i64;
+                // Slightly negative when the offset estimate is off by more than the latency
+                latency.lock().await.record(latency_us.max(0) as u64);
+            }
+        });
+
+        Ok(handle)
+    }
+
+    // Connects and performs the handshake, but hands decoded events to `sink` instead of
+    // replaying them. This is what lets two peers be wired together in-process.
+    pub async fn connect_with_sink(&self, sink: mpsc::Sender<MouseEvent>) -> Result<ClientHandle> {
         let (stop_tx, mut stop_rx) = mpsc::channel::<()>(1);
-        
+
         // Store stop channel
         *self.stop_tx.lock().await = Some(stop_tx.clone());
-        
-        // Start message handling loop (simplified for now)
-        let input_manager = self.input_manager.clone();
-        
+
+        let connector = Connector {
+            config: self.config.clone(),
+            transport: self.transport.clone(),
+            fingerprint: self.fingerprint.clone(),
+            screen: Rect::bounding(&self.input_manager.get_screen_bounds().await?),
+            states: self.states.clone(),
+        };
+
+        // The first attempt is made inline so the caller sees why it failed
+        let mut session = connector
+            .establish()
+            .await
+            .inspect_err(|e| self.states.fail(e))?;
+        self.states
+            .transition_from(&ConnectionState::Handshaking, ConnectionState::Active)?;
+        let status = Arc::new(Mutex::new(ClientStatus::new(&session, &connector.config)));
+        let task_status = status.clone();
+
         tokio::spawn(async move {
+            let status = task_status;
+            let mut tracker = InputTracker::default();
+
             loop {
-                tokio::select! {
-                    _ = stop_rx.recv() => break,
-                    _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {
-                        // Handle incoming mouse events (simplified)
-                        // TODO: Implement actual network communication
+                let link = status.lock().await.link.clone();
+                let end = run_session(
+                    &mut session,
+                    &connector,
+                    &link,
+                    &sink,
+                    &mut stop_rx,
+                    &mut tracker,
+                )
+                .await;
+                link.lock().await.mark_closed();
+
+                if matches!(end, SessionEnd::Stopped) {
+                    release_buttons(&mut tracker, &sink).await;
+                    break;
+                }
+
+                let policy = &connector.config.reconnect;
+                if !policy.enabled || !crate::reconnect::is_enabled() {
+                    release_buttons(&mut tracker, &sink).await;
+                    status.lock().await.state = LinkState::Disconnected;
+                    connector.states.fail(&anyhow::anyhow!(
+                        "Lost the connection to {}",
+                        session.remote_address
+                    ));
+                    break;
+                }
+
+                match reconnect(&connector, &status, &mut stop_rx, &session, &tracker).await {
+                    Some((next, resumed)) => {
+                        // A fresh session knows nothing of what was held before the drop
+                        if !resumed {
+                            release_buttons(&mut tracker, &sink).await;
+                            tracker.reset();
+                        }
+                        session = next;
+                        status.lock().await.update(&session, &connector.config);
+                        let _ = connector.states.transition_from(
+                            &ConnectionState::Handshaking,
+                            ConnectionState::Active,
+                        );
+                    }
+                    None => {
+                        release_buttons(&mut tracker, &sink).await;
+                        status.lock().await.state = LinkState::Disconnected;
+                        break;
                     }
                 }
             }
         });
-        
-        Ok(ClientHandle { stop_tx })
+
+        Ok(ClientHandle {
+            stop_tx,
+            status,
+            latency: Arc::new(Mutex::new(LatencyWindow::default())),
+        })
+    }
+}
+
+// Everything needed to (re)establish a session with the server
+struct Connector {
+    config: ConnectionConfig,
+    transport: Arc<dyn Transport>,
+    fingerprint: String,
+    screen: Option<Rect>,
+    states: Arc<StateMachine>,
+}
+
+struct Session {
+    connection: Box<dyn Connection>,
+    remote_address: SocketAddr,
+    server_fingerprint: String,
+    negotiated: Negotiated,
+    resume_token: Option<String>,
+}
+
+enum SessionEnd {
+    Stopped,
+    Lost,
+}
+
+impl Connector {
+    async fn connect(
+        &self,
+        address: &str,
+        timeout: Duration,
+    ) -> Result<(Box<dyn Connection>, Arc<ConnectionMeter>)> {
+        // Fails if the user disconnected while an attempt was on its way
+        self.states.transition(ConnectionState::Connecting)?;
+        let connection = self.transport.connect(address, timeout).await?;
+        self.states.transition(ConnectionState::Handshaking)?;
+        Ok(traffic::track(
+            connection,
+            self.transport.name(),
+            ConnectionSide::Client,
+        ))
+    }
+
+    async fn establish(&self) -> Result<Session> {
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let address = format!("{}:{}", self.config.host, self.config.port);
+
+        let (mut connection, meter) = self.connect(&address, timeout).await?;
+        let remote_address = connection.peer_addr();
+
+        connection
+            .send(&NetworkMessage::ConnectionRequest {
+                fingerprint: self.fingerprint.clone(),
+                device_name: protocol::local_device_name(),
+                min_version: protocol::MIN_PROTOCOL_VERSION,
+                max_version: protocol::PROTOCOL_VERSION,
+                capabilities: Capabilities::local(),
+                probe: false,
+                screen: self.screen,
+            })
+            .await?;
+
+        let (server_fingerprint, negotiated, resume_token) =
+            match tokio::time::timeout(timeout, connection.receive()).await {
+                Ok(Err(e)) => return Err(e),
+                Ok(Ok(Some(NetworkMessage::ConnectionResponse {
+                    accepted: true,
+                    fingerprint,
+                    version,
+                    capabilities,
+                    resume_token,
+                    ..
+                }))) => {
+                    if !protocol::is_supported(version) {
+                        return Err(anyhow::anyhow!(
+                            "{} selected unsupported protocol version {}",
+                            address,
+                            version
+                        ));
+                    }
+                    let negotiated = Negotiated {
+                        version,
+                        capabilities: Capabilities::local().intersect(&capabilities),
+                    };
+                    (fingerprint, negotiated, resume_token)
+                }
+                Ok(Ok(Some(NetworkMessage::ConnectionResponse {
+                    accepted: false,
+                    reason,
+                    ..
+                }))) => {
+                    return Err(anyhow::anyhow!(
+                        "Connection rejected by {}: {}",
+                        address,
+                        reason.unwrap_or_else(|| "no reason given".to_string())
+                    ));
+                }
+                Ok(Ok(Some(other))) => {
+                    return Err(anyhow::anyhow!(
+                        "Expected connection response, got {:?}",
+                        other
+                    ));
+                }
+                Ok(Ok(None)) => {
+                    return Err(anyhow::anyhow!(
+                        "{} closed the connection during handshake",
+                        address
+                    ))
+                }
+                Err(_) => {
+                    return Err(anyhow::anyhow!(
+                        "Timed out waiting for handshake from {}",
+                        address
+                    ))
+                }
+            };
+        log::info!(
+            "Connected to {} with fingerprint {} using protocol v{}",
+            remote_address,
+            server_fingerprint,
+            negotiated.version
+        );
+        meter.set_peer_fingerprint(&server_fingerprint);
+        connection.set_wire_format(negotiated.wire_format());
+
+        Ok(Session {
+            connection,
+            remote_address,
+            server_fingerprint,
+            negotiated,
+            resume_token,
+        })
+    }
+
+    // Picks `previous` back up without a handshake. Ok(None) means the server no longer holds
+    // the session and a full handshake is needed.
+    async fn resume(&self, previous: &Session, token: &str, received: u64) -> Result<Option<Session>> {
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let address = format!("{}:{}", self.config.host, self.config.port);
+
+        let (mut connection, meter) = self.connect(&address, timeout).await?;
+        meter.set_peer_fingerprint(&previous.server_fingerprint);
+        connection
+            .send(&NetworkMessage::ResumeRequest {
+                token: token.to_string(),
+                received,
+                fingerprint: self.fingerprint.clone(),
+            })
+            .await?;
+
+        match tokio::time::timeout(timeout, connection.receive()).await {
+            Ok(Ok(Some(NetworkMessage::ResumeResponse {
+                accepted: true,
+                resume_token,
+                ..
+            }))) => {
+                log::info!("Resumed session with {}", connection.peer_addr());
+                connection.set_wire_format(previous.negotiated.wire_format());
+                Ok(Some(Session {
+                    remote_address: connection.peer_addr(),
+                    connection,
+                    server_fingerprint: previous.server_fingerprint.clone(),
+                    negotiated: previous.negotiated,
+                    resume_token,
+                }))
+            }
+            Ok(Ok(Some(NetworkMessage::ResumeResponse { reason, .. }))) => {
+                log::info!(
+                    "{} could not resume the session: {}",
+                    address,
+                    reason.unwrap_or_else(|| "no reason given".to_string())
+                );
+                let _ = connection.close().await;
+                Ok(None)
+            }
+            Ok(Ok(Some(other))) => Err(anyhow::anyhow!(
+                "Expected resume response, got {:?}",
+                other
+            )),
+            Ok(Ok(None)) => Err(anyhow::anyhow!(
+                "{} closed the connection while resuming",
+                address
+            )),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(anyhow::anyhow!(
+                "Timed out waiting for {} to resume the session",
+                address
+            )),
+        }
+    }
+
+    // Resumes when the server still holds the session, otherwise starts a new one. The flag
+    // says which happened.
+    async fn reestablish(&self, previous: &Session, received: u64) -> Result<(Session, bool)> {
+        if let Some(token) = &previous.resume_token {
+            if let Some(session) = self.resume(previous, token, received).await? {
+                return Ok((session, true));
+            }
+        }
+        Ok((self.establish().await?, false))
+    }
+}
+
+async fn release_buttons(tracker: &mut InputTracker, sink: &mpsc::Sender<MouseEvent>) {
+    for release in tracker.release_all() {
+        let _ = sink.send(release).await;
+    }
+}
+
+async fn run_session(
+    session: &mut Session,
+    connector: &Connector,
+    link: &Mutex<LinkMonitor>,
+    sink: &mpsc::Sender<MouseEvent>,
+    stop_rx: &mut mpsc::Receiver<()>,
+    tracker: &mut InputTracker,
+) -> SessionEnd {
+    let remote_address = session.remote_address;
+    let connection = session.connection.as_mut();
+    let mut heartbeat =
+        tokio::time::interval(heartbeat::interval_for_timeout(connector.config.timeout_ms));
+
+    let end = loop {
+        tokio::select! {
+            _ = stop_rx.recv() => {
+                let _ = connection.send(&NetworkMessage::Goodbye).await;
+                break SessionEnd::Stopped;
+            }
+            message = connection.receive() => match message {
+                Ok(Some(message)) => match handle_heartbeat(connection, link, message).await {
+                    Ok(Some(NetworkMessage::MouseEvent(event))) => {
+                        tracker.record(&event);
+                        if sink.send(event).await.is_err() {
+                            break SessionEnd::Stopped;
+                        }
+                    }
+                    Ok(Some(message)) => log::debug!("Ignoring message from server: {:?}", message),
+                    Ok(None) => {}
+                    Err(e) => {
+                        log::warn!("Lost connection to {}: {}", remote_address, e);
+                        break SessionEnd::Lost;
+                    }
+                },
+                Ok(None) => {
+                    log::info!("Server {} closed the connection", remote_address);
+                    break SessionEnd::Lost;
+                }
+                Err(e) => {
+                    log::warn!("Lost connection to {}: {}", remote_address, e);
+                    break SessionEnd::Lost;
+                }
+            },
+            _ = heartbeat.tick() => match send_heartbeat(connection, link).await {
+                Ok(true) => {
+                    if let Some(received) = tracker.take_ack() {
+                        if let Err(e) = connection.send(&NetworkMessage::InputAck { received }).await {
+                            log::warn!("Lost connection to {}: {}", remote_address, e);
+                            break SessionEnd::Lost;
+                        }
+                    }
+                }
+                Ok(false) => {
+                    log::warn!("Server {} stopped responding", remote_address);
+                    break SessionEnd::Lost;
+                }
+                Err(e) => {
+                    log::warn!("Lost connection to {}: {}", remote_address, e);
+                    break SessionEnd::Lost;
+                }
+            },
+        }
+    };
+
+    let _ = connection.close().await;
+    log::debug!(
+        "Session with {} ended: {:?}",
+        remote_address,
+        connection.stats()
+    );
+    end
+}
+
+// Retries with backoff until a session is re-established, the user disconnects, or the
+// configured attempt limit runs out
+async fn reconnect(
+    connector: &Connector,
+    status: &Mutex<ClientStatus>,
+    stop_rx: &mut mpsc::Receiver<()>,
+    previous: &Session,
+    tracker: &InputTracker,
+) -> Option<(Session, bool)> {
+    let mut backoff = Backoff::new(connector.config.reconnect.clone());
+
+    while let Some(delay) = backoff.next_delay() {
+        // Refused once the user has disconnected
+        if connector
+            .states
+            .transition(ConnectionState::Reconnecting)
+            .is_err()
+        {
+            return None;
+        }
+        let next_retry_at = Utc::now()
+            + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::zero());
+        status.lock().await.state = LinkState::Reconnecting {
+            attempt: backoff.attempt(),
+            next_retry_at,
+        };
+        log::info!(
+            "Reconnecting to {}:{} in {} ms (attempt {})",
+            connector.config.host,
+            connector.config.port,
+            delay.as_millis(),
+            backoff.attempt()
+        );
+
+        tokio::select! {
+            _ = stop_rx.recv() => return None,
+            _ = tokio::time::sleep(delay) => {}
+        }
+
+        tokio::select! {
+            _ = stop_rx.recv() => return None,
+            result = connector.reestablish(previous, tracker.received()) => match result {
+                Ok(reestablished) => return Some(reestablished),
+                Err(e) => log::warn!("Reconnect attempt {} failed: {}", backoff.attempt(), e),
+            },
+        }
+    }
+
+    let error = anyhow::anyhow!(
+        "Gave up on {}:{} after {} attempts",
+        connector.config.host,
+        connector.config.port,
+        backoff.attempt()
+    );
+    log::warn!("{}", error);
+    connector.states.fail(&error);
+    None
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LinkState {
+    Connected,
+    Reconnecting {
+        attempt: u32,
+        next_retry_at: DateTime<Utc>,
+    },
+    Disconnected,
+}
+
+// What the client currently knows about its server, replaced on every successful reconnect
+struct ClientStatus {
+    state: LinkState,
+    remote_address: SocketAddr,
+    server_fingerprint: String,
+    negotiated: Negotiated,
+    link: Arc<Mutex<LinkMonitor>>,
+}
+
+impl ClientStatus {
+    fn new(session: &Session, config: &ConnectionConfig) -> Self {
+        Self {
+            state: LinkState::Connected,
+            remote_address: session.remote_address,
+            server_fingerprint: session.server_fingerprint.clone(),
+            negotiated: session.negotiated,
+            link: Arc::new(Mutex::new(LinkMonitor::new(Duration::from_millis(
+                config.timeout_ms,
+            )))),
+        }
+    }
+
+    fn update(&mut self, session: &Session, config: &ConnectionConfig) {
+        *self = Self::new(session, config);
     }
 }
 
 pub struct ServerHandle {
     stop_tx: mpsc::Sender<()>,
+    outgoing_tx: mpsc::Sender<NetworkMessage>,
+    local_addr: SocketAddr,
+    links: LinkRegistry,
+    layout: Arc<Mutex<DesktopLayout>>,
+    routes: RouteTable,
 }
 
 pub struct ClientHandle {
     stop_tx: mpsc::Sender<()>,
+    status: Arc<Mutex<ClientStatus>>,
+    // Kept across reconnects, unlike the link
+    latency: Arc<Mutex<LatencyWindow>>,
 }
 
 impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // Broadcasts a message to every connected client.
+    pub async fn send(&self, message: NetworkMessage) -> Result<()> {
+        self.outgoing_tx
+            .send(message)
+            .await
+            .map_err(|_| anyhow::anyhow!("Server is not running"))
+    }
+
+    // Sends a message to one client only
+    pub async fn send_to(&self, fingerprint: &str, message: NetworkMessage) -> Result<()> {
+        let route = self
+            .routes
+            .lock()
+            .await
+            .get(fingerprint)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No client with fingerprint {}", fingerprint))?;
+        route.push(message);
+        Ok(())
+    }
+
+    pub async fn queue_stats(&self, fingerprint: &str) -> Option<QueueStats> {
+        self.routes
+            .lock()
+            .await
+            .get(fingerprint)
+            .map(|route| route.stats())
+    }
+
+    pub async fn clients(&self) -> Vec<ClientScreen> {
+        self.layout.lock().await.clients()
+    }
+
+    pub async fn active_client(&self) -> Option<String> {
+        self.layout.lock().await.active().map(str::to_string)
+    }
+
+    pub async fn set_placement(&self, fingerprint: &str, placement: ScreenPlacement) -> Result<()> {
+        if self
+            .layout
+            .lock()
+            .await
+            .set_placement(fingerprint, placement)
+        {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "No client with fingerprint {}",
+                fingerprint
+            ))
+        }
+    }
+
+    pub async fn link_stats(&self) -> Vec<(SocketAddr, LinkStats)> {
+        let links = self.links.lock().await;
+        let mut stats = Vec::with_capacity(links.len());
+        for (peer, link) in links.iter() {
+            stats.push((*peer, link.lock().await.stats()));
+        }
+        stats
+    }
+
     pub async fn stop(self) -> Result<()> {
         let _ = self.stop_tx.send(()).await;
         Ok(())
@@ -111,6 +1626,34 @@ impl ServerHandle {
 }
 
 impl ClientHandle {
+    pub async fn remote_address(&self) -> SocketAddr {
+        self.status.lock().await.remote_address
+    }
+
+    pub async fn server_fingerprint(&self) -> String {
+        self.status.lock().await.server_fingerprint.clone()
+    }
+
+    pub async fn negotiated(&self) -> Negotiated {
+        self.status.lock().await.negotiated
+    }
+
+    pub async fn state(&self) -> LinkState {
+        self.status.lock().await.state.clone()
+    }
+
+    pub async fn link_stats(&self) -> LinkStats {
+        let link = self.status.lock().await.link.clone();
+        let stats = link.lock().await.stats();
+        stats
+    }
+
+    // Capture on the server to injection here; None until events have been injected with a
+    // clock offset known, and never filled for clients connected with a sink
+    pub async fn input_latency(&self) -> Option<LatencyStats> {
+        self.latency.lock().await.stats()
+    }
+
     pub async fn disconnect(self) -> Result<()> {
         let _ = self.stop_tx.send(()).await;
         Ok(())
@@ -118,15 +1661,289 @@ impl ClientHandle {
 }
 
 // Functions called from lib.rs
-pub async fn test_connectivity(host: String, port: u16) -> Result<u64> {
-    // Simple ping-like test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectivityFailure {
+    DnsFailure,
+    Refused,
+    Timeout,
+    Unreachable,
+    VersionMismatch,
+    Rejected,
+    ProtocolError,
+    Other,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConnectivityReport {
+    pub host: String,
+    pub port: u16,
+    pub address: Option<String>,
+    pub success: bool,
+    pub resolve_ms: Option<f64>,
+    pub connect_ms: Option<f64>,
+    pub handshake_ms: Option<f64>,
+    pub remote_version: Option<u32>,
+    pub remote_fingerprint: Option<String>,
+    pub remote_device_name: Option<String>,
+    pub failure: Option<ConnectivityFailure>,
+    pub error: Option<String>,
+}
+
+impl ConnectivityReport {
+    fn fail(mut self, failure: ConnectivityFailure, error: impl Into<String>) -> Self {
+        self.success = false;
+        self.failure = Some(failure);
+        self.error = Some(error.into());
+        self
+    }
+}
+
+// Resolves config.host, connects over the transport the config selects and (when `handshake`
+// is set) runs a probe handshake, timing each stage. Failures are part of the report rather
+// than an Err so the caller always sees how far it got.
+pub async fn test_connectivity(
+    config: &ConnectionConfig,
+    handshake: bool,
+) -> Result<ConnectivityReport> {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let host = config.host.clone();
+    let port = config.port;
+    let mut report = ConnectivityReport {
+        host: host.clone(),
+        port,
+        ..Default::default()
+    };
+
+    let start = std::time::Instant::now();
+    let addresses: Vec<SocketAddr> =
+        match tokio::time::timeout(timeout, tokio::net::lookup_host((host.as_str(), port))).await {
+            Ok(Ok(addresses)) => addresses.collect(),
+            Ok(Err(e)) => {
+                return Ok(report.fail(
+                    ConnectivityFailure::DnsFailure,
+                    format!("Failed to resolve {}: {}", host, e),
+                ))
+            }
+            Err(_) => {
+                return Ok(report.fail(
+                    ConnectivityFailure::Timeout,
+                    format!("Timed out resolving {}", host),
+                ))
+            }
+        };
+    report.resolve_ms = Some(elapsed_ms(start));
+    if addresses.is_empty() {
+        return Ok(report.fail(
+            ConnectivityFailure::DnsFailure,
+            format!("{} did not resolve to any address", host),
+        ));
+    }
+
+    // Try each resolved address like TcpStream::connect does, keeping the last failure. The
+    // transport gets longer than we wait for it, so running out of time reads as a timeout
+    // rather than as whatever error the transport words it as.
+    let transport = transport::for_config(config);
+    let start = std::time::Instant::now();
+    let mut connection = None;
+    let mut last_failure = (ConnectivityFailure::Other, String::new());
+    for address in &addresses {
+        let address = address.to_string();
+        match tokio::time::timeout(timeout, transport.connect(&address, timeout * 2)).await {
+            Ok(Ok(connected)) => {
+                report.address = Some(address);
+                connection = Some(connected);
+                break;
+            }
+            Ok(Err(e)) => {
+                last_failure = (
+                    connect_failure(&e),
+                    format!("Failed to connect to {}: {:#}", address, e),
+                );
+            }
+            Err(_) => {
+                last_failure = (
+                    ConnectivityFailure::Timeout,
+                    format!("Timed out connecting to {}", address),
+                );
+            }
+        }
+    }
+    let Some(mut connection) = connection else {
+        return Ok(report.fail(last_failure.0, last_failure.1));
+    };
+    report.connect_ms = Some(elapsed_ms(start));
+
+    if !handshake {
+        let _ = connection.close().await;
+        report.success = true;
+        return Ok(report);
+    }
+
     let start = std::time::Instant::now();
-    // TODO: Implement actual connectivity test
-    let duration = start.elapsed();
-    Ok(duration.as_millis() as u64)
+    let request = NetworkMessage::ConnectionRequest {
+        fingerprint: Uuid::new_v4().to_string(),
+        device_name: protocol::local_device_name(),
+        min_version: protocol::MIN_PROTOCOL_VERSION,
+        max_version: protocol::PROTOCOL_VERSION,
+        capabilities: Capabilities::local(),
+        probe: true,
+        screen: None,
+    };
+    let exchange = async {
+        connection.send(&request).await?;
+        connection.receive().await
+    };
+    let response = tokio::time::timeout(timeout, exchange).await;
+    let _ = connection.close().await;
+
+    match response {
+        Ok(Ok(Some(NetworkMessage::ConnectionResponse {
+            accepted,
+            fingerprint,
+            device_name,
+            version,
+            reason,
+            ..
+        }))) => {
+            report.handshake_ms = Some(elapsed_ms(start));
+            report.remote_version = Some(version);
+            report.remote_fingerprint = Some(fingerprint);
+            report.remote_device_name = Some(device_name);
+
+            if accepted && protocol::is_supported(version) {
+                report.success = true;
+                Ok(report)
+            } else if !protocol::is_supported(version) {
+                Ok(report.fail(
+                    ConnectivityFailure::VersionMismatch,
+                    reason.unwrap_or_else(|| {
+                        format!(
+                            "Server speaks protocol v{}, this device supports {}-{}",
+                            version,
+                            protocol::MIN_PROTOCOL_VERSION,
+                            protocol::PROTOCOL_VERSION
+                        )
+                    }),
+                ))
+            } else {
+                Ok(report.fail(
+                    ConnectivityFailure::Rejected,
+                    reason.unwrap_or_else(|| "Connection rejected".to_string()),
+                ))
+            }
+        }
+        Ok(Ok(Some(other))) => Ok(report.fail(
+            ConnectivityFailure::ProtocolError,
+            format!("Expected connection response, got {:?}", other),
+        )),
+        Ok(Ok(None)) => Ok(report.fail(
+            ConnectivityFailure::ProtocolError,
+            "Server closed the connection during the handshake",
+        )),
+        Ok(Err(e)) => Ok(report.fail(
+            ConnectivityFailure::ProtocolError,
+            format!("Handshake failed: {}", e),
+        )),
+        Err(_) => Ok(report.fail(
+            ConnectivityFailure::Timeout,
+            "Timed out waiting for handshake response",
+        )),
+    }
 }
 
-pub async fn get_available_interfaces() -> Result<Vec<String>> {
-    // TODO: Implement actual network interface detection
-    Ok(vec!["eth0".to_string(), "wlan0".to_string()])
-} 
\ No newline at end of file
+// Transports wrap the socket error in context of their own
+fn connect_failure(error: &anyhow::Error) -> ConnectivityFailure {
+    let io_error = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<std::io::Error>());
+    match io_error.map(std::io::Error::kind) {
+        Some(std::io::ErrorKind::ConnectionRefused) => ConnectivityFailure::Refused,
+        Some(std::io::ErrorKind::TimedOut) => ConnectivityFailure::Timeout,
+        Some(std::io::ErrorKind::HostUnreachable | std::io::ErrorKind::NetworkUnreachable) => {
+            ConnectivityFailure::Unreachable
+        }
+        _ => ConnectivityFailure::Other,
+    }
+}
+
+fn elapsed_ms(start: std::time::Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterfaceAddress {
+    pub address: IpAddr,
+    pub netmask: IpAddr,
+    pub prefix_len: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub friendly_name: Option<String>,
+    pub ipv4: Vec<InterfaceAddress>,
+    pub ipv6: Vec<InterfaceAddress>,
+    pub is_up: bool,
+    pub is_loopback: bool,
+}
+
+pub async fn get_available_interfaces() -> Result<Vec<NetworkInterface>> {
+    let mut interfaces: Vec<NetworkInterface> = netdev::get_interfaces()
+        .into_iter()
+        .map(|interface| NetworkInterface {
+            ipv4: interface
+                .ipv4
+                .iter()
+                .map(|net| InterfaceAddress {
+                    address: IpAddr::V4(net.addr()),
+                    netmask: IpAddr::V4(net.netmask()),
+                    prefix_len: net.prefix_len(),
+                })
+                .collect(),
+            ipv6: interface
+                .ipv6
+                .iter()
+                .map(|net| InterfaceAddress {
+                    address: IpAddr::V6(net.addr()),
+                    netmask: IpAddr::V6(net.netmask()),
+                    prefix_len: net.prefix_len(),
+                })
+                .collect(),
+            is_up: interface.is_up(),
+            is_loopback: interface.is_loopback(),
+            friendly_name: interface.friendly_name,
+            name: interface.name,
+        })
+        .collect();
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(interfaces)
+}
+
+// `bind` is either an IP address or an interface name; an interface binds to its first IPv4
+// address, falling back to IPv6. Unset means every interface.
+pub fn resolve_bind_address(bind: Option<&str>) -> Result<IpAddr> {
+    let bind = match bind.map(str::trim) {
+        None | Some("") => return Ok(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        Some(bind) => bind,
+    };
+    if let Ok(address) = bind.parse::<IpAddr>() {
+        return Ok(address);
+    }
+
+    let interface = netdev::get_interfaces()
+        .into_iter()
+        .find(|interface| {
+            interface.name == bind || interface.friendly_name.as_deref() == Some(bind)
+        })
+        .ok_or_else(|| anyhow::anyhow!("No network interface named {}", bind))?;
+    if !interface.is_up() {
+        return Err(anyhow::anyhow!("Network interface {} is down", bind));
+    }
+
+    interface
+        .ipv4
+        .first()
+        .map(|net| IpAddr::V4(net.addr()))
+        .or_else(|| interface.ipv6.first().map(|net| IpAddr::V6(net.addr())))
+        .ok_or_else(|| anyhow::anyhow!("Network interface {} has no IP address", bind))
+}
@@ -142,14 +142,34 @@ impl PluginManager {
             .join("mousebridge")
             .join("plugin_config.json");
 
-        if config_path.exists() {
-            if let Ok(content) = std::fs::read_to_string(config_path) {
-                if let Ok(config) = serde_json::from_str::<PluginConfig>(&content) {
-                    *self.config.lock().await = config;
-                }
+        let mut plugins = self.plugins.lock().await;
+        let mut config = self.config.lock().await;
+        let saved = std::fs::read_to_string(config_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<PluginConfig>(&content).ok());
+        match saved {
+            Some(saved) => *config = saved,
+            // Nothing saved yet: the built-in defaults are what's enabled, and what gets saved
+            // the first time a plugin is toggled
+            None => {
+                config.enabled_plugins = plugins
+                    .values()
+                    .filter(|plugin| plugin.enabled)
+                    .map(|plugin| plugin.name.clone())
+                    .collect();
             }
         }
 
+        for (name, plugin) in plugins.iter_mut() {
+            plugin.enabled = config.enabled_plugins.contains(name);
+        }
+        crate::reconnect::set_enabled(
+            config
+                .enabled_plugins
+                .iter()
+                .any(|name| name == "auto-reconnect"),
+        );
+
         Ok(())
     }
 
@@ -183,7 +203,13 @@ impl PluginManager {
                 config.enabled_plugins.push(plugin_name.clone());
             }
 
+            if plugin_name == "auto-reconnect" {
+                crate::reconnect::set_enabled(true);
+            }
+
             log::info!("Plugin enabled: {}", plugin_name);
+            // save_plugin_config takes the config lock itself
+            drop(config);
             self.save_plugin_config().await?;
         } else {
             return Err(anyhow::anyhow!("Plugin not found: {}", plugin_name));
@@ -209,7 +235,13 @@ impl PluginManager {
                 }
             }
 
+            if plugin_name == "auto-reconnect" {
+                crate::reconnect::set_enabled(false);
+            }
+
             log::info!("Plugin disabled: {}", plugin_name);
+            // save_plugin_config takes the config lock itself
+            drop(config);
             self.save_plugin_config().await?;
         } else {
             return Err(anyhow::anyhow!("Plugin not found: {}", plugin_name));
@@ -269,7 +301,9 @@ impl PluginManager {
             }
             "get_metrics" => {
                 let data = crate::analytics::get_session_data().await?;
-                Ok(serde_json::to_value(data)?)
+                let mut metrics = serde_json::to_value(data)?;
+                metrics["connections"] = serde_json::to_value(crate::traffic::connection_stats())?;
+                Ok(metrics)
             }
             "export_report" => {
                 let report = crate::analytics::get_global_manager().export_data().await?;
@@ -281,14 +315,19 @@ impl PluginManager {
 
     async fn execute_auto_reconnect_action(&self, action: &str, _params: serde_json::Value) -> Result<serde_json::Value> {
         match action {
+            // Toggling the plugin itself keeps its listed state and the saved config in step
             "enable" => {
-                // TODO: Implement auto-reconnect logic
+                self.enable_plugin("auto-reconnect".to_string()).await?;
                 Ok(serde_json::json!({"status": "enabled"}))
             }
             "disable" => {
-                // TODO: Implement auto-reconnect logic
+                self.disable_plugin("auto-reconnect".to_string()).await?;
                 Ok(serde_json::json!({"status": "disabled"}))
             }
+            "status" => {
+                let status = if crate::reconnect::is_enabled() { "enabled" } else { "disabled" };
+                Ok(serde_json::json!({"status": status}))
+            }
             _ => Err(anyhow::anyhow!("Unknown action: {}", action)),
         }
     }
@@ -333,6 +372,10 @@ pub fn get_global_manager() -> &'static PluginManager {
     }
 }
 
+pub async fn load_plugins() -> Result<()> {
+    get_global_manager().load_plugins().await
+}
+
 pub async fn list_available_plugins() -> Result<Vec<String>> {
     get_global_manager().list_available_plugins().await
 }
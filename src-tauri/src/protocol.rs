@@ -0,0 +1,215 @@
+use crate::codec::{Compression, WireFormat};
+use serde::{Deserialize, Serialize};
+
+// Range of wire protocol versions this build can speak. Bump PROTOCOL_VERSION for any change
+// to NetworkMessage that an older peer would misread, and raise MIN_PROTOCOL_VERSION once
+// support for the old behaviour is dropped.
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub keyboard: bool,
+    pub clipboard: bool,
+    pub wheel: bool,
+    pub compression: bool,
+    #[serde(default)]
+    pub relative_motion: bool,
+    // Understands codec.rs; both sides switch to it once the handshake is done
+    #[serde(default)]
+    pub binary_codec: bool,
+    // Algorithms `compression` may use
+    #[serde(default)]
+    pub compression_algorithms: CompressionAlgorithms,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressionAlgorithms {
+    #[serde(default)]
+    pub lz4: bool,
+    #[serde(default)]
+    pub deflate: bool,
+}
+
+impl Capabilities {
+    // What this build is able to send and replay
+    pub fn local() -> Self {
+        Self {
+            keyboard: false,
+            clipboard: false,
+            wheel: true,
+            compression: true,
+            relative_motion: true,
+            binary_codec: true,
+            compression_algorithms: CompressionAlgorithms {
+                lz4: true,
+                deflate: true,
+            },
+        }
+    }
+
+    pub fn intersect(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            keyboard: self.keyboard && other.keyboard,
+            clipboard: self.clipboard && other.clipboard,
+            wheel: self.wheel && other.wheel,
+            compression: self.compression && other.compression,
+            relative_motion: self.relative_motion && other.relative_motion,
+            binary_codec: self.binary_codec && other.binary_codec,
+            compression_algorithms: CompressionAlgorithms {
+                lz4: self.compression_algorithms.lz4 && other.compression_algorithms.lz4,
+                deflate: self.compression_algorithms.deflate
+                    && other.compression_algorithms.deflate,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Negotiated {
+    pub version: u32,
+    pub capabilities: Capabilities,
+}
+
+impl Negotiated {
+    pub fn wire_format(&self) -> WireFormat {
+        WireFormat {
+            binary: self.capabilities.binary_codec,
+            compression: self.compression(),
+        }
+    }
+
+    // Both sides derive the same choice from the intersected capabilities, so it needs no
+    // message of its own. LZ4 wins when both have it: bulk payloads share the link with input.
+    pub fn compression(&self) -> Option<Compression> {
+        let capabilities = &self.capabilities;
+        if !capabilities.compression {
+            None
+        } else if capabilities.compression_algorithms.lz4 {
+            Some(Compression::Lz4)
+        } else if capabilities.compression_algorithms.deflate {
+            Some(Compression::Deflate)
+        } else {
+            None
+        }
+    }
+}
+
+// Picks the highest version both sides support. The error is meant to be shown to the user
+// on the refused side, so it names both ranges.
+pub fn negotiate(
+    peer_min_version: u32,
+    peer_max_version: u32,
+    peer_capabilities: &Capabilities,
+) -> Result<Negotiated, String> {
+    let version = PROTOCOL_VERSION.min(peer_max_version);
+    if peer_min_version > peer_max_version || version < MIN_PROTOCOL_VERSION.max(peer_min_version) {
+        return Err(format!(
+            "Incompatible protocol versions: peer supports {}-{}, this device supports {}-{}",
+            peer_min_version, peer_max_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ));
+    }
+
+    Ok(Negotiated {
+        version,
+        capabilities: Capabilities::local().intersect(peer_capabilities),
+    })
+}
+
+pub fn is_supported(version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+pub fn local_device_name() -> String {
+    hostname::get()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_highest_common_version() {
+        let negotiated = negotiate(
+            MIN_PROTOCOL_VERSION,
+            PROTOCOL_VERSION + 5,
+            &Capabilities::local(),
+        )
+        .unwrap();
+        assert_eq!(negotiated.version, PROTOCOL_VERSION);
+
+        let negotiated = negotiate(0, MIN_PROTOCOL_VERSION, &Capabilities::local()).unwrap();
+        assert_eq!(negotiated.version, MIN_PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn refuses_peers_without_a_common_version() {
+        let newer = negotiate(
+            PROTOCOL_VERSION + 1,
+            PROTOCOL_VERSION + 2,
+            &Capabilities::local(),
+        );
+        let error = newer.unwrap_err();
+        assert!(error.contains(&format!(
+            "{}-{}",
+            PROTOCOL_VERSION + 1,
+            PROTOCOL_VERSION + 2
+        )));
+        assert!(error.contains(&format!("{}-{}", MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)));
+
+        let older = negotiate(0, MIN_PROTOCOL_VERSION - 1, &Capabilities::local());
+        assert!(older.is_err());
+    }
+
+    #[test]
+    fn refuses_an_inverted_range() {
+        assert!(negotiate(
+            PROTOCOL_VERSION,
+            MIN_PROTOCOL_VERSION - 1,
+            &Capabilities::local()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn keeps_only_capabilities_both_sides_have() {
+        let peer = Capabilities {
+            keyboard: true,
+            wheel: true,
+            compression: true,
+            compression_algorithms: CompressionAlgorithms {
+                lz4: false,
+                deflate: true,
+            },
+            ..Capabilities::default()
+        };
+        let negotiated = negotiate(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, &peer).unwrap();
+        let capabilities = negotiated.capabilities;
+
+        // This build has no keyboard support to offer
+        assert!(!capabilities.keyboard);
+        assert!(capabilities.wheel);
+        assert!(!capabilities.relative_motion);
+        assert!(!capabilities.binary_codec);
+        assert!(!capabilities.compression_algorithms.lz4);
+        assert!(capabilities.compression_algorithms.deflate);
+    }
+
+    #[test]
+    fn prefers_lz4_and_falls_back_to_deflate() {
+        let mut peer = Capabilities::local();
+        let negotiated = negotiate(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, &peer).unwrap();
+        assert_eq!(negotiated.compression(), Some(Compression::Lz4));
+
+        peer.compression_algorithms.lz4 = false;
+        let negotiated = negotiate(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, &peer).unwrap();
+        assert_eq!(negotiated.compression(), Some(Compression::Deflate));
+
+        peer.compression = false;
+        let negotiated = negotiate(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, &peer).unwrap();
+        assert_eq!(negotiated.compression(), None);
+        assert_eq!(negotiated.wire_format().compression, None);
+    }
+}
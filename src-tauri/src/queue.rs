@@ -0,0 +1,299 @@
+use crate::{input::MouseEvent, network::NetworkMessage};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+pub const OUTBOUND_QUEUE_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    // Pointer motion: only the latest position matters, so it may be merged or dropped
+    Motion,
+    // Buttons, keys, wheel and everything else: never dropped
+    Reliable,
+    // Clipboard and file payloads: never dropped, but must not hold up input
+    Bulk,
+}
+
+impl Priority {
+    pub fn of(message: &NetworkMessage) -> Self {
+        match message {
+            NetworkMessage::MouseEvent(event) if event.is_motion() => Priority::Motion,
+            NetworkMessage::Clipboard(_) => Priority::Bulk,
+            _ => Priority::Reliable,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub coalesced_motion: u64,
+    pub dropped_motion: u64,
+}
+
+#[derive(Default)]
+struct QueueState {
+    items: VecDeque<NetworkMessage>,
+    // Deltas of dropped relative motion, added to the next relative move so the cursor still
+    // ends up in the same place
+    carry: (i32, i32),
+    stats: QueueStats,
+}
+
+// Per-client outgoing queue. Motion that piles up between sends is merged into one event, so a
+// slow link sees fewer, larger moves instead of a growing backlog. When the queue is full the
+// oldest motion goes first; reliable events are kept even if that means going over capacity.
+pub struct OutboundQueue {
+    capacity: usize,
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+impl OutboundQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(QueueState::default()),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn push(&self, mut message: NetworkMessage) {
+        let mut state = self.state.lock().unwrap();
+
+        if let NetworkMessage::MouseEvent(event) = &mut message {
+            if event.is_relative() {
+                event.x += state.carry.0;
+                event.y += state.carry.1;
+                state.carry = (0, 0);
+            } else if event.is_motion() {
+                state.carry = (0, 0);
+            }
+
+            if event.is_motion() {
+                if let Some(NetworkMessage::MouseEvent(last)) = state.items.back_mut() {
+                    if last.is_motion() {
+                        merge_motion(last, event);
+                        state.stats.coalesced_motion += 1;
+                        return;
+                    }
+                }
+            }
+        }
+
+        if state.items.len() >= self.capacity && !drop_oldest_motion(&mut state) {
+            if let NetworkMessage::MouseEvent(event) = &message {
+                if event.is_motion() {
+                    drop_motion(&mut state, event);
+                    return;
+                }
+            }
+        }
+
+        state.items.push_back(message);
+        state.stats.depth = state.items.len();
+        drop(state);
+        self.notify.notify_one();
+    }
+
+    // Waits for at least one message and takes everything queued
+    pub async fn pop_batch(&self) -> Vec<NetworkMessage> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if !state.items.is_empty() {
+                    state.stats.depth = 0;
+                    return state.items.drain(..).collect();
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+// `later` replaces `earlier`, except that deltas accumulate
+fn merge_motion(earlier: &mut MouseEvent, later: &MouseEvent) {
+    if later.is_relative() {
+        earlier.x += later.x;
+        earlier.y += later.y;
+    } else {
+        *earlier = later.clone();
+    }
+}
+
+fn drop_oldest_motion(state: &mut QueueState) -> bool {
+    let Some(index) = state
+        .items
+        .iter()
+        .position(|message| Priority::of(message) == Priority::Motion)
+    else {
+        return false;
+    };
+
+    if let Some(NetworkMessage::MouseEvent(dropped)) = state.items.remove(index) {
+        drop_motion(state, &dropped);
+    }
+    true
+}
+
+fn drop_motion(state: &mut QueueState, dropped: &MouseEvent) {
+    if dropped.is_relative() {
+        let next = state.items.iter_mut().find_map(|message| match message {
+            NetworkMessage::MouseEvent(event) if event.is_motion() => Some(event),
+            _ => None,
+        });
+        match next {
+            // A later absolute position already covers this move
+            Some(next) if !next.is_relative() => {}
+            Some(next) => {
+                next.x += dropped.x;
+                next.y += dropped.y;
+            }
+            None => {
+                state.carry.0 += dropped.x;
+                state.carry.1 += dropped.y;
+            }
+        }
+    }
+    state.stats.dropped_motion += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Positioning;
+    use std::sync::Arc;
+
+    fn motion(x: i32, y: i32) -> NetworkMessage {
+        NetworkMessage::MouseEvent(MouseEvent {
+            positioning: Positioning::Absolute,
+            ..MouseEvent::relative(x, y)
+        })
+    }
+
+    fn relative(dx: i32, dy: i32) -> NetworkMessage {
+        NetworkMessage::MouseEvent(MouseEvent::relative(dx, dy))
+    }
+
+    fn click(pressed: bool) -> NetworkMessage {
+        NetworkMessage::MouseEvent(MouseEvent {
+            button: Some("left".to_string()),
+            pressed,
+            ..MouseEvent::relative(0, 0)
+        })
+    }
+
+    fn events(batch: Vec<NetworkMessage>) -> Vec<MouseEvent> {
+        batch
+            .into_iter()
+            .map(|message| match message {
+                NetworkMessage::MouseEvent(event) => event,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    async fn drain(queue: &OutboundQueue) -> Vec<MouseEvent> {
+        events(queue.pop_batch().await)
+    }
+
+    #[tokio::test]
+    async fn keeps_only_the_latest_absolute_position() {
+        let queue = OutboundQueue::new(OUTBOUND_QUEUE_CAPACITY);
+        queue.push(motion(1, 1));
+        queue.push(motion(2, 2));
+        queue.push(motion(5, 5));
+
+        let batch = drain(&queue).await;
+        assert_eq!(batch.len(), 1);
+        assert_eq!((batch[0].x, batch[0].y), (5, 5));
+        assert_eq!(queue.stats().coalesced_motion, 2);
+    }
+
+    #[tokio::test]
+    async fn adds_up_relative_deltas() {
+        let queue = OutboundQueue::new(OUTBOUND_QUEUE_CAPACITY);
+        queue.push(relative(1, 2));
+        queue.push(relative(3, 4));
+
+        let batch = drain(&queue).await;
+        assert_eq!(batch.len(), 1);
+        assert!(batch[0].is_relative());
+        assert_eq!((batch[0].x, batch[0].y), (4, 6));
+    }
+
+    #[tokio::test]
+    async fn does_not_merge_motion_across_a_button() {
+        let queue = OutboundQueue::new(OUTBOUND_QUEUE_CAPACITY);
+        queue.push(motion(1, 1));
+        queue.push(click(true));
+        queue.push(motion(2, 2));
+
+        let batch = drain(&queue).await;
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch[1].button.as_deref(), Some("left"));
+        assert_eq!(queue.stats().coalesced_motion, 0);
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_drops_the_oldest_motion_first() {
+        let queue = OutboundQueue::new(2);
+        queue.push(motion(1, 1));
+        queue.push(click(true));
+        queue.push(click(false));
+
+        let batch = drain(&queue).await;
+        assert_eq!(batch.len(), 2);
+        assert!(batch.iter().all(|event| !event.is_motion()));
+        assert_eq!(queue.stats().dropped_motion, 1);
+    }
+
+    #[tokio::test]
+    async fn reliable_events_are_kept_over_capacity() {
+        let queue = OutboundQueue::new(2);
+        queue.push(click(true));
+        queue.push(click(false));
+        queue.push(motion(1, 1));
+        queue.push(click(true));
+
+        let batch = drain(&queue).await;
+        assert_eq!(batch.len(), 3);
+        assert!(batch.iter().all(|event| !event.is_motion()));
+        assert_eq!(queue.stats().dropped_motion, 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_deltas_are_carried_into_the_next_move() {
+        let queue = OutboundQueue::new(2);
+        queue.push(relative(5, 0));
+        queue.push(click(true));
+        queue.push(click(false));
+        assert_eq!(drain(&queue).await.len(), 2);
+
+        queue.push(relative(1, 1));
+        let batch = drain(&queue).await;
+        assert_eq!((batch[0].x, batch[0].y), (6, 1));
+    }
+
+    #[tokio::test]
+    async fn pop_batch_waits_for_a_message() {
+        let queue = Arc::new(OutboundQueue::new(OUTBOUND_QUEUE_CAPACITY));
+        let popped = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.pop_batch().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!popped.is_finished());
+
+        queue.push(click(true));
+        let batch = events(popped.await.unwrap());
+        assert_eq!(batch.len(), 1);
+        assert_eq!(queue.stats().depth, 0);
+    }
+}
@@ -0,0 +1,438 @@
+use crate::{
+    analytics,
+    codec::WireFormat,
+    config::{BucketConfig, RateLimitConfig},
+    network::{NetworkMessage, DEFAULT_MAX_MESSAGE_LEN, MAX_FRAME_LEN},
+    transport::{Connection, TransportCounters, TransportStats},
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// Violations older than this no longer count towards a ban
+const VIOLATION_WINDOW: Duration = Duration::from_secs(60);
+// An address we haven't heard from in this long, and that isn't banned, is forgotten
+const IDLE_PEER: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MessageClass {
+    Handshake,
+    Control,
+    Input,
+    Bulk,
+}
+
+impl MessageClass {
+    fn of(message: &NetworkMessage) -> Self {
+        match message {
+            NetworkMessage::ConnectionRequest { .. } | NetworkMessage::ResumeRequest { .. } => {
+                MessageClass::Handshake
+            }
+            NetworkMessage::MouseEvent(_) | NetworkMessage::Motion { .. } => MessageClass::Input,
+            NetworkMessage::Clipboard(_) => MessageClass::Bulk,
+            _ => MessageClass::Control,
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: &BucketConfig, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn take(&mut self, limit: &BucketConfig, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.updated = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+// Everything we hold against one address. Keyed by IP rather than by connection, so opening
+// more connections doesn't buy a flooder more allowance.
+struct PeerLimits {
+    connections: TokenBucket,
+    handshake: TokenBucket,
+    control: TokenBucket,
+    input: TokenBucket,
+    bulk: TokenBucket,
+    violations: u32,
+    first_violation: Instant,
+    banned_until: Option<Instant>,
+    last_seen: Instant,
+}
+
+impl PeerLimits {
+    fn new(config: &RateLimitConfig, now: Instant) -> Self {
+        Self {
+            connections: TokenBucket::full(&config.connections, now),
+            handshake: TokenBucket::full(&config.handshake, now),
+            control: TokenBucket::full(&config.control, now),
+            input: TokenBucket::full(&config.input, now),
+            bulk: TokenBucket::full(&config.bulk, now),
+            violations: 0,
+            first_violation: now,
+            banned_until: None,
+            last_seen: now,
+        }
+    }
+
+    fn is_banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| until > now)
+    }
+
+    // Counts a limit hit; true if it got the address banned
+    fn violate(&mut self, config: &RateLimitConfig, now: Instant) -> bool {
+        if now.saturating_duration_since(self.first_violation) > VIOLATION_WINDOW {
+            self.violations = 0;
+            self.first_violation = now;
+        }
+        self.violations += 1;
+        if self.violations < config.violations_before_ban {
+            return false;
+        }
+        self.violations = 0;
+        self.banned_until = Some(now + Duration::from_millis(config.ban_ms));
+        true
+    }
+}
+
+// What receive() fails with on a banned peer's connection. The server ends the session
+// instead of parking it for a resume.
+#[derive(Debug)]
+pub struct Banned(pub SocketAddr);
+
+impl fmt::Display for Banned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is banned for flooding", self.0)
+    }
+}
+
+impl std::error::Error for Banned {}
+
+enum Verdict {
+    Allow,
+    // Over a limit: the message is dropped and the connection carries on
+    Drop,
+    // Banned, now or earlier: the connection is closed
+    Ban,
+}
+
+// Server-side flood protection, shared by every connection the server accepts
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    peers: Mutex<HashMap<IpAddr, PeerLimits>>,
+    handshakes: Arc<Semaphore>,
+}
+
+impl RateLimiter {
+    pub fn new(mut config: RateLimitConfig) -> Arc<Self> {
+        // No frame could carry a message past this, so a bigger limit would only pretend to
+        // allow one
+        if config.max_message_bytes > MAX_FRAME_LEN {
+            log::warn!(
+                "max_message_bytes of {} is over the frame limit, using {}",
+                config.max_message_bytes,
+                MAX_FRAME_LEN
+            );
+            config.max_message_bytes = MAX_FRAME_LEN;
+        }
+        let handshakes = if config.enabled {
+            config.max_pending_handshakes
+        } else {
+            Semaphore::MAX_PERMITS
+        };
+        Arc::new(Self {
+            handshakes: Arc::new(Semaphore::new(handshakes)),
+            config,
+            peers: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Decides on a freshly accepted connection: None to refuse it, which has already been
+    // logged. The permit counts it as a pending handshake until dropped.
+    pub fn admit(&self, peer: SocketAddr) -> Option<OwnedSemaphorePermit> {
+        if self.config.enabled {
+            let now = Instant::now();
+            let mut peers = self.peers.lock().unwrap();
+            peers.retain(|_, limits| {
+                limits.is_banned(now) || now.saturating_duration_since(limits.last_seen) < IDLE_PEER
+            });
+            let limits = peers
+                .entry(peer.ip())
+                .or_insert_with(|| PeerLimits::new(&self.config, now));
+            limits.last_seen = now;
+
+            if limits.is_banned(now) {
+                record_limit_hit(peer, "connection from a banned address");
+                return None;
+            }
+            if !limits.connections.take(&self.config.connections, now) {
+                let banned = limits.violate(&self.config, now);
+                record_limit_hit(peer, &Self::reason("too many connections", banned));
+                return None;
+            }
+        }
+
+        let permit = self.handshakes.clone().try_acquire_owned().ok();
+        if permit.is_none() {
+            record_limit_hit(peer, "too many pending handshakes");
+        }
+        permit
+    }
+
+    // For Transport::listen_with_limit: messages over the size limit are refused by the
+    // transport as their length is read, before they take any memory
+    pub fn max_message_len(&self) -> usize {
+        if self.config.enabled {
+            self.config.max_message_bytes
+        } else {
+            DEFAULT_MAX_MESSAGE_LEN
+        }
+    }
+
+    // Puts a connection under this limiter's message rates
+    pub fn wrap(self: &Arc<Self>, connection: Box<dyn Connection>) -> Box<dyn Connection> {
+        if !self.config.enabled {
+            return connection;
+        }
+        Box::new(LimitedConnection {
+            inner: connection,
+            limiter: self.clone(),
+        })
+    }
+
+    fn check(&self, peer: SocketAddr, message: &NetworkMessage) -> Verdict {
+        let now = Instant::now();
+        let mut peers = self.peers.lock().unwrap();
+        let limits = peers
+            .entry(peer.ip())
+            .or_insert_with(|| PeerLimits::new(&self.config, now));
+        limits.last_seen = now;
+        if limits.is_banned(now) {
+            return Verdict::Ban;
+        }
+
+        let class = MessageClass::of(message);
+        let allowed = match class {
+            MessageClass::Handshake => limits.handshake.take(&self.config.handshake, now),
+            MessageClass::Control => limits.control.take(&self.config.control, now),
+            MessageClass::Input => limits.input.take(&self.config.input, now),
+            MessageClass::Bulk => limits.bulk.take(&self.config.bulk, now),
+        };
+        if allowed {
+            return Verdict::Allow;
+        }
+
+        let what = format!("{:?} rate exceeded", class);
+        let banned = limits.violate(&self.config, now);
+        record_limit_hit(peer, &Self::reason(&what, banned));
+        if banned {
+            Verdict::Ban
+        } else {
+            Verdict::Drop
+        }
+    }
+
+    fn reason(what: &str, banned: bool) -> String {
+        if banned {
+            format!("{}, banned for repeated violations", what)
+        } else {
+            what.to_string()
+        }
+    }
+}
+
+// The counter is behind an async lock; spawned so that neither the accept loop nor a
+// cancel-safe receive() waits on it
+fn record_limit_hit(peer: SocketAddr, reason: &str) {
+    log::warn!("Rate limit hit by {}: {}", peer, reason);
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+        runtime.spawn(analytics::record_error());
+    }
+}
+
+struct LimitedConnection {
+    inner: Box<dyn Connection>,
+    limiter: Arc<RateLimiter>,
+}
+
+#[async_trait]
+impl Connection for LimitedConnection {
+    fn peer_addr(&self) -> SocketAddr {
+        self.inner.peer_addr()
+    }
+
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()> {
+        self.inner.send(message).await
+    }
+
+    // Messages over a limit are dropped here, so the session never sees them
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>> {
+        loop {
+            let Some(message) = self.inner.receive().await? else {
+                return Ok(None);
+            };
+            let peer = self.inner.peer_addr();
+            match self.limiter.check(peer, &message) {
+                Verdict::Allow => return Ok(Some(message)),
+                Verdict::Drop => continue,
+                Verdict::Ban => return Err(Banned(peer).into()),
+            }
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.inner.close().await
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.inner.stats()
+    }
+
+    fn counters(&self) -> Arc<TransportCounters> {
+        self.inner.counters()
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.inner.set_wire_format(format);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUCKET: BucketConfig = BucketConfig {
+        per_second: 10.0,
+        burst: 3,
+    };
+
+    fn config() -> RateLimitConfig {
+        RateLimitConfig {
+            control: BUCKET,
+            violations_before_ban: 3,
+            ban_ms: 1000,
+            ..RateLimitConfig::default()
+        }
+    }
+
+    fn peer() -> SocketAddr {
+        "192.0.2.1:5000".parse().unwrap()
+    }
+
+    #[test]
+    fn buckets_allow_a_burst_then_refill_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(&BUCKET, start);
+        for _ in 0..BUCKET.burst {
+            assert!(bucket.take(&BUCKET, start));
+        }
+        assert!(!bucket.take(&BUCKET, start));
+
+        // One token every 100 ms
+        assert!(!bucket.take(&BUCKET, start + Duration::from_millis(50)));
+        assert!(bucket.take(&BUCKET, start + Duration::from_millis(100)));
+        assert!(!bucket.take(&BUCKET, start + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn buckets_never_hold_more_than_a_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(&BUCKET, start);
+        let later = start + Duration::from_secs(60);
+        for _ in 0..BUCKET.burst {
+            assert!(bucket.take(&BUCKET, later));
+        }
+        assert!(!bucket.take(&BUCKET, later));
+    }
+
+    #[test]
+    fn bans_after_repeated_violations_until_the_ban_runs_out() {
+        let config = config();
+        let start = Instant::now();
+        let mut limits = PeerLimits::new(&config, start);
+
+        assert!(!limits.violate(&config, start));
+        assert!(!limits.violate(&config, start));
+        assert!(limits.violate(&config, start));
+        assert!(limits.is_banned(start));
+        assert!(limits.is_banned(start + Duration::from_millis(999)));
+        assert!(!limits.is_banned(start + Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn violations_outside_the_window_are_forgotten() {
+        let config = config();
+        let start = Instant::now();
+        let mut limits = PeerLimits::new(&config, start);
+
+        assert!(!limits.violate(&config, start));
+        assert!(!limits.violate(&config, start));
+        let later = start + VIOLATION_WINDOW + Duration::from_secs(1);
+        assert!(!limits.violate(&config, later));
+        assert!(!limits.violate(&config, later));
+        assert!(!limits.is_banned(later));
+    }
+
+    #[test]
+    fn drops_messages_over_the_rate_then_bans_the_address() {
+        let limiter = RateLimiter::new(config());
+        let ack = NetworkMessage::InputAck { received: 0 };
+        for _ in 0..BUCKET.burst {
+            assert!(matches!(limiter.check(peer(), &ack), Verdict::Allow));
+        }
+        assert!(matches!(limiter.check(peer(), &ack), Verdict::Drop));
+        assert!(matches!(limiter.check(peer(), &ack), Verdict::Drop));
+        assert!(matches!(limiter.check(peer(), &ack), Verdict::Ban));
+
+        // Every connection from the address, new or not
+        let input = NetworkMessage::MouseEvent(crate::input::MouseEvent::relative(1, 1));
+        assert!(matches!(limiter.check(peer(), &input), Verdict::Ban));
+        assert!(limiter.admit(peer()).is_none());
+        let other: SocketAddr = "192.0.2.2:5000".parse().unwrap();
+        assert!(limiter.admit(other).is_some());
+    }
+
+    #[test]
+    fn listeners_only_get_the_size_limit_when_enabled() {
+        assert_eq!(RateLimiter::new(config()).max_message_len(), MAX_FRAME_LEN);
+        let disabled = RateLimiter::new(RateLimitConfig {
+            enabled: false,
+            ..config()
+        });
+        assert_eq!(disabled.max_message_len(), DEFAULT_MAX_MESSAGE_LEN);
+    }
+
+    #[test]
+    fn message_limits_are_capped_at_the_frame_limit() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            max_message_bytes: 4 * MAX_FRAME_LEN,
+            ..config()
+        });
+        assert_eq!(limiter.max_message_len(), MAX_FRAME_LEN);
+
+        let limiter = RateLimiter::new(RateLimitConfig {
+            max_message_bytes: 4096,
+            ..config()
+        });
+        assert_eq!(limiter.max_message_len(), 4096);
+    }
+}
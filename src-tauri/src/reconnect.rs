@@ -0,0 +1,115 @@
+use crate::config::ReconnectConfig;
+use rand::Rng;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Toggled by the auto-reconnect plugin; the per-connection config can still opt out
+static AUTO_RECONNECT_ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn set_enabled(enable: bool) {
+    AUTO_RECONNECT_ENABLED.store(enable, Ordering::SeqCst);
+    log::info!(
+        "Auto-reconnect {}",
+        if enable { "enabled" } else { "disabled" }
+    );
+}
+
+pub fn is_enabled() -> bool {
+    AUTO_RECONNECT_ENABLED.load(Ordering::SeqCst)
+}
+
+// Exponential backoff with equal jitter: each delay is half the exponential step plus a random
+// share of the other half, so a room full of clients doesn't hammer a restarted server in sync.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    config: ReconnectConfig,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(config: ReconnectConfig) -> Self {
+        Self { config, attempt: 0 }
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    // Returns None once max_attempts has been used up
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if let Some(max_attempts) = self.config.max_attempts {
+            if self.attempt >= max_attempts {
+                return None;
+            }
+        }
+        self.attempt += 1;
+
+        let step = self
+            .config
+            .initial_delay_ms
+            .saturating_mul(1u64 << (self.attempt - 1).min(20))
+            .min(self.config.max_delay_ms)
+            .max(1);
+        let half = step / 2;
+        let jitter = rand::thread_rng().gen_range(0..=step - half);
+        Some(Duration::from_millis(half + jitter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(initial_delay_ms: u64, max_delay_ms: u64, max_attempts: Option<u32>) -> Backoff {
+        Backoff::new(ReconnectConfig {
+            initial_delay_ms,
+            max_delay_ms,
+            max_attempts,
+            ..ReconnectConfig::default()
+        })
+    }
+
+    #[test]
+    fn delays_stay_between_half_and_full_step() {
+        let mut backoff = backoff(100, 100_000, None);
+        for attempt in 0..10 {
+            let step = 100u64 << attempt;
+            let delay = backoff.next_delay().unwrap().as_millis() as u64;
+            assert!(
+                (step / 2..=step).contains(&delay),
+                "attempt {}: {} ms outside {}..={}",
+                attempt + 1,
+                delay,
+                step / 2,
+                step
+            );
+        }
+    }
+
+    #[test]
+    fn delays_are_capped_at_max_delay() {
+        let mut backoff = backoff(500, 2000, None);
+        for _ in 0..100 {
+            assert!(backoff.next_delay().unwrap() <= Duration::from_millis(2000));
+        }
+    }
+
+    #[test]
+    fn huge_attempt_counts_do_not_overflow() {
+        let mut backoff = backoff(u64::MAX / 2, u64::MAX, None);
+        for _ in 0..100 {
+            assert!(backoff.next_delay().is_some());
+        }
+    }
+
+    #[test]
+    fn stops_after_max_attempts() {
+        let mut backoff = backoff(10, 1000, Some(3));
+        for attempt in 1..=3 {
+            assert!(backoff.next_delay().is_some());
+            assert_eq!(backoff.attempt(), attempt);
+        }
+        assert_eq!(backoff.next_delay(), None);
+        assert_eq!(backoff.attempt(), 3);
+    }
+}
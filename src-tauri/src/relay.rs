@@ -0,0 +1,304 @@
+use anyhow::{Context, Result};
+use ring::{digest, pbkdf2};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Duration;
+
+// Relay control messages are tiny; anything bigger is not ours
+const MAX_RELAY_MESSAGE_LEN: usize = 4096;
+// A peer that connects but never says which session it wants is dropped after this
+const JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+// Servers waiting for a client in one session; more than this is a misconfigured or hostile peer
+const MAX_WAITING_PER_SESSION: usize = 4;
+// PBKDF2 rounds for the shared secret. Every guess at it from a session ID costs this much.
+const SECRET_ITERATIONS: u32 = 600_000;
+const SECRET_SALT: &[u8] = b"mousebridge-relay-secret";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RelayRole {
+    // Waits at the relay until a client joins the same session
+    Server,
+    Client,
+}
+
+// Length-prefixed JSON like NetworkMessage frames. Once both sides have seen Paired, the
+// relay stops parsing and copies bytes in both directions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RelayMessage {
+    Join { session: String, role: RelayRole },
+    Paired,
+    Error { reason: String },
+}
+
+// The shared secret run through PBKDF2. The session ID and the link keys both come from this
+// rather than from the secret itself, so the relay, or anyone watching it, can't test guesses
+// at a weak secret quickly. Slow on purpose: make it once, and not on an async thread.
+pub struct StretchedSecret([u8; digest::SHA256_OUTPUT_LEN]);
+
+impl StretchedSecret {
+    pub fn new(secret: &str) -> Self {
+        let mut key = [0u8; digest::SHA256_OUTPUT_LEN];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(SECRET_ITERATIONS).unwrap(),
+            SECRET_SALT,
+            secret.as_bytes(),
+            &mut key,
+        );
+        Self(key)
+    }
+
+    // Both peers derive the same ID, so the relay can pair them without ever learning what the
+    // link keys come from
+    pub fn session_id(&self) -> String {
+        let mut input = b"mousebridge-relay-session:".to_vec();
+        input.extend_from_slice(&self.0);
+        let digest = digest::digest(&digest::SHA256, &input);
+        hex::encode(&digest.as_ref()[..16])
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+pub fn session_id(secret: &str) -> String {
+    StretchedSecret::new(secret).session_id()
+}
+
+pub async fn write_relay_message<W>(writer: &mut W, message: &RelayMessage) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let payload = serde_json::to_vec(message)?;
+    writer.write_u32(payload.len() as u32).await?;
+    writer.write_all(&payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub async fn read_relay_message<R>(reader: &mut R) -> Result<RelayMessage>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_RELAY_MESSAGE_LEN {
+        return Err(anyhow::anyhow!("Relay message of {} bytes exceeds limit", len));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+// Dials the relay and waits to be paired. A server waits for as long as it takes; a client
+// gives up after `timeout`. The returned stream carries the peer's bytes untouched.
+pub async fn join(
+    address: &str,
+    session: &str,
+    role: RelayRole,
+    timeout: Duration,
+) -> Result<TcpStream> {
+    let mut stream = tokio::time::timeout(timeout, TcpStream::connect(address))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out connecting to relay {}", address))?
+        .with_context(|| format!("Failed to connect to relay {}", address))?;
+    stream.set_nodelay(true)?;
+
+    write_relay_message(
+        &mut stream,
+        &RelayMessage::Join {
+            session: session.to_string(),
+            role,
+        },
+    )
+    .await?;
+
+    let reply = match role {
+        RelayRole::Server => read_relay_message(&mut stream).await?,
+        RelayRole::Client => tokio::time::timeout(timeout, read_relay_message(&mut stream))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out waiting for relay {}", address))??,
+    };
+    match reply {
+        RelayMessage::Paired => Ok(stream),
+        RelayMessage::Error { reason } => {
+            Err(anyhow::anyhow!("Relay {} refused: {}", address, reason))
+        }
+        other => Err(anyhow::anyhow!("Expected pairing from relay, got {:?}", other)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RelayStats {
+    pub waiting_servers: usize,
+    pub active_pairs: u64,
+    pub total_pairs: u64,
+    pub bytes_forwarded: u64,
+}
+
+#[derive(Default)]
+struct RelayState {
+    waiting: HashMap<String, Vec<TcpStream>>,
+    stats: RelayStats,
+}
+
+pub struct RelayHandle {
+    local_addr: SocketAddr,
+    state: Arc<Mutex<RelayState>>,
+    acceptor: JoinHandle<()>,
+}
+
+impl RelayHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub async fn stats(&self) -> RelayStats {
+        let state = self.state.lock().await;
+        RelayStats {
+            waiting_servers: state.waiting.values().map(Vec::len).sum(),
+            ..state.stats
+        }
+    }
+
+    // Hangs up on every peer: spliced pairs go with the acceptor, which owns their tasks, and
+    // servers still waiting for a client are dropped here
+    pub async fn stop(mut self) -> Result<()> {
+        self.acceptor.abort();
+        let _ = (&mut self.acceptor).await;
+        self.state.lock().await.waiting.clear();
+        Ok(())
+    }
+}
+
+impl Drop for RelayHandle {
+    fn drop(&mut self) {
+        self.acceptor.abort();
+    }
+}
+
+// Pairs a waiting server with each client that names the same session and splices the two
+// sockets together. The peers encrypt end to end, so all the relay ever sees is ciphertext.
+pub async fn start_relay_server(address: SocketAddr) -> Result<RelayHandle> {
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to listen on {}", address))?;
+    let local_addr = listener.local_addr()?;
+    let state = Arc::new(Mutex::new(RelayState::default()));
+    log::info!("Relay listening on {}", local_addr);
+
+    let acceptor_state = state.clone();
+    let acceptor = tokio::spawn(async move {
+        // Dropped with the acceptor when the relay stops, which aborts every peer task
+        let mut peers = JoinSet::new();
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                Some(_) = peers.join_next() => continue,
+            };
+            let (stream, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("Failed to accept relay connection: {}", e);
+                    continue;
+                }
+            };
+            let state = acceptor_state.clone();
+
+            peers.spawn(async move {
+                if let Err(e) = serve_peer(stream, state).await {
+                    log::debug!("Relay peer {} failed: {}", peer, e);
+                }
+            });
+        }
+    });
+
+    Ok(RelayHandle {
+        local_addr,
+        state,
+        acceptor,
+    })
+}
+
+async fn serve_peer(mut stream: TcpStream, state: Arc<Mutex<RelayState>>) -> Result<()> {
+    let _ = stream.set_nodelay(true);
+    let (session, role) =
+        match tokio::time::timeout(JOIN_TIMEOUT, read_relay_message(&mut stream)).await {
+            Ok(Ok(RelayMessage::Join { session, role })) => (session, role),
+            Ok(Ok(other)) => return Err(anyhow::anyhow!("Expected join, got {:?}", other)),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => return Err(anyhow::anyhow!("Timed out waiting for join")),
+        };
+
+    match role {
+        RelayRole::Server => {
+            let mut state = state.lock().await;
+            let waiting = state.waiting.entry(session.clone()).or_default();
+            if waiting.len() >= MAX_WAITING_PER_SESSION {
+                drop(state);
+                write_relay_message(
+                    &mut stream,
+                    &RelayMessage::Error {
+                        reason: "Too many servers waiting in this session".to_string(),
+                    },
+                )
+                .await?;
+                return Ok(());
+            }
+            log::debug!("Server waiting in relay session {}", session);
+            waiting.push(stream);
+            Ok(())
+        }
+        RelayRole::Client => {
+            // Servers that hung up while waiting fail the Paired write and are skipped
+            loop {
+                let server = {
+                    let mut state = state.lock().await;
+                    let server = state.waiting.get_mut(&session).and_then(Vec::pop);
+                    if state.waiting.get(&session).is_some_and(Vec::is_empty) {
+                        state.waiting.remove(&session);
+                    }
+                    server
+                };
+                let Some(mut server) = server else {
+                    write_relay_message(
+                        &mut stream,
+                        &RelayMessage::Error {
+                            reason: "No server is waiting in this session".to_string(),
+                        },
+                    )
+                    .await?;
+                    return Ok(());
+                };
+                if write_relay_message(&mut server, &RelayMessage::Paired)
+                    .await
+                    .is_err()
+                {
+                    continue;
+                }
+                write_relay_message(&mut stream, &RelayMessage::Paired).await?;
+                log::info!("Paired peers in relay session {}", session);
+
+                {
+                    let mut state = state.lock().await;
+                    state.stats.active_pairs += 1;
+                    state.stats.total_pairs += 1;
+                }
+                let copied = tokio::io::copy_bidirectional(&mut server, &mut stream).await;
+                let mut state = state.lock().await;
+                state.stats.active_pairs -= 1;
+                if let Ok((to_client, to_server)) = copied {
+                    state.stats.bytes_forwarded += to_client + to_server;
+                }
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{input::MouseEvent, network::NetworkMessage};
+use std::collections::{HashSet, VecDeque};
+use uuid::Uuid;
+
+// Unacknowledged events kept for replay; a client further behind than this has lost too much
+// for a resume to be exact
+const REPLAY_CAPACITY: usize = 256;
+
+// Events that leave state behind on the client if lost: buttons and wheel. Motion is left out
+// because the next move makes up for a lost one.
+pub fn is_replayable(message: &NetworkMessage) -> bool {
+    matches!(message, NetworkMessage::MouseEvent(event) if is_replayable_event(event))
+}
+
+fn is_replayable_event(event: &MouseEvent) -> bool {
+    !event.is_motion()
+}
+
+pub fn new_token() -> String {
+    Uuid::new_v4().to_string()
+}
+
+// Server side: every replayable event sent in a session, numbered from 1, until the client
+// acknowledges it
+#[derive(Debug, Default)]
+pub struct ReplayBuffer {
+    sent: u64,
+    pending: VecDeque<(u64, NetworkMessage)>,
+}
+
+impl ReplayBuffer {
+    pub fn record(&mut self, message: &NetworkMessage) {
+        if !is_replayable(message) {
+            return;
+        }
+        self.sent += 1;
+        if self.pending.len() >= REPLAY_CAPACITY {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((self.sent, message.clone()));
+    }
+
+    pub fn ack(&mut self, received: u64) {
+        while self
+            .pending
+            .front()
+            .is_some_and(|(seq, _)| *seq <= received)
+        {
+            self.pending.pop_front();
+        }
+    }
+
+    // Everything the client hasn't applied, oldest first. Fails if some of it was already
+    // pushed out of the buffer.
+    pub fn unacked(&mut self, received: u64) -> Result<Vec<NetworkMessage>, String> {
+        self.ack(received);
+        match self.pending.front() {
+            Some((seq, _)) if *seq > received + 1 => Err(format!(
+                "{} events were lost beyond the replay buffer",
+                seq - received - 1
+            )),
+            _ => Ok(self
+                .pending
+                .iter()
+                .map(|(_, message)| message.clone())
+                .collect()),
+        }
+    }
+}
+
+// Client side: what has been applied, carried across reconnects
+#[derive(Debug, Default)]
+pub struct InputTracker {
+    received: u64,
+    acked: u64,
+    pressed: HashSet<String>,
+}
+
+impl InputTracker {
+    pub fn record(&mut self, event: &MouseEvent) {
+        if !is_replayable_event(event) {
+            return;
+        }
+        self.received += 1;
+        if let Some(button) = &event.button {
+            if event.pressed {
+                self.pressed.insert(button.clone());
+            } else {
+                self.pressed.remove(button);
+            }
+        }
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    // The count to acknowledge, if it moved since the last ack
+    pub fn take_ack(&mut self) -> Option<u64> {
+        if self.received == self.acked {
+            return None;
+        }
+        self.acked = self.received;
+        Some(self.received)
+    }
+
+    // Releases for every button still held, for when the session is gone for good. Sent as
+    // zero-length relative moves so the cursor stays put.
+    pub fn release_all(&mut self) -> Vec<MouseEvent> {
+        self.pressed
+            .drain()
+            .map(|button| MouseEvent {
+                button: Some(button),
+                ..MouseEvent::relative(0, 0)
+            })
+            .collect()
+    }
+
+    // A new session numbers its events from scratch
+    pub fn reset(&mut self) {
+        self.received = 0;
+        self.acked = 0;
+    }
+}
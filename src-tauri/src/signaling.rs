@@ -0,0 +1,452 @@
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+// Used by the standalone signaling server when no port is given
+pub const DEFAULT_SIGNALING_PORT: u16 = 4244;
+
+// SDP is a few kilobytes at most; anything bigger is not ours
+const MAX_SIGNAL_LEN: usize = 64 * 1024;
+// A peer that opens the socket but never finishes the upgrade is dropped after this
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignalRole {
+    // The MouseBridge server: stays in its room and answers every offer made there
+    Answerer,
+    // A client: makes one offer and waits for the answer
+    Offerer,
+}
+
+// One JSON message per WebSocket text message. Peers join a room first; offers are forwarded
+// to the room's answerer tagged with a session number that the answer must echo back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SignalMessage {
+    Join {
+        room: String,
+        role: SignalRole,
+    },
+    Offer {
+        // Assigned by the signaling server; offerers leave it at zero
+        #[serde(default)]
+        session: u64,
+        sdp: String,
+        // Where the signaling server saw the offer come from, filled in by the server. Unlike
+        // the candidates in the SDP it isn't the offerer's to choose, so the answerer can rate
+        // limit and ban by it.
+        #[serde(default)]
+        from: Option<SocketAddr>,
+    },
+    Answer {
+        session: u64,
+        sdp: String,
+    },
+    Error {
+        reason: String,
+    },
+}
+
+// Either end of a signaling conversation, whether it crosses a WebSocket or stays in-process
+pub struct SignalLink {
+    tx: mpsc::Sender<SignalMessage>,
+    rx: mpsc::Receiver<SignalMessage>,
+}
+
+impl SignalLink {
+    fn pair() -> (Self, Self) {
+        let (a_tx, a_rx) = mpsc::channel(16);
+        let (b_tx, b_rx) = mpsc::channel(16);
+        (
+            Self { tx: a_tx, rx: b_rx },
+            Self { tx: b_tx, rx: a_rx },
+        )
+    }
+
+    pub async fn join(&self, room: &str, role: SignalRole) -> Result<()> {
+        self.send(SignalMessage::Join {
+            room: room.to_string(),
+            role,
+        })
+        .await
+    }
+
+    pub async fn send(&self, message: SignalMessage) -> Result<()> {
+        self.tx
+            .send(message)
+            .await
+            .map_err(|_| anyhow::anyhow!("Signaling connection closed"))
+    }
+
+    // None once the other end has gone away
+    pub async fn recv(&mut self) -> Option<SignalMessage> {
+        self.rx.recv().await
+    }
+
+    // For answering from several tasks at once
+    pub fn sender(&self) -> mpsc::Sender<SignalMessage> {
+        self.tx.clone()
+    }
+}
+
+#[derive(Default)]
+struct Rooms {
+    answerers: HashMap<String, mpsc::Sender<SignalMessage>>,
+    offerers: HashMap<u64, mpsc::Sender<SignalMessage>>,
+    next_session: u64,
+}
+
+pub struct SignalingHandle {
+    local_addr: SocketAddr,
+    rooms: Arc<Mutex<Rooms>>,
+    acceptor: JoinHandle<()>,
+}
+
+impl SignalingHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // Joins this server from the same process, without a socket in between
+    pub fn connect_local(&self) -> SignalLink {
+        let (local, remote) = SignalLink::pair();
+        let rooms = self.rooms.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_peer(rooms, remote, None).await {
+                log::debug!("Local signaling peer failed: {}", e);
+            }
+        });
+        local
+    }
+
+    pub async fn stop(self) -> Result<()> {
+        self.acceptor.abort();
+        Ok(())
+    }
+}
+
+impl Drop for SignalingHandle {
+    fn drop(&mut self) {
+        self.acceptor.abort();
+    }
+}
+
+// Pairs peers by room and forwards their SDP. It never sees any input: once the offer and
+// answer have crossed, the peers talk to each other directly.
+pub async fn start_signaling_server(address: SocketAddr) -> Result<SignalingHandle> {
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to listen on {}", address))?;
+    let local_addr = listener.local_addr()?;
+    let rooms = Arc::new(Mutex::new(Rooms::default()));
+    log::info!("Signaling server listening on {}", local_addr);
+
+    let acceptor_rooms = rooms.clone();
+    let acceptor = tokio::spawn(async move {
+        // Dropped with the acceptor when the server stops, which aborts every peer task
+        let mut peers = JoinSet::new();
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                Some(_) = peers.join_next() => continue,
+            };
+            let (stream, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("Failed to accept signaling connection: {}", e);
+                    continue;
+                }
+            };
+            let rooms = acceptor_rooms.clone();
+
+            peers.spawn(async move {
+                let upgrade =
+                    tokio_tungstenite::accept_async_with_config(stream, Some(socket_config()));
+                let socket = match tokio::time::timeout(UPGRADE_TIMEOUT, upgrade).await {
+                    Ok(Ok(socket)) => socket,
+                    Ok(Err(e)) => {
+                        log::debug!("Signaling upgrade from {} failed: {}", peer, e);
+                        return;
+                    }
+                    Err(_) => {
+                        log::debug!("Signaling upgrade from {} timed out", peer);
+                        return;
+                    }
+                };
+                if let Err(e) = serve_peer(rooms, bridge_socket(socket, peer), Some(peer)).await {
+                    log::debug!("Signaling peer {} failed: {}", peer, e);
+                }
+            });
+        }
+    });
+
+    Ok(SignalingHandle {
+        local_addr,
+        rooms,
+        acceptor,
+    })
+}
+
+// Connects to a signaling server at host:port
+pub async fn dial(address: &str, timeout: Duration) -> Result<(SignalLink, SocketAddr)> {
+    let stream = tokio::time::timeout(timeout, TcpStream::connect(address))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out connecting to {}", address))?
+        .with_context(|| format!("Failed to connect to {}", address))?;
+    let peer = stream.peer_addr()?;
+
+    let url = format!("ws://{}/", address);
+    let upgrade = tokio_tungstenite::client_async_with_config(url, stream, Some(socket_config()));
+    let (socket, _) = tokio::time::timeout(timeout, upgrade)
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out upgrading connection to {}", address))?
+        .with_context(|| format!("Signaling upgrade with {} failed", address))?;
+
+    Ok((bridge_socket(socket, peer), peer))
+}
+
+// The library refuses an oversized message from its header, before buffering it
+fn socket_config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_SIGNAL_LEN),
+        max_frame_size: Some(MAX_SIGNAL_LEN),
+        ..Default::default()
+    }
+}
+
+// Moves messages between a WebSocket and a SignalLink until either side closes
+fn bridge_socket(socket: WebSocketStream<TcpStream>, peer: SocketAddr) -> SignalLink {
+    let (local, mut remote) = SignalLink::pair();
+    let (mut sink, mut stream) = socket.split();
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                outgoing = remote.recv() => {
+                    let Some(message) = outgoing else { break };
+                    let text = match serde_json::to_string(&message) {
+                        Ok(text) => text,
+                        Err(e) => {
+                            log::warn!("Failed to encode signaling message: {}", e);
+                            continue;
+                        }
+                    };
+                    if sink.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                incoming = stream.next() => {
+                    let payload = match incoming {
+                        Some(Ok(Message::Text(text))) => text.into_bytes(),
+                        Some(Ok(Message::Binary(bytes))) => bytes,
+                        Some(Ok(Message::Close(_))) | None => break,
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => {
+                            log::debug!("Failed to read signaling message from {}: {}", peer, e);
+                            break;
+                        }
+                    };
+                    match serde_json::from_slice(&payload) {
+                        Ok(message) => {
+                            if remote.send(message).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => log::warn!("Dropping malformed signaling message from {}: {}", peer, e),
+                    }
+                }
+            }
+        }
+        let _ = sink.close().await;
+    });
+
+    local
+}
+
+// `peer` is the address of the peer's socket, None for one in this process
+async fn serve_peer(
+    rooms: Arc<Mutex<Rooms>>,
+    mut link: SignalLink,
+    peer: Option<SocketAddr>,
+) -> Result<()> {
+    let (room, role) = match link.recv().await {
+        Some(SignalMessage::Join { room, role }) => (room, role),
+        Some(other) => return Err(anyhow::anyhow!("Expected join, got {:?}", other)),
+        None => return Ok(()),
+    };
+
+    match role {
+        SignalRole::Answerer => {
+            {
+                let mut rooms = rooms.lock().await;
+                // A stale answerer whose connection already dropped gives up its room
+                if rooms
+                    .answerers
+                    .get(&room)
+                    .is_some_and(|answerer| !answerer.is_closed())
+                {
+                    drop(rooms);
+                    link.send(SignalMessage::Error {
+                        reason: format!("Room {} already has a server", room),
+                    })
+                    .await?;
+                    return Ok(());
+                }
+                rooms.answerers.insert(room.clone(), link.sender());
+            }
+
+            while let Some(message) = link.recv().await {
+                match message {
+                    SignalMessage::Answer { session, sdp } => {
+                        let offerer = rooms.lock().await.offerers.get(&session).cloned();
+                        match offerer {
+                            Some(offerer) => {
+                                let _ = offerer.send(SignalMessage::Answer { session, sdp }).await;
+                            }
+                            None => log::debug!("Answer for unknown session {}", session),
+                        }
+                    }
+                    other => log::debug!("Ignoring signaling message: {:?}", other),
+                }
+            }
+
+            let mut rooms = rooms.lock().await;
+            if rooms
+                .answerers
+                .get(&room)
+                .is_some_and(|answerer| answerer.same_channel(&link.tx))
+            {
+                rooms.answerers.remove(&room);
+            }
+        }
+        SignalRole::Offerer => {
+            let session = {
+                let mut rooms = rooms.lock().await;
+                rooms.next_session += 1;
+                let session = rooms.next_session;
+                rooms.offerers.insert(session, link.sender());
+                session
+            };
+
+            while let Some(message) = link.recv().await {
+                match message {
+                    SignalMessage::Offer { sdp, .. } => {
+                        let answerer = rooms.lock().await.answerers.get(&room).cloned();
+                        let forwarded = match answerer {
+                            Some(answerer) => answerer
+                                .send(SignalMessage::Offer {
+                                    session,
+                                    sdp,
+                                    from: peer,
+                                })
+                                .await
+                                .is_ok(),
+                            None => false,
+                        };
+                        if !forwarded {
+                            link.send(SignalMessage::Error {
+                                reason: format!("No server in room {}", room),
+                            })
+                            .await?;
+                        }
+                    }
+                    other => log::debug!("Ignoring signaling message: {:?}", other),
+                }
+            }
+
+            rooms.lock().await.offerers.remove(&session);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn offers_carry_the_address_they_were_signaled_from() {
+        let server = start_signaling_server("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let mut answerer = server.connect_local();
+        answerer.join("room", SignalRole::Answerer).await.unwrap();
+        while !server.rooms.lock().await.answerers.contains_key("room") {
+            tokio::task::yield_now().await;
+        }
+
+        let (offerer, _) = dial(&server.local_addr().to_string(), WAIT).await.unwrap();
+        offerer.join("room", SignalRole::Offerer).await.unwrap();
+        // Whatever the offerer claims is replaced
+        let claimed: SocketAddr = "192.0.2.1:5000".parse().unwrap();
+        offerer
+            .send(SignalMessage::Offer {
+                session: 0,
+                sdp: "sdp".to_string(),
+                from: Some(claimed),
+            })
+            .await
+            .unwrap();
+
+        match tokio::time::timeout(WAIT, answerer.recv()).await.unwrap() {
+            Some(SignalMessage::Offer {
+                session,
+                from: Some(from),
+                ..
+            }) => {
+                assert_eq!(session, 1);
+                assert!(from.ip().is_loopback(), "{}", from);
+                assert_ne!(from.port(), server.local_addr().port());
+            }
+            other => panic!("expected an offer, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn hangs_up_on_oversized_messages() {
+        let server = start_signaling_server("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let (mut offerer, _) = dial(&server.local_addr().to_string(), WAIT).await.unwrap();
+        offerer.join("room", SignalRole::Offerer).await.unwrap();
+        offerer
+            .send(SignalMessage::Offer {
+                session: 0,
+                sdp: "x".repeat(MAX_SIGNAL_LEN),
+                from: None,
+            })
+            .await
+            .unwrap();
+
+        let closed = tokio::time::timeout(WAIT, offerer.recv()).await.unwrap();
+        assert!(closed.is_none(), "{:?}", closed);
+    }
+
+    #[tokio::test]
+    async fn stopping_hangs_up_on_every_peer() {
+        let server = start_signaling_server("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let (mut answerer, _) = dial(&server.local_addr().to_string(), WAIT).await.unwrap();
+        answerer.join("room", SignalRole::Answerer).await.unwrap();
+        while !server.rooms.lock().await.answerers.contains_key("room") {
+            tokio::task::yield_now().await;
+        }
+
+        server.stop().await.unwrap();
+        let closed = tokio::time::timeout(WAIT, answerer.recv()).await.unwrap();
+        assert!(closed.is_none(), "{:?}", closed);
+    }
+}
@@ -0,0 +1,241 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+// Transitions a subscriber can fall behind by before it starts missing them
+const TRANSITION_BACKLOG: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionState {
+    Idle,
+    // Server or relay waiting for peers
+    Listening,
+    // Client opening the transport
+    Connecting,
+    // Transport is up; handshake or resume in progress
+    Handshaking,
+    // Handshake answered by a server that asks its user before accepting a new device. Nothing
+    // enters this yet: servers accept every device.
+    AwaitingApproval,
+    // Server with at least one client, or client with a live session
+    Active,
+    // Client that lost its session and is waiting to retry
+    Reconnecting,
+    Error(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateTransition {
+    pub from: ConnectionState,
+    pub to: ConnectionState,
+    pub at: DateTime<Utc>,
+}
+
+fn is_allowed(from: &ConnectionState, to: &ConnectionState) -> bool {
+    use ConnectionState::*;
+
+    match to {
+        // Whatever runs can be stopped, and anything can fail
+        Idle | Error(_) => true,
+        Listening => matches!(from, Idle | Error(_) | Active),
+        // From Handshaking when the server has forgotten a session we tried to resume, and a
+        // fresh connection is needed for a full handshake
+        Connecting => matches!(from, Idle | Error(_) | Reconnecting | Handshaking),
+        Handshaking => matches!(from, Connecting),
+        AwaitingApproval => matches!(from, Handshaking),
+        // Refusal leaves AwaitingApproval through Error or Idle
+        Active => matches!(from, Listening | Handshaking | AwaitingApproval),
+        // A failed attempt goes back to waiting for the next one
+        Reconnecting => matches!(from, Active | Connecting | Handshaking),
+    }
+}
+
+// The one connection lifecycle of this device, whichever side of it we are on
+pub struct StateMachine {
+    state: Mutex<ConnectionState>,
+    transitions: broadcast::Sender<StateTransition>,
+}
+
+impl StateMachine {
+    pub fn new() -> Arc<Self> {
+        let (transitions, _) = broadcast::channel(TRANSITION_BACKLOG);
+        Arc::new(Self {
+            state: Mutex::new(ConnectionState::Idle),
+            transitions,
+        })
+    }
+
+    pub fn current(&self) -> ConnectionState {
+        self.state.lock().unwrap().clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StateTransition> {
+        self.transitions.subscribe()
+    }
+
+    // Moving to the current state is a no-op. Transitions are sent under the lock, so every
+    // subscriber sees them in the order they happened.
+    pub fn transition(&self, to: ConnectionState) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.apply(&mut state, to)
+    }
+
+    // Transitions only if still in `from`, for changes that would be wrong after anything
+    // else happened in between
+    pub fn transition_from(&self, from: &ConnectionState, to: ConnectionState) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if *state != *from {
+            return Err(anyhow::anyhow!(
+                "Connection state is {:?}, not {:?}",
+                *state,
+                from
+            ));
+        }
+        self.apply(&mut state, to)
+    }
+
+    // Error is reachable from every state, so this can't be refused
+    pub fn fail(&self, error: &anyhow::Error) {
+        let _ = self.transition(ConnectionState::Error(error.to_string()));
+    }
+
+    fn apply(&self, state: &mut ConnectionState, to: ConnectionState) -> Result<()> {
+        if *state == to {
+            return Ok(());
+        }
+        if !is_allowed(state, &to) {
+            return Err(anyhow::anyhow!(
+                "Illegal connection state transition from {:?} to {:?}",
+                *state,
+                to
+            ));
+        }
+
+        let from = std::mem::replace(state, to.clone());
+        log::debug!("Connection state {:?} -> {:?}", from, to);
+        // Nobody subscribed is fine
+        let _ = self.transitions.send(StateTransition {
+            from,
+            to,
+            at: Utc::now(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ConnectionState::*;
+
+    fn all() -> Vec<ConnectionState> {
+        vec![
+            Idle,
+            Listening,
+            Connecting,
+            Handshaking,
+            AwaitingApproval,
+            Active,
+            Reconnecting,
+            Error("lost".to_string()),
+        ]
+    }
+
+    #[test]
+    fn allows_only_the_lifecycle_transitions() {
+        let allowed = [
+            (Idle, Listening),
+            (Idle, Connecting),
+            (Listening, Active),
+            (Connecting, Handshaking),
+            (Connecting, Reconnecting),
+            (Handshaking, Connecting),
+            (Handshaking, AwaitingApproval),
+            (Handshaking, Active),
+            (Handshaking, Reconnecting),
+            (AwaitingApproval, Active),
+            (Active, Listening),
+            (Active, Reconnecting),
+            (Reconnecting, Connecting),
+            (Error("lost".to_string()), Listening),
+            (Error("lost".to_string()), Connecting),
+        ];
+        for from in all() {
+            for to in all() {
+                let expected = matches!(to, Idle | Error(_))
+                    || allowed.iter().any(|(f, t)| *f == from && *t == to);
+                assert_eq!(is_allowed(&from, &to), expected, "{:?} -> {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_illegal_transitions_without_moving() {
+        let states = StateMachine::new();
+        assert!(states.transition(Active).is_err());
+        assert_eq!(states.current(), Idle);
+    }
+
+    #[test]
+    fn transition_from_needs_the_expected_state() {
+        let states = StateMachine::new();
+        states.transition(Connecting).unwrap();
+        states.transition(Handshaking).unwrap();
+        // A session of a server that has since given way to this client
+        assert!(states.transition_from(&Listening, Active).is_err());
+        assert_eq!(states.current(), Handshaking);
+
+        states.transition_from(&Handshaking, Active).unwrap();
+        assert_eq!(states.current(), Active);
+    }
+
+    #[test]
+    fn approval_ends_in_a_session_or_a_refusal() {
+        let states = StateMachine::new();
+        assert!(states.transition(AwaitingApproval).is_err());
+        states.transition(Connecting).unwrap();
+        states.transition(Handshaking).unwrap();
+        states.transition(AwaitingApproval).unwrap();
+        assert!(states.transition(Listening).is_err());
+        states.transition(Active).unwrap();
+
+        let refused = StateMachine::new();
+        refused.transition(Connecting).unwrap();
+        refused.transition(Handshaking).unwrap();
+        refused.transition(AwaitingApproval).unwrap();
+        refused.fail(&anyhow::anyhow!("refused by the server's user"));
+        assert_eq!(
+            refused.current(),
+            Error("refused by the server's user".to_string())
+        );
+
+        let cancelled = StateMachine::new();
+        cancelled.transition(Connecting).unwrap();
+        cancelled.transition(Handshaking).unwrap();
+        cancelled.transition(AwaitingApproval).unwrap();
+        cancelled.transition(Idle).unwrap();
+        assert_eq!(cancelled.current(), Idle);
+    }
+
+    #[test]
+    fn moving_to_the_current_state_is_a_silent_no_op() {
+        let states = StateMachine::new();
+        let mut transitions = states.subscribe();
+        states.transition(Listening).unwrap();
+        states.transition(Listening).unwrap();
+        states.fail(&anyhow::anyhow!("port taken"));
+
+        let seen: Vec<_> = std::iter::from_fn(|| transitions.try_recv().ok())
+            .map(|t| (t.from, t.to))
+            .collect();
+        assert_eq!(
+            seen,
+            [
+                (Idle, Listening),
+                (Listening, Error("port taken".to_string()))
+            ]
+        );
+    }
+}
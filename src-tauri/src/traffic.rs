@@ -0,0 +1,245 @@
+use crate::{
+    analytics,
+    codec::{Compression, WireFormat},
+    network::NetworkMessage,
+    queue::{OutboundQueue, QueueStats},
+    transport::{Connection, MessageCounts, TransportCounters, TransportStats},
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+
+// Traffic is added to the analytics session total in chunks of at least this much, so that
+// busy links don't take the analytics lock for every message
+const ANALYTICS_FLUSH_BYTES: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionSide {
+    // We accepted the connection
+    Server,
+    // We dialed it
+    Client,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionTraffic {
+    pub id: u64,
+    pub side: ConnectionSide,
+    pub transport: String,
+    pub peer: SocketAddr,
+    pub peer_fingerprint: Option<String>,
+    pub connected_ms: u64,
+    pub by_type: BTreeMap<String, MessageCounts>,
+    pub transport_stats: TransportStats,
+    // Share of motion datagrams that never made it, for transports with an unreliable path
+    pub datagram_loss: Option<f64>,
+    // Events sent again to a client that resumed its session
+    pub replayed: u64,
+    // Server side only: the client's outgoing queue
+    pub queue: Option<QueueStats>,
+    // Negotiated algorithm for bulk payloads, if any
+    pub compression: Option<Compression>,
+    pub compressed_messages: u64,
+    // Size before over size after, for every payload big enough to be considered, including
+    // those sent as they were because they wouldn't shrink
+    pub compression_ratio: Option<f64>,
+}
+
+#[derive(Default)]
+struct MeterState {
+    peer_fingerprint: Option<String>,
+    transport_stats: TransportStats,
+    replayed: u64,
+    queue: Option<Arc<OutboundQueue>>,
+    // Wire bytes already added to the analytics session total
+    reported_bytes: u64,
+    // What we send in
+    format: WireFormat,
+}
+
+// Counters for one live connection. The session that owns the connection fills in what only
+// it knows, such as the peer's fingerprint and its queue.
+pub struct ConnectionMeter {
+    id: u64,
+    side: ConnectionSide,
+    transport: &'static str,
+    peer: SocketAddr,
+    opened: Instant,
+    // The connection's own, which size each message as it is framed or unframed
+    counters: Arc<TransportCounters>,
+    state: Mutex<MeterState>,
+}
+
+impl ConnectionMeter {
+    pub fn set_peer_fingerprint(&self, fingerprint: &str) {
+        self.state.lock().unwrap().peer_fingerprint = Some(fingerprint.to_string());
+    }
+
+    pub fn attach_queue(&self, queue: Arc<OutboundQueue>) {
+        self.state.lock().unwrap().queue = Some(queue);
+    }
+
+    pub fn record_replayed(&self, count: usize) {
+        self.state.lock().unwrap().replayed += count as u64;
+    }
+
+    fn set_wire_format(&self, format: WireFormat) {
+        self.state.lock().unwrap().format = format;
+    }
+
+    // Stores the latest transport counters and returns the wire bytes not yet reported to
+    // analytics, once there are at least `threshold` of them
+    fn update_transport(&self, stats: TransportStats, threshold: u64) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        state.transport_stats = stats;
+        let total = stats.bytes_sent + stats.bytes_received;
+        let unreported = total.saturating_sub(state.reported_bytes);
+        if unreported == 0 || unreported < threshold {
+            return None;
+        }
+        state.reported_bytes = total;
+        Some(unreported)
+    }
+
+    pub fn snapshot(&self) -> ConnectionTraffic {
+        let state = self.state.lock().unwrap();
+        let stats = state.transport_stats;
+        let delivered = stats
+            .datagrams_received
+            .saturating_sub(stats.stale_datagrams);
+        let expected = delivered + stats.lost_datagrams;
+
+        ConnectionTraffic {
+            id: self.id,
+            side: self.side,
+            transport: self.transport.to_string(),
+            peer: self.peer,
+            peer_fingerprint: state.peer_fingerprint.clone(),
+            connected_ms: self.opened.elapsed().as_millis() as u64,
+            by_type: self.counters.message_counts(),
+            transport_stats: stats,
+            datagram_loss: (expected > 0).then(|| stats.lost_datagrams as f64 / expected as f64),
+            replayed: state.replayed,
+            queue: state.queue.as_ref().map(|queue| queue.stats()),
+            compression: state.format.compression,
+            compressed_messages: stats.compressed_messages,
+            compression_ratio: (state.format.compression.is_some() && stats.compressed_bytes > 0)
+                .then(|| stats.compressible_bytes as f64 / stats.compressed_bytes as f64),
+        }
+    }
+}
+
+type Registry = Mutex<HashMap<u64, Arc<ConnectionMeter>>>;
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Wraps a freshly opened connection so its traffic is counted. The meter stays listed for as
+// long as the connection is alive.
+pub fn track(
+    connection: Box<dyn Connection>,
+    transport: &'static str,
+    side: ConnectionSide,
+) -> (Box<dyn Connection>, Arc<ConnectionMeter>) {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+    let meter = Arc::new(ConnectionMeter {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        side,
+        transport,
+        peer: connection.peer_addr(),
+        opened: Instant::now(),
+        counters: connection.counters(),
+        state: Mutex::new(MeterState::default()),
+    });
+    registry().lock().unwrap().insert(meter.id, meter.clone());
+
+    let metered = MeteredConnection {
+        inner: connection,
+        meter: meter.clone(),
+    };
+    (Box::new(metered), meter)
+}
+
+// Every live connection, oldest first
+pub fn connection_stats() -> Vec<ConnectionTraffic> {
+    let mut stats: Vec<_> = registry()
+        .lock()
+        .unwrap()
+        .values()
+        .map(|meter| meter.snapshot())
+        .collect();
+    stats.sort_by_key(|traffic| traffic.id);
+    stats
+}
+
+struct MeteredConnection {
+    inner: Box<dyn Connection>,
+    meter: Arc<ConnectionMeter>,
+}
+
+impl MeteredConnection {
+    // Reported from a task of its own: awaiting it here would make receive() lose a message
+    // when cancelled
+    fn refresh(&self, threshold: u64) {
+        let Some(bytes) = self.meter.update_transport(self.inner.stats(), threshold) else {
+            return;
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(analytics::record_data_transfer(bytes));
+        }
+    }
+}
+
+#[async_trait]
+impl Connection for MeteredConnection {
+    fn peer_addr(&self) -> SocketAddr {
+        self.inner.peer_addr()
+    }
+
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()> {
+        self.inner.send(message).await?;
+        self.refresh(ANALYTICS_FLUSH_BYTES);
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>> {
+        let message = self.inner.receive().await?;
+        if message.is_some() {
+            self.refresh(ANALYTICS_FLUSH_BYTES);
+        }
+        Ok(message)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.inner.close().await
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.inner.stats()
+    }
+
+    fn counters(&self) -> Arc<TransportCounters> {
+        self.inner.counters()
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.meter.set_wire_format(format);
+        self.inner.set_wire_format(format);
+    }
+}
+
+impl Drop for MeteredConnection {
+    fn drop(&mut self) {
+        registry().lock().unwrap().remove(&self.meter.id);
+        // Whatever is left below the flush threshold still belongs in the session total
+        self.refresh(0);
+    }
+}
@@ -0,0 +1,343 @@
+use super::{Connection, Listener, MessageSize, Transport, TransportCounters, TransportStats};
+use crate::network::NetworkMessage;
+use crate::queue::Priority;
+use anyhow::Result;
+use async_trait::async_trait;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{Duration, Instant};
+
+// Virtual ports handed out for port 0 and for the client end of each link
+const FIRST_EPHEMERAL_PORT: u16 = 40000;
+const LINK_BUFFER: usize = 64;
+// A message held back for reordering goes out anyway if nothing overtakes it by then
+const REORDER_HOLD: Duration = Duration::from_millis(20);
+
+// What the simulated network does to traffic. Loss and reordering only hit pointer motion by
+// default, the way datagrams get lost and overtaken while a stream keeps everything else intact.
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    pub latency: Duration,
+    // Extra delay picked uniformly from zero to this, per message
+    pub jitter: Duration,
+    // Probability of dropping a message, 0.0 to 1.0
+    pub loss: f64,
+    // Loss and reordering also apply to buttons, wheel, heartbeats and the handshake
+    pub affect_reliable: bool,
+    // Probability of a message being held back and delivered after the next one
+    pub reorder: f64,
+    // Cut each link after this many messages in one direction
+    pub disconnect_after: Option<u64>,
+    // Same seed, same decisions for the same traffic
+    pub seed: u64,
+}
+
+struct MemoryHub {
+    listeners: Mutex<HashMap<u16, mpsc::Sender<MemoryConnection>>>,
+    next_port: AtomicU16,
+    links: AtomicU64,
+    faults: Mutex<FaultConfig>,
+    // Bumped by sever(); every pump watching it gives up its link
+    generation: watch::Sender<u64>,
+}
+
+impl MemoryHub {
+    fn allocate_port(&self) -> u16 {
+        self.next_port.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+// Connects servers and clients living in the same process, with no sockets involved. Clone it
+// and hand the same instance to both ends.
+#[derive(Clone)]
+pub struct MemoryTransport {
+    hub: Arc<MemoryHub>,
+}
+
+impl Default for MemoryTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self::with_faults(FaultConfig::default())
+    }
+
+    pub fn with_faults(faults: FaultConfig) -> Self {
+        Self {
+            hub: Arc::new(MemoryHub {
+                listeners: Mutex::new(HashMap::new()),
+                next_port: AtomicU16::new(FIRST_EPHEMERAL_PORT),
+                links: AtomicU64::new(0),
+                faults: Mutex::new(faults),
+                generation: watch::channel(0).0,
+            }),
+        }
+    }
+
+    // Takes effect for the next message on every link, open or not
+    pub fn set_faults(&self, faults: FaultConfig) {
+        *self.hub.faults.lock().unwrap() = faults;
+    }
+
+    // Drops every open link at once, like pulling the cable. Listeners stay up, so clients
+    // can reconnect.
+    pub fn sever(&self) {
+        self.hub
+            .generation
+            .send_modify(|generation| *generation += 1);
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn listen_with_limit(
+        &self,
+        address: SocketAddr,
+        _max_message_len: usize,
+    ) -> Result<Box<dyn Listener>> {
+        let port = match address.port() {
+            0 => self.hub.allocate_port(),
+            port => port,
+        };
+        let mut listeners = self.hub.listeners.lock().unwrap();
+        if listeners
+            .get(&port)
+            .is_some_and(|pending| !pending.is_closed())
+        {
+            return Err(anyhow::anyhow!("Address {} already in use", port));
+        }
+        let (pending_tx, pending) = mpsc::channel(LINK_BUFFER);
+        listeners.insert(port, pending_tx);
+
+        Ok(Box::new(MemoryListener {
+            hub: self.hub.clone(),
+            local_addr: SocketAddr::new(address.ip(), port),
+            pending,
+        }))
+    }
+
+    async fn connect(&self, address: &str, _timeout: Duration) -> Result<Box<dyn Connection>> {
+        let port: u16 = address
+            .rsplit_once(':')
+            .and_then(|(_, port)| port.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("Invalid address {}", address))?;
+        let pending = self
+            .hub
+            .listeners
+            .lock()
+            .unwrap()
+            .get(&port)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Connection to {} refused", address))?;
+
+        let loopback = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let server_addr = SocketAddr::new(loopback, port);
+        let client_addr = SocketAddr::new(loopback, self.hub.allocate_port());
+        let link = self.hub.links.fetch_add(1, Ordering::Relaxed);
+
+        let (to_server, from_client) = spawn_pump(self.hub.clone(), link * 2);
+        let (to_client, from_server) = spawn_pump(self.hub.clone(), link * 2 + 1);
+
+        pending
+            .send(MemoryConnection::new(client_addr, to_client, from_client))
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection to {} refused", address))?;
+        Ok(Box::new(MemoryConnection::new(
+            server_addr,
+            to_server,
+            from_server,
+        )))
+    }
+}
+
+struct MemoryListener {
+    hub: Arc<MemoryHub>,
+    local_addr: SocketAddr,
+    pending: mpsc::Receiver<MemoryConnection>,
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn accept(&mut self) -> Result<Box<dyn Connection>> {
+        match self.pending.recv().await {
+            Some(connection) => Ok(Box::new(connection)),
+            None => Err(anyhow::anyhow!("Listener on {} closed", self.local_addr)),
+        }
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        self.hub
+            .listeners
+            .lock()
+            .unwrap()
+            .remove(&self.local_addr.port());
+    }
+}
+
+type Outgoing = mpsc::Sender<(Instant, NetworkMessage)>;
+
+struct MemoryConnection {
+    peer: SocketAddr,
+    outgoing: Option<Outgoing>,
+    incoming: mpsc::Receiver<NetworkMessage>,
+    counters: Arc<TransportCounters>,
+}
+
+impl MemoryConnection {
+    fn new(peer: SocketAddr, outgoing: Outgoing, incoming: mpsc::Receiver<NetworkMessage>) -> Self {
+        Self {
+            peer,
+            outgoing: Some(outgoing),
+            incoming,
+            counters: Arc::new(TransportCounters::default()),
+        }
+    }
+}
+
+#[async_trait]
+impl Connection for MemoryConnection {
+    fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()> {
+        let outgoing = self
+            .outgoing
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Connection to {} is closed", self.peer))?;
+        // A full link blocks the sender, the same backpressure a socket buffer gives
+        let permit = outgoing
+            .reserve()
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection to {} was reset", self.peer))?;
+        permit.send((Instant::now(), message.clone()));
+        self.counters
+            .record_sent(message.kind(), json_size(message)?);
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>> {
+        let message = self.incoming.recv().await;
+        if let Some(message) = &message {
+            self.counters
+                .record_received(message.kind(), json_size(message)?);
+        }
+        Ok(message)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.outgoing = None;
+        Ok(())
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+
+    fn counters(&self) -> Arc<TransportCounters> {
+        self.counters.clone()
+    }
+}
+
+// Nothing is encoded on the way through, so messages are sized as the JSON they would be
+fn json_size(message: &NetworkMessage) -> Result<MessageSize> {
+    let payload = serde_json::to_vec(message)?;
+    Ok(MessageSize::new(&payload, payload.len()))
+}
+
+// One direction of a link: applies the fault config to each message on its way through
+fn spawn_pump(hub: Arc<MemoryHub>, direction: u64) -> (Outgoing, mpsc::Receiver<NetworkMessage>) {
+    let (input_tx, mut input) = mpsc::channel::<(Instant, NetworkMessage)>(LINK_BUFFER);
+    let (output, output_rx) = mpsc::channel(LINK_BUFFER);
+    let mut severed = hub.generation.subscribe();
+
+    tokio::spawn(async move {
+        let seed = hub.faults.lock().unwrap().seed;
+        let mut rng = StdRng::seed_from_u64(seed ^ direction.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let mut held: Option<NetworkMessage> = None;
+        let mut last_delivery = Instant::now();
+        let mut delivered: u64 = 0;
+
+        loop {
+            let (sent_at, message) = tokio::select! {
+                received = input.recv() => match received {
+                    Some(received) => received,
+                    None => break,
+                },
+                _ = tokio::time::sleep(REORDER_HOLD), if held.is_some() => {
+                    if let Some(message) = held.take() {
+                        if output.send(message).await.is_err() {
+                            return;
+                        }
+                    }
+                    continue;
+                }
+                _ = severed.changed() => return,
+            };
+
+            let faults = hub.faults.lock().unwrap().clone();
+            let exposed = faults.affect_reliable || Priority::of(&message) == Priority::Motion;
+            if exposed && faults.loss > 0.0 && rng.gen_bool(faults.loss.min(1.0)) {
+                continue;
+            }
+            if exposed
+                && held.is_none()
+                && faults.reorder > 0.0
+                && rng.gen_bool(faults.reorder.min(1.0))
+            {
+                held = Some(message);
+                continue;
+            }
+
+            // Never deliver before the previous message, so only `reorder` changes the order
+            let jitter = if faults.jitter.is_zero() {
+                Duration::ZERO
+            } else {
+                faults.jitter.mul_f64(rng.gen::<f64>())
+            };
+            let deliver_at = (sent_at + faults.latency + jitter).max(last_delivery);
+            last_delivery = deliver_at;
+            tokio::select! {
+                _ = tokio::time::sleep_until(deliver_at) => {}
+                _ = severed.changed() => return,
+            }
+
+            for message in std::iter::once(message).chain(held.take()) {
+                if output.send(message).await.is_err() {
+                    return;
+                }
+                delivered += 1;
+                if faults
+                    .disconnect_after
+                    .is_some_and(|limit| delivered >= limit)
+                {
+                    return;
+                }
+            }
+        }
+
+        if let Some(message) = held {
+            let _ = output.send(message).await;
+        }
+    });
+
+    (input_tx, output_rx)
+}
@@ -0,0 +1,240 @@
+use crate::{
+    codec::{self, WireFormat},
+    config::{ConnectionConfig, Protocol},
+    network::{NetworkMessage, DEFAULT_MAX_MESSAGE_LEN},
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::time::Duration;
+
+mod memory;
+mod quic;
+mod relay;
+mod tcp;
+mod udp;
+mod webrtc;
+mod websocket;
+
+pub use memory::{FaultConfig, MemoryTransport};
+pub use quic::QuicTransport;
+pub use relay::RelayTransport;
+pub use tcp::TcpTransport;
+pub use udp::UdpTransport;
+pub use webrtc::WebRtcTransport;
+pub use websocket::WebSocketTransport;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TransportStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub datagrams_sent: u64,
+    pub datagrams_received: u64,
+    // Datagrams that arrived after a newer one and were discarded
+    pub stale_datagrams: u64,
+    // Motion sequence numbers that never arrived in time
+    pub lost_datagrams: u64,
+    // Packets the transport itself declared lost, whose contents it then sent again; only QUIC
+    // can see these
+    pub lost_packets: u64,
+    // Payloads big enough to be worth compressing, in either direction, and what they came to
+    // before and after. Those that went out as they were because they wouldn't shrink count
+    // too.
+    pub compressible_bytes: u64,
+    pub compressed_bytes: u64,
+    pub compressed_messages: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MessageCounts {
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    // Encoded message size, without framing or encryption overhead
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+// What one message took. `wire` includes the transport's framing and encryption, `encoded` is
+// the payload alone and `plain` the payload before compression.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageSize {
+    pub wire: usize,
+    pub encoded: usize,
+    pub plain: usize,
+}
+
+impl MessageSize {
+    pub fn new(payload: &[u8], wire: usize) -> Self {
+        Self {
+            wire,
+            encoded: payload.len(),
+            plain: codec::plain_len(payload),
+        }
+    }
+}
+
+// Shared between a connection and the tasks that read on its behalf
+#[derive(Debug, Default)]
+pub struct TransportCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    datagrams_sent: AtomicU64,
+    datagrams_received: AtomicU64,
+    stale_datagrams: AtomicU64,
+    lost_datagrams: AtomicU64,
+    compressible_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+    compressed_messages: AtomicU64,
+    by_type: Mutex<BTreeMap<&'static str, MessageCounts>>,
+}
+
+impl TransportCounters {
+    // `kind` is NetworkMessage::kind of what was sent
+    pub fn record_sent(&self, kind: &'static str, size: MessageSize) {
+        self.bytes_sent
+            .fetch_add(size.wire as u64, Ordering::Relaxed);
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.record_message(kind, size, true);
+    }
+
+    pub fn record_received(&self, kind: &'static str, size: MessageSize) {
+        self.bytes_received
+            .fetch_add(size.wire as u64, Ordering::Relaxed);
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.record_message(kind, size, false);
+    }
+
+    // Datagrams only ever carry motion
+    pub fn record_datagram_sent(&self, size: MessageSize) {
+        self.record_sent("Motion", size);
+        self.datagrams_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_datagram_received(&self, size: MessageSize) {
+        self.record_received("Motion", size);
+        self.datagrams_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_message(&self, kind: &'static str, size: MessageSize, sent: bool) {
+        if size.plain >= codec::COMPRESSION_THRESHOLD {
+            self.compressible_bytes
+                .fetch_add(size.plain as u64, Ordering::Relaxed);
+            self.compressed_bytes
+                .fetch_add(size.encoded as u64, Ordering::Relaxed);
+            if size.encoded < size.plain {
+                self.compressed_messages.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let mut by_type = self.by_type.lock().unwrap();
+        let counts = by_type.entry(kind).or_default();
+        if sent {
+            counts.messages_sent += 1;
+            counts.bytes_sent += size.encoded as u64;
+        } else {
+            counts.messages_received += 1;
+            counts.bytes_received += size.encoded as u64;
+        }
+    }
+
+    pub fn record_stale_datagram(&self) {
+        self.stale_datagrams.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Takes the running total from the connection's MotionSequencer
+    pub fn set_lost_datagrams(&self, total: u64) {
+        self.lost_datagrams.store(total, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> TransportStats {
+        TransportStats {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            datagrams_sent: self.datagrams_sent.load(Ordering::Relaxed),
+            datagrams_received: self.datagrams_received.load(Ordering::Relaxed),
+            stale_datagrams: self.stale_datagrams.load(Ordering::Relaxed),
+            lost_datagrams: self.lost_datagrams.load(Ordering::Relaxed),
+            lost_packets: 0,
+            compressible_bytes: self.compressible_bytes.load(Ordering::Relaxed),
+            compressed_bytes: self.compressed_bytes.load(Ordering::Relaxed),
+            compressed_messages: self.compressed_messages.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn message_counts(&self) -> BTreeMap<String, MessageCounts> {
+        self.by_type
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(kind, counts)| (kind.to_string(), *counts))
+            .collect()
+    }
+}
+
+// One implementation per config::Protocol. The server and client only ever talk to these
+// traits, so a new protocol (or a fake in tests) plugs in without touching either.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn listen(&self, address: SocketAddr) -> Result<Box<dyn Listener>> {
+        self.listen_with_limit(address, DEFAULT_MAX_MESSAGE_LEN)
+            .await
+    }
+    // Connections accepted from the listener refuse any message over `max_message_len`,
+    // measured before compression, as soon as its length is read. Streams can't skip what
+    // they refused, so on most transports that also ends the connection. The in-memory
+    // transport encodes nothing and ignores it.
+    async fn listen_with_limit(
+        &self,
+        address: SocketAddr,
+        max_message_len: usize,
+    ) -> Result<Box<dyn Listener>>;
+    async fn connect(&self, address: &str, timeout: Duration) -> Result<Box<dyn Connection>>;
+}
+
+#[async_trait]
+pub trait Listener: Send {
+    fn local_addr(&self) -> SocketAddr;
+    // Must be cancel-safe: the server polls it inside select!
+    async fn accept(&mut self) -> Result<Box<dyn Connection>>;
+}
+
+// Sync because the client's reconnect task holds on to the dropped session across awaits
+#[async_trait]
+pub trait Connection: Send + Sync {
+    fn peer_addr(&self) -> SocketAddr;
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()>;
+    // Ok(None) once the peer has gone away. Must be cancel-safe, like Listener::accept.
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>>;
+    async fn close(&mut self) -> Result<()>;
+    fn stats(&self) -> TransportStats;
+    // The live counters behind stats(), for a meter that outlives any one snapshot
+    fn counters(&self) -> Arc<TransportCounters>;
+    // Called once the handshake has agreed on a format; connections that never encode
+    // messages (the in-memory one) ignore it
+    fn set_wire_format(&mut self, _format: WireFormat) {}
+}
+
+pub fn for_config(config: &ConnectionConfig) -> Box<dyn Transport> {
+    if let Some(relay) = &config.relay {
+        return Box::new(RelayTransport::new(relay.clone()));
+    }
+
+    match config.protocol {
+        Protocol::TCP => Box::new(TcpTransport),
+        Protocol::UDP => Box::new(UdpTransport),
+        Protocol::WebSocket => Box::new(WebSocketTransport),
+        Protocol::QUIC => Box::new(QuicTransport::new(config.quic_cert_sha256.clone())),
+        Protocol::WebRTC => Box::new(WebRtcTransport::new(config.signaling.clone())),
+    }
+}
@@ -0,0 +1,586 @@
+use super::{Connection, Listener, MessageSize, Transport, TransportCounters, TransportStats};
+use crate::codec::WireFormat;
+use crate::network::{
+    decode_datagram, encode_datagram, read_frame_sized, write_frame, MotionSequencer,
+    NetworkMessage, DEFAULT_MAX_MESSAGE_LEN,
+};
+use crate::queue::Priority;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{Endpoint, RecvStream, SendStream, TransportConfig};
+use ring::digest;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+const ALPN: &[u8] = b"mousebridge";
+const SERVER_NAME: &str = "mousebridge";
+// A peer that starts the QUIC handshake but never opens its control stream is dropped after this
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(1);
+// Closing throws away anything still in flight, so close() first waits this long for the peer
+// to have everything sent on the control stream
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+// Bulk payloads waiting for the bulk stream; send() waits once this many are queued
+const BULK_QUEUE: usize = 16;
+
+// Everything rides one QUIC connection: pointer motion as unreliable datagrams, the handshake,
+// heartbeats, buttons and keys on one reliable control stream, and clipboard and file payloads
+// in order on a stream of their own, so a large transfer never stalls input. QUIC follows the
+// client across address changes, so a roaming laptop keeps its session.
+//
+// Server certificates are self-signed, so instead of a CA the client is given the SHA-256 of
+// the one it should see, which the server announces in its discovery beacons.
+#[derive(Clone, Default)]
+pub struct QuicTransport {
+    // Server side: what listeners present, made up front so its hash can be announced
+    certificate: Option<Arc<rcgen::CertifiedKey>>,
+    // Client side: hex SHA-256 of the only server certificate to accept
+    pinned: Option<String>,
+}
+
+impl QuicTransport {
+    pub fn new(pinned: Option<String>) -> Self {
+        Self {
+            certificate: None,
+            pinned,
+        }
+    }
+
+    pub fn server() -> Result<Self> {
+        Ok(Self {
+            certificate: Some(generate_certificate()?),
+            pinned: None,
+        })
+    }
+
+    // What clients need to pin, for a transport made by server()
+    pub fn certificate_sha256(&self) -> Option<String> {
+        self.certificate
+            .as_ref()
+            .map(|certificate| certificate_sha256(certificate.cert.der()))
+    }
+}
+
+// Each server run presents a fresh self-signed certificate
+fn generate_certificate() -> Result<Arc<rcgen::CertifiedKey>> {
+    let certificate = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])?;
+    Ok(Arc::new(certificate))
+}
+
+fn certificate_sha256(certificate: &[u8]) -> String {
+    hex::encode(digest::digest(&digest::SHA256, certificate))
+}
+
+#[async_trait]
+impl Transport for QuicTransport {
+    fn name(&self) -> &'static str {
+        "quic"
+    }
+
+    async fn listen_with_limit(
+        &self,
+        address: SocketAddr,
+        max_message_len: usize,
+    ) -> Result<Box<dyn Listener>> {
+        let certificate = match &self.certificate {
+            Some(certificate) => certificate.clone(),
+            // Only clients told the hash logged here can reach it
+            None => {
+                let certificate = generate_certificate()?;
+                log::info!(
+                    "QUIC certificate SHA-256 is {}",
+                    certificate_sha256(certificate.cert.der())
+                );
+                certificate
+            }
+        };
+        let endpoint = Endpoint::server(server_config(&certificate)?, address)
+            .with_context(|| format!("Failed to listen on {}", address))?;
+        let local_addr = endpoint.local_addr()?;
+        let (accepted_tx, accepted) = mpsc::channel(16);
+
+        Ok(Box::new(QuicListener {
+            local_addr,
+            accepted,
+            acceptor: spawn_acceptor(endpoint.clone(), accepted_tx, max_message_len),
+            endpoint,
+        }))
+    }
+
+    async fn connect(&self, address: &str, timeout: Duration) -> Result<Box<dyn Connection>> {
+        let pinned = self.pinned.clone().ok_or_else(|| {
+            anyhow::anyhow!(
+                "QUIC needs the SHA-256 of the server's certificate, which discovery reports"
+            )
+        })?;
+        let remote = tokio::net::lookup_host(address)
+            .await
+            .with_context(|| format!("Failed to resolve {}", address))?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("{} did not resolve to any address", address))?;
+        let bind: SocketAddr = match remote.ip() {
+            IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let mut endpoint = Endpoint::client(bind)?;
+        endpoint.set_default_client_config(client_config(pinned)?);
+
+        let (connection, send, recv) = tokio::time::timeout(timeout, async {
+            let connection = endpoint.connect(remote, SERVER_NAME)?.await?;
+            let (send, recv) = connection.open_bi().await?;
+            anyhow::Ok((connection, send, recv))
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out connecting to {}", address))?
+        .with_context(|| format!("Failed to connect to {}", address))?;
+
+        Ok(Box::new(QuicConnection::new(
+            connection,
+            send,
+            recv,
+            Some(endpoint),
+            DEFAULT_MAX_MESSAGE_LEN,
+        )))
+    }
+}
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut config = TransportConfig::default();
+    config.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    Arc::new(config)
+}
+
+fn server_config(certificate: &rcgen::CertifiedKey) -> Result<quinn::ServerConfig> {
+    let key = PrivatePkcs8KeyDer::from(certificate.key_pair.serialize_der());
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut crypto = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate.cert.der().clone()], key.into())?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let mut config =
+        quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(crypto)?));
+    config.transport_config(transport_config());
+    config.migration(true);
+    Ok(config)
+}
+
+fn client_config(pinned: String) -> Result<quinn::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier { pinned, provider }))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let mut config = quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto)?));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+// There is no CA to check a self-signed certificate against, so the server's must hash to the
+// pinned value. Handshake signatures are verified as usual.
+#[derive(Debug)]
+struct PinnedVerifier {
+    pinned: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if certificate_sha256(end_entity).eq_ignore_ascii_case(&self.pinned) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+struct QuicListener {
+    local_addr: SocketAddr,
+    accepted: mpsc::Receiver<QuicConnection>,
+    acceptor: JoinHandle<()>,
+    endpoint: Endpoint,
+}
+
+#[async_trait]
+impl Listener for QuicListener {
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn accept(&mut self) -> Result<Box<dyn Connection>> {
+        match self.accepted.recv().await {
+            Some(connection) => Ok(Box::new(connection)),
+            None => Err(anyhow::anyhow!("Listener on {} closed", self.local_addr)),
+        }
+    }
+}
+
+impl Drop for QuicListener {
+    fn drop(&mut self) {
+        self.acceptor.abort();
+        self.endpoint.close(0u32.into(), b"server stopped");
+    }
+}
+
+// Handshakes run on their own tasks so one slow peer can't hold up the others, and so accept()
+// stays cancel-safe
+fn spawn_acceptor(
+    endpoint: Endpoint,
+    accepted: mpsc::Sender<QuicConnection>,
+    max_message_len: usize,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            let accepted = accepted.clone();
+
+            tokio::spawn(async move {
+                let peer = incoming.remote_address();
+                let handshake = async {
+                    let connection = incoming.await?;
+                    let (send, recv) = connection.accept_bi().await?;
+                    anyhow::Ok((connection, send, recv))
+                };
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+                    Ok(Ok((connection, send, recv))) => {
+                        let connection =
+                            QuicConnection::new(connection, send, recv, None, max_message_len);
+                        let _ = accepted.send(connection).await;
+                    }
+                    Ok(Err(e)) => log::debug!("QUIC handshake with {} failed: {}", peer, e),
+                    Err(_) => log::debug!("QUIC handshake with {} timed out", peer),
+                }
+            });
+        }
+    })
+}
+
+struct QuicConnection {
+    connection: quinn::Connection,
+    control: SendStream,
+    incoming: mpsc::Receiver<NetworkMessage>,
+    readers: Vec<JoinHandle<()>>,
+    bulk: mpsc::Sender<(NetworkMessage, WireFormat)>,
+    bulk_writer: JoinHandle<()>,
+    // Why the bulk stream gave up, once it has
+    bulk_failure: Arc<Mutex<Option<String>>>,
+    motion_seq: u64,
+    counters: Arc<TransportCounters>,
+    format: WireFormat,
+    // Client side only: keeps the local endpoint alive for as long as the connection
+    _endpoint: Option<Endpoint>,
+}
+
+impl QuicConnection {
+    fn new(
+        connection: quinn::Connection,
+        control: SendStream,
+        control_recv: RecvStream,
+        endpoint: Option<Endpoint>,
+        max_message_len: usize,
+    ) -> Self {
+        let counters = Arc::new(TransportCounters::default());
+        let (tx, incoming) = mpsc::channel(64);
+        let readers = vec![
+            spawn_control_reader(control_recv, counters.clone(), tx.clone(), max_message_len),
+            spawn_datagram_reader(connection.clone(), counters.clone(), tx.clone()),
+            spawn_bulk_reader(connection.clone(), counters.clone(), tx, max_message_len),
+        ];
+        let bulk_failure = Arc::new(Mutex::new(None));
+        let (bulk, bulk_writer) =
+            spawn_bulk_writer(connection.clone(), counters.clone(), bulk_failure.clone());
+
+        Self {
+            connection,
+            control,
+            incoming,
+            readers,
+            bulk,
+            bulk_writer,
+            bulk_failure,
+            motion_seq: 0,
+            counters,
+            format: WireFormat::default(),
+            _endpoint: endpoint,
+        }
+    }
+
+    // Motion goes out as a datagram unless the path can't carry one that size
+    fn send_motion(&mut self, message: &NetworkMessage) -> Result<bool> {
+        let NetworkMessage::MouseEvent(event) = message else {
+            return Ok(false);
+        };
+        self.motion_seq += 1;
+        let datagram = encode_datagram(
+            &NetworkMessage::Motion {
+                seq: self.motion_seq,
+                event: event.clone(),
+            },
+            self.format,
+        )?;
+        match self.connection.max_datagram_size() {
+            Some(max) if datagram.len() <= max => {}
+            _ => return Ok(false),
+        }
+
+        let size = MessageSize::new(&datagram, datagram.len());
+        match self.connection.send_datagram(datagram.into()) {
+            Ok(()) => self.counters.record_datagram_sent(size),
+            Err(e) => log::debug!("Failed to send motion datagram: {}", e),
+        }
+        Ok(true)
+    }
+
+    // Queued for the bulk writer, so input keeps flowing while it transfers. A payload that
+    // fails to go out is reported by the send after it.
+    async fn send_bulk(&self, message: &NetworkMessage) -> Result<()> {
+        if self.bulk.send((message.clone(), self.format)).await.is_ok() {
+            return Ok(());
+        }
+        let failure = self.bulk_failure.lock().unwrap().clone();
+        Err(anyhow::anyhow!(
+            "Bulk stream to {} failed: {}",
+            self.connection.remote_address(),
+            failure.unwrap_or_else(|| "closed".to_string())
+        ))
+    }
+
+    fn stop_tasks(&mut self) {
+        for reader in &self.readers {
+            reader.abort();
+        }
+        self.bulk_writer.abort();
+    }
+}
+
+#[async_trait]
+impl Connection for QuicConnection {
+    fn peer_addr(&self) -> SocketAddr {
+        // Follows the peer if it migrates to a new address
+        self.connection.remote_address()
+    }
+
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()> {
+        match Priority::of(message) {
+            Priority::Motion if self.send_motion(message)? => Ok(()),
+            Priority::Bulk => self.send_bulk(message).await,
+            _ => {
+                let size = write_frame(&mut self.control, message, self.format).await?;
+                self.counters.record_sent(message.kind(), size);
+                Ok(())
+            }
+        }
+    }
+
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>> {
+        Ok(self.incoming.recv().await)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.stop_tasks();
+        self.incoming.close();
+        if self.control.finish().is_ok() {
+            let _ = tokio::time::timeout(CLOSE_TIMEOUT, self.control.stopped()).await;
+        }
+        self.connection.close(0u32.into(), b"closed");
+        Ok(())
+    }
+
+    fn stats(&self) -> TransportStats {
+        TransportStats {
+            lost_packets: self.connection.stats().path.lost_packets,
+            ..self.counters.snapshot()
+        }
+    }
+
+    fn counters(&self) -> Arc<TransportCounters> {
+        self.counters.clone()
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.format = format;
+    }
+}
+
+impl Drop for QuicConnection {
+    fn drop(&mut self) {
+        self.stop_tasks();
+        self.connection.close(0u32.into(), b"closed");
+    }
+}
+
+fn spawn_control_reader(
+    mut recv: RecvStream,
+    counters: Arc<TransportCounters>,
+    tx: mpsc::Sender<NetworkMessage>,
+    max_message_len: usize,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match read_frame_sized(&mut recv, max_message_len).await {
+                Ok(Some((message, size))) => {
+                    counters.record_received(message.kind(), size);
+                    if tx.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Failed to read frame: {}", e);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+// Motion only ever needs the latest position, so datagrams overtaken by a newer one are dropped
+fn spawn_datagram_reader(
+    connection: quinn::Connection,
+    counters: Arc<TransportCounters>,
+    tx: mpsc::Sender<NetworkMessage>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut sequencer = MotionSequencer::new();
+        while let Ok(datagram) = connection.read_datagram().await {
+            match decode_datagram(&datagram) {
+                Ok(NetworkMessage::Motion { seq, event }) => {
+                    counters.record_datagram_received(MessageSize::new(&datagram, datagram.len()));
+                    if !sequencer.accept(seq) {
+                        counters.record_stale_datagram();
+                        continue;
+                    }
+                    counters.set_lost_datagrams(sequencer.lost());
+                    if tx.send(NetworkMessage::MouseEvent(event)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(other) => log::debug!("Ignoring datagram: {:?}", other),
+                Err(e) => log::debug!("Dropping malformed datagram: {}", e),
+            }
+        }
+    })
+}
+
+// Writes every bulk payload, in order, on one unidirectional stream opened with the first. The
+// first failure ends the task, which closes the queue and fails every send after it.
+fn spawn_bulk_writer(
+    connection: quinn::Connection,
+    counters: Arc<TransportCounters>,
+    failure: Arc<Mutex<Option<String>>>,
+) -> (mpsc::Sender<(NetworkMessage, WireFormat)>, JoinHandle<()>) {
+    let (tx, mut queue) = mpsc::channel::<(NetworkMessage, WireFormat)>(BULK_QUEUE);
+
+    let writer = tokio::spawn(async move {
+        let result = async {
+            let mut stream: Option<SendStream> = None;
+            while let Some((message, format)) = queue.recv().await {
+                let stream = match &mut stream {
+                    Some(stream) => stream,
+                    None => stream.insert(connection.open_uni().await?),
+                };
+                let size = write_frame(stream, &message, format).await?;
+                counters.record_sent(message.kind(), size);
+            }
+            if let Some(mut stream) = stream {
+                stream.finish()?;
+            }
+            anyhow::Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            log::warn!("Failed to send bulk payload: {}", e);
+            *failure.lock().unwrap() = Some(e.to_string());
+        }
+    });
+    (tx, writer)
+}
+
+// Peers keep to one bulk stream, but each stream that arrives is read to its end
+fn spawn_bulk_reader(
+    connection: quinn::Connection,
+    counters: Arc<TransportCounters>,
+    tx: mpsc::Sender<NetworkMessage>,
+    max_message_len: usize,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Ok(mut stream) = connection.accept_uni().await {
+            let counters = counters.clone();
+            let tx = tx.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    match read_frame_sized(&mut stream, max_message_len).await {
+                        Ok(Some((message, size))) => {
+                            counters.record_received(message.kind(), size);
+                            if tx.send(message).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            log::warn!("Failed to read bulk payload: {}", e);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    })
+}
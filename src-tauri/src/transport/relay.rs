@@ -0,0 +1,378 @@
+use super::{Connection, Listener, MessageSize, Transport, TransportCounters, TransportStats};
+use crate::codec::{self, WireFormat};
+use crate::config::{ReconnectConfig, RelayConfig};
+use crate::network::{self, NetworkMessage, DEFAULT_MAX_MESSAGE_LEN, MAX_FRAME_LEN};
+use crate::reconnect::Backoff;
+use crate::relay::{self, RelayRole, StretchedSecret};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, OnceCell};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+const SEED_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+// Both peers must finish the key exchange within this once the relay has paired them
+const KEY_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Reaches the other peer through a relay both can dial out to. Every frame is sealed with
+// ChaCha20-Poly1305 under keys derived from the shared secret and fresh per-connection nonces,
+// so the relay forwards ciphertext it can neither read nor alter. The relay only ever learns a
+// hash of the stretched secret, which it uses to pair the two sides.
+pub struct RelayTransport {
+    config: RelayConfig,
+    // Stretched on first use and kept, as that takes a while
+    secret: OnceCell<Arc<StretchedSecret>>,
+}
+
+impl RelayTransport {
+    pub fn new(config: RelayConfig) -> Self {
+        Self {
+            config,
+            secret: OnceCell::new(),
+        }
+    }
+
+    async fn secret(&self) -> Result<Arc<StretchedSecret>> {
+        if self.config.secret.is_empty() {
+            return Err(anyhow::anyhow!("Relay secret must not be empty"));
+        }
+        self.secret
+            .get_or_try_init(|| async {
+                let secret = self.config.secret.clone();
+                let stretched =
+                    tokio::task::spawn_blocking(move || StretchedSecret::new(&secret)).await?;
+                anyhow::Ok(Arc::new(stretched))
+            })
+            .await
+            .cloned()
+    }
+}
+
+#[async_trait]
+impl Transport for RelayTransport {
+    fn name(&self) -> &'static str {
+        "relay"
+    }
+
+    async fn listen_with_limit(
+        &self,
+        address: SocketAddr,
+        max_message_len: usize,
+    ) -> Result<Box<dyn Listener>> {
+        let secret = self.secret().await?;
+        let (accepted_tx, accepted) = mpsc::channel(16);
+
+        Ok(Box::new(RelayListener {
+            local_addr: address,
+            accepted,
+            waiter: spawn_waiter(
+                self.config.address.clone(),
+                secret,
+                accepted_tx,
+                max_message_len,
+            ),
+        }))
+    }
+
+    async fn connect(&self, _address: &str, timeout: Duration) -> Result<Box<dyn Connection>> {
+        let secret = self.secret().await?;
+        let stream = relay::join(
+            &self.config.address,
+            &secret.session_id(),
+            RelayRole::Client,
+            timeout,
+        )
+        .await?;
+        Ok(Box::new(
+            SealedConnection::establish(
+                stream,
+                &secret,
+                RelayRole::Client,
+                DEFAULT_MAX_MESSAGE_LEN,
+            )
+            .await?,
+        ))
+    }
+}
+
+struct RelayListener {
+    local_addr: SocketAddr,
+    accepted: mpsc::Receiver<SealedConnection>,
+    waiter: JoinHandle<()>,
+}
+
+#[async_trait]
+impl Listener for RelayListener {
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn accept(&mut self) -> Result<Box<dyn Connection>> {
+        match self.accepted.recv().await {
+            Some(connection) => Ok(Box::new(connection)),
+            None => Err(anyhow::anyhow!("Relay listener closed")),
+        }
+    }
+}
+
+impl Drop for RelayListener {
+    fn drop(&mut self) {
+        self.waiter.abort();
+    }
+}
+
+// Keeps one connection parked at the relay; as soon as a client takes it, parks another. A relay
+// that is down or refuses us is retried with backoff.
+fn spawn_waiter(
+    address: String,
+    secret: Arc<StretchedSecret>,
+    accepted: mpsc::Sender<SealedConnection>,
+    max_message_len: usize,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let session = secret.session_id();
+        let mut backoff = Backoff::new(ReconnectConfig::default());
+
+        while !accepted.is_closed() {
+            let paired =
+                relay::join(&address, &session, RelayRole::Server, KEY_EXCHANGE_TIMEOUT).await;
+            let established = match paired {
+                Ok(stream) => {
+                    backoff = Backoff::new(ReconnectConfig::default());
+                    SealedConnection::establish(stream, &secret, RelayRole::Server, max_message_len)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            match established {
+                Ok(connection) => {
+                    let _ = accepted.send(connection).await;
+                }
+                Err(e) => {
+                    let delay = backoff.next_delay().unwrap_or(Duration::from_secs(1));
+                    log::warn!(
+                        "Relay {} unavailable, retrying in {} ms: {}",
+                        address,
+                        delay.as_millis(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    })
+}
+
+struct SealedConnection {
+    writer: OwnedWriteHalf,
+    cipher: FrameCipher,
+    incoming: mpsc::Receiver<NetworkMessage>,
+    peer: SocketAddr,
+    counters: Arc<TransportCounters>,
+    format: WireFormat,
+}
+
+impl SealedConnection {
+    // Each side sends a random seed; the keys for both directions come from the secret and
+    // both seeds, so no two connections ever share a key even with the same secret
+    async fn establish(
+        mut stream: TcpStream,
+        secret: &StretchedSecret,
+        role: RelayRole,
+        max_message_len: usize,
+    ) -> Result<Self> {
+        let peer = stream.peer_addr()?;
+        let mut local_seed = [0u8; SEED_LEN];
+        SystemRandom::new()
+            .fill(&mut local_seed)
+            .map_err(|_| anyhow::anyhow!("No randomness available"))?;
+
+        let mut remote_seed = [0u8; SEED_LEN];
+        tokio::time::timeout(KEY_EXCHANGE_TIMEOUT, async {
+            stream.write_all(&local_seed).await?;
+            stream.flush().await?;
+            stream.read_exact(&mut remote_seed).await?;
+            anyhow::Ok(())
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out exchanging keys through relay {}", peer))?
+        .with_context(|| format!("Key exchange through relay {} failed", peer))?;
+
+        let (client_seed, server_seed) = match role {
+            RelayRole::Client => (local_seed, remote_seed),
+            RelayRole::Server => (remote_seed, local_seed),
+        };
+        let mut salt = Vec::with_capacity(SEED_LEN * 2);
+        salt.extend_from_slice(&client_seed);
+        salt.extend_from_slice(&server_seed);
+        let to_server = derive_key(secret, &salt, b"mousebridge relay client to server")?;
+        let to_client = derive_key(secret, &salt, b"mousebridge relay server to client")?;
+        let (sealing, opening) = match role {
+            RelayRole::Client => (to_server, to_client),
+            RelayRole::Server => (to_client, to_server),
+        };
+
+        let counters = Arc::new(TransportCounters::default());
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            writer,
+            cipher: FrameCipher::new(sealing),
+            incoming: spawn_sealed_reader(
+                reader,
+                FrameCipher::new(opening),
+                counters.clone(),
+                max_message_len,
+            ),
+            peer,
+            counters,
+            format: WireFormat::default(),
+        })
+    }
+}
+
+fn derive_key(secret: &StretchedSecret, salt: &[u8], info: &'static [u8]) -> Result<LessSafeKey> {
+    let info = [info];
+    let prk = Salt::new(HKDF_SHA256, salt).extract(secret.as_bytes());
+    let okm = prk
+        .expand(&info, &CHACHA20_POLY1305)
+        .map_err(|_| anyhow::anyhow!("Key derivation failed"))?;
+    Ok(LessSafeKey::new(UnboundKey::from(okm)))
+}
+
+// One direction of the link. The nonce is a frame counter, so a frame the relay drops,
+// replays or reorders fails to open and ends the connection.
+struct FrameCipher {
+    key: LessSafeKey,
+    counter: u64,
+}
+
+impl FrameCipher {
+    fn new(key: LessSafeKey) -> Self {
+        Self { key, counter: 0 }
+    }
+
+    fn next_nonce(&mut self) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        Nonce::assume_unique_for_key(nonce)
+    }
+
+    fn seal(&mut self, mut payload: Vec<u8>) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce();
+        self.key
+            .seal_in_place_append_tag(nonce, Aad::empty(), &mut payload)
+            .map_err(|_| anyhow::anyhow!("Failed to seal frame"))?;
+        Ok(payload)
+    }
+
+    fn open(&mut self, mut sealed: Vec<u8>) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce();
+        let len = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut sealed)
+            .map_err(|_| anyhow::anyhow!("Frame failed authentication"))?
+            .len();
+        sealed.truncate(len);
+        Ok(sealed)
+    }
+}
+
+#[async_trait]
+impl Connection for SealedConnection {
+    fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()> {
+        let payload = codec::encode(message, self.format)?;
+        if payload.len() > MAX_FRAME_LEN {
+            return Err(anyhow::anyhow!(
+                "Frame of {} bytes exceeds limit",
+                payload.len()
+            ));
+        }
+
+        let mut size = MessageSize::new(&payload, 0);
+        let sealed = self.cipher.seal(payload)?;
+        self.writer.write_u32(sealed.len() as u32).await?;
+        self.writer.write_all(&sealed).await?;
+        self.writer.flush().await?;
+        size.wire = sealed.len() + 4;
+        self.counters.record_sent(message.kind(), size);
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>> {
+        Ok(self.incoming.recv().await)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.incoming.close();
+        // The peer may already be gone, which is what we wanted anyway
+        let _ = self.writer.shutdown().await;
+        Ok(())
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+
+    fn counters(&self) -> Arc<TransportCounters> {
+        self.counters.clone()
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.format = format;
+    }
+}
+
+// Frames are opened on their own task for the same reason TCP frames are decoded on one: a
+// half-read frame must never be torn by a cancelled receive()
+fn spawn_sealed_reader(
+    mut reader: OwnedReadHalf,
+    mut opener: FrameCipher,
+    counters: Arc<TransportCounters>,
+    max_message_len: usize,
+) -> mpsc::Receiver<NetworkMessage> {
+    let (tx, rx) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        let result: Result<()> = async {
+            loop {
+                let len = match reader.read_u32().await {
+                    Ok(len) => len as usize,
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                };
+                network::check_frame_len(len.saturating_sub(TAG_LEN), max_message_len)?;
+
+                let mut sealed = vec![0u8; len];
+                reader.read_exact(&mut sealed).await?;
+                let payload = opener.open(sealed)?;
+                let message = network::decode_frame(&payload, max_message_len)?;
+                counters.record_received(message.kind(), MessageSize::new(&payload, len + 4));
+                if tx.send(message).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        .await;
+
+        if let Err(e) = result {
+            log::warn!("Failed to read relayed frame: {}", e);
+        }
+    });
+
+    rx
+}
@@ -0,0 +1,174 @@
+use super::{Connection, Listener, Transport, TransportCounters, TransportStats};
+use crate::codec::WireFormat;
+use crate::network::{read_frame_sized, write_frame, NetworkMessage, DEFAULT_MAX_MESSAGE_LEN};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+// Length-prefixed frames over a single TCP stream
+pub struct TcpTransport;
+
+#[async_trait]
+impl Transport for TcpTransport {
+    fn name(&self) -> &'static str {
+        "tcp"
+    }
+
+    async fn listen_with_limit(
+        &self,
+        address: SocketAddr,
+        max_message_len: usize,
+    ) -> Result<Box<dyn Listener>> {
+        Ok(Box::new(
+            TcpFrameListener::bind(address, max_message_len).await?,
+        ))
+    }
+
+    async fn connect(&self, address: &str, timeout: Duration) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(TcpConnection::connect(address, timeout).await?))
+    }
+}
+
+pub(super) struct TcpFrameListener {
+    listener: TcpListener,
+    local_addr: SocketAddr,
+    max_message_len: usize,
+}
+
+impl TcpFrameListener {
+    pub(super) async fn bind(address: SocketAddr, max_message_len: usize) -> Result<Self> {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("Failed to listen on {}", address))?;
+        let local_addr = listener.local_addr()?;
+        Ok(Self {
+            listener,
+            local_addr,
+            max_message_len,
+        })
+    }
+
+    pub(super) async fn accept_stream(&mut self) -> Result<TcpConnection> {
+        let (stream, _) = self.listener.accept().await?;
+        TcpConnection::new(stream, self.max_message_len)
+    }
+}
+
+#[async_trait]
+impl Listener for TcpFrameListener {
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn accept(&mut self) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(self.accept_stream().await?))
+    }
+}
+
+pub(super) struct TcpConnection {
+    writer: OwnedWriteHalf,
+    incoming: mpsc::Receiver<NetworkMessage>,
+    peer: SocketAddr,
+    counters: Arc<TransportCounters>,
+    format: WireFormat,
+}
+
+impl TcpConnection {
+    pub(super) async fn connect(address: &str, timeout: Duration) -> Result<Self> {
+        let stream = tokio::time::timeout(timeout, TcpStream::connect(address))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out connecting to {}", address))?
+            .with_context(|| format!("Failed to connect to {}", address))?;
+        Self::new(stream, DEFAULT_MAX_MESSAGE_LEN)
+    }
+
+    pub(super) fn new(stream: TcpStream, max_message_len: usize) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        let peer = stream.peer_addr()?;
+        let counters = Arc::new(TransportCounters::default());
+        let (reader, writer) = stream.into_split();
+
+        Ok(Self {
+            writer,
+            incoming: spawn_frame_reader(reader, counters.clone(), max_message_len),
+            peer,
+            counters,
+            format: WireFormat::default(),
+        })
+    }
+}
+
+#[async_trait]
+impl Connection for TcpConnection {
+    fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()> {
+        let size = write_frame(&mut self.writer, message, self.format).await?;
+        self.counters.record_sent(message.kind(), size);
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>> {
+        Ok(self.incoming.recv().await)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.incoming.close();
+        // The peer may already be gone, which is what we wanted anyway
+        let _ = self.writer.shutdown().await;
+        Ok(())
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+
+    fn counters(&self) -> Arc<TransportCounters> {
+        self.counters.clone()
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.format = format;
+    }
+}
+
+// read_frame is not cancel-safe, so frames are decoded on their own task and handed over a
+// channel that can be used inside select! without tearing a frame in half.
+fn spawn_frame_reader<R>(
+    mut reader: R,
+    counters: Arc<TransportCounters>,
+    max_message_len: usize,
+) -> mpsc::Receiver<NetworkMessage>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        loop {
+            match read_frame_sized(&mut reader, max_message_len).await {
+                Ok(Some((message, size))) => {
+                    counters.record_received(message.kind(), size);
+                    if tx.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Failed to read frame: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    rx
+}
@@ -0,0 +1,522 @@
+use super::tcp::{TcpConnection, TcpFrameListener};
+use super::{Connection, Listener, MessageSize, Transport, TransportCounters, TransportStats};
+use crate::codec::WireFormat;
+use crate::input::{MouseEvent, Positioning};
+use crate::network::{
+    decode_datagram, encode_datagram, MotionSequencer, NetworkMessage, MAX_DATAGRAM_LEN,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+// Clients re-announce their UDP address this often so NAT bindings and the server's
+// peer table stay fresh even when no motion is flowing
+const UDP_REGISTER_INTERVAL_MS: u64 = 1000;
+
+// Framed TCP for the handshake, heartbeats, buttons and wheel, with pointer motion riding on
+// UDP datagrams next to it. Motion falls back to TCP until the peer's datagram address is known.
+pub struct UdpTransport;
+
+#[async_trait]
+impl Transport for UdpTransport {
+    fn name(&self) -> &'static str {
+        "udp"
+    }
+
+    async fn listen_with_limit(
+        &self,
+        address: SocketAddr,
+        max_message_len: usize,
+    ) -> Result<Box<dyn Listener>> {
+        Ok(Box::new(UdpListener::bind(address, max_message_len).await?))
+    }
+
+    async fn connect(&self, address: &str, timeout: Duration) -> Result<Box<dyn Connection>> {
+        let frames = TcpConnection::connect(address, timeout).await?;
+        let remote_address = frames.peer_addr();
+        let bind_address: SocketAddr = if remote_address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind_address).await?;
+        socket.connect(remote_address).await?;
+
+        let socket = Arc::new(socket);
+        let counters = frames.counters();
+        let (motion_tx, motion) = mpsc::channel(64);
+        let reader = spawn_motion_reader(socket.clone(), counters.clone(), motion_tx);
+        Ok(Box::new(UdpClientConnection {
+            frames,
+            socket,
+            motion,
+            reader,
+            register: None,
+            counters,
+        }))
+    }
+}
+
+// Keyed by client fingerprint
+type PeerTable = Arc<Mutex<HashMap<String, UdpPeer>>>;
+
+struct UdpPeer {
+    // Where the client's TCP session comes from. The fingerprint goes over the wire in the
+    // clear, so registrations from any other IP are ignored; otherwise anyone who saw it could
+    // point the client's motion at themselves.
+    session: SocketAddr,
+    // Where its datagrams come from, once it has registered
+    datagrams: Option<SocketAddr>,
+}
+
+struct UdpListener {
+    frames: TcpFrameListener,
+    socket: Arc<UdpSocket>,
+    peers: PeerTable,
+    registrations: JoinHandle<()>,
+}
+
+impl UdpListener {
+    async fn bind(address: SocketAddr, max_message_len: usize) -> Result<Self> {
+        let frames = TcpFrameListener::bind(address, max_message_len).await?;
+        let local_addr = frames.local_addr();
+        let socket = UdpSocket::bind(local_addr)
+            .await
+            .with_context(|| format!("Failed to bind UDP port {}", local_addr.port()))?;
+        log::info!(
+            "Server accepting motion datagrams on {}",
+            socket.local_addr()?
+        );
+
+        let socket = Arc::new(socket);
+        let peers = Arc::new(Mutex::new(HashMap::new()));
+        let registrations = spawn_registrations(socket.clone(), peers.clone());
+        Ok(Self {
+            frames,
+            socket,
+            peers,
+            registrations,
+        })
+    }
+
+    async fn accept_session(&mut self) -> Result<UdpServerConnection> {
+        let frames = self.frames.accept_stream().await?;
+        let counters = frames.counters();
+        Ok(UdpServerConnection {
+            frames,
+            socket: self.socket.clone(),
+            peers: self.peers.clone(),
+            fingerprint: None,
+            motion_seq: 0,
+            position: None,
+            counters,
+            format: WireFormat::default(),
+        })
+    }
+}
+
+#[async_trait]
+impl Listener for UdpListener {
+    fn local_addr(&self) -> SocketAddr {
+        self.frames.local_addr()
+    }
+
+    async fn accept(&mut self) -> Result<Box<dyn Connection>> {
+        Ok(Box::new(self.accept_session().await?))
+    }
+}
+
+impl Drop for UdpListener {
+    fn drop(&mut self) {
+        self.registrations.abort();
+    }
+}
+
+fn spawn_registrations(socket: Arc<UdpSocket>, peers: PeerTable) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut datagram = vec![0u8; MAX_DATAGRAM_LEN];
+        loop {
+            match socket.recv_from(&mut datagram).await {
+                Ok((len, from)) => match decode_datagram(&datagram[..len]) {
+                    Ok(NetworkMessage::UdpRegister { fingerprint }) => {
+                        match peers.lock().unwrap().get_mut(&fingerprint) {
+                            Some(peer) if peer.session.ip() == from.ip() => {
+                                peer.datagrams = Some(from);
+                            }
+                            _ => log::debug!(
+                                "Ignoring UDP registration for {} from {}",
+                                fingerprint,
+                                from
+                            ),
+                        }
+                    }
+                    Ok(other) => log::debug!("Ignoring datagram from {}: {:?}", from, other),
+                    Err(e) => log::debug!("Dropping malformed datagram from {}: {}", from, e),
+                },
+                Err(e) => log::warn!("Failed to receive datagram: {}", e),
+            }
+        }
+    })
+}
+
+struct UdpServerConnection {
+    frames: TcpConnection,
+    socket: Arc<UdpSocket>,
+    peers: PeerTable,
+    // Learned from the client's connection or resume request; its datagrams register under this
+    fingerprint: Option<String>,
+    motion_seq: u64,
+    // Where the client's pointer is, going by the events sent so far. Motion goes out as a
+    // datagram with this absolute position, so a lost one can't leave the pointer off by its
+    // delta; relative motion before anything placed the pointer stays on TCP.
+    position: Option<(i32, i32)>,
+    counters: Arc<TransportCounters>,
+    format: WireFormat,
+}
+
+impl UdpServerConnection {
+    fn datagram_target(&self) -> Option<SocketAddr> {
+        let fingerprint = self.fingerprint.as_ref()?;
+        self.peers.lock().unwrap().get(fingerprint)?.datagrams
+    }
+}
+
+#[async_trait]
+impl Connection for UdpServerConnection {
+    fn peer_addr(&self) -> SocketAddr {
+        self.frames.peer_addr()
+    }
+
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()> {
+        if let NetworkMessage::MouseEvent(event) = message {
+            self.position = match (event.positioning, self.position) {
+                (Positioning::Absolute, _) => Some((event.x, event.y)),
+                (Positioning::Relative, Some((x, y))) => Some((x + event.x, y + event.y)),
+                (Positioning::Relative, None) => None,
+            };
+            if let (true, Some((x, y)), Some(target)) =
+                (event.is_motion(), self.position, self.datagram_target())
+            {
+                self.motion_seq += 1;
+                let datagram = encode_datagram(
+                    &NetworkMessage::Motion {
+                        seq: self.motion_seq,
+                        event: MouseEvent {
+                            x,
+                            y,
+                            positioning: Positioning::Absolute,
+                            ..event.clone()
+                        },
+                    },
+                    self.format,
+                )?;
+                match self.socket.send_to(&datagram, target).await {
+                    Ok(_) => self
+                        .counters
+                        .record_datagram_sent(MessageSize::new(&datagram, datagram.len())),
+                    Err(e) => log::debug!("Failed to send motion to {}: {}", target, e),
+                }
+                return Ok(());
+            }
+        }
+        self.frames.send(message).await
+    }
+
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>> {
+        let message = self.frames.receive().await?;
+        match &message {
+            Some(NetworkMessage::ConnectionRequest { fingerprint, .. })
+            | Some(NetworkMessage::ResumeRequest { fingerprint, .. }) => {
+                self.fingerprint = Some(fingerprint.clone());
+                self.peers.lock().unwrap().insert(
+                    fingerprint.clone(),
+                    UdpPeer {
+                        session: self.frames.peer_addr(),
+                        datagrams: None,
+                    },
+                );
+            }
+            _ => {}
+        }
+        Ok(message)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.frames.close().await
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+
+    fn counters(&self) -> Arc<TransportCounters> {
+        self.counters.clone()
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.format = format;
+        self.frames.set_wire_format(format);
+    }
+}
+
+impl Drop for UdpServerConnection {
+    fn drop(&mut self) {
+        // A resumed connection may already have taken the entry over
+        if let Some(fingerprint) = &self.fingerprint {
+            let mut peers = self.peers.lock().unwrap();
+            if peers
+                .get(fingerprint)
+                .is_some_and(|peer| peer.session == self.frames.peer_addr())
+            {
+                peers.remove(fingerprint);
+            }
+        }
+    }
+}
+
+struct UdpClientConnection {
+    frames: TcpConnection,
+    socket: Arc<UdpSocket>,
+    // Sequenced motion decoded from datagrams by the reader task
+    motion: mpsc::Receiver<NetworkMessage>,
+    reader: JoinHandle<()>,
+    register: Option<JoinHandle<()>>,
+    counters: Arc<TransportCounters>,
+}
+
+#[async_trait]
+impl Connection for UdpClientConnection {
+    fn peer_addr(&self) -> SocketAddr {
+        self.frames.peer_addr()
+    }
+
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()> {
+        // Our own connection or resume request tells us which fingerprint to register
+        // datagrams under
+        if let NetworkMessage::ConnectionRequest { fingerprint, .. }
+        | NetworkMessage::ResumeRequest { fingerprint, .. } = message
+        {
+            if self.register.is_none() {
+                self.register = Some(spawn_register(self.socket.clone(), fingerprint)?);
+            }
+        }
+        self.frames.send(message).await
+    }
+
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>> {
+        tokio::select! {
+            message = self.frames.receive() => message,
+            Some(message) = self.motion.recv() => Ok(Some(message)),
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.stop_tasks();
+        self.frames.close().await
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+
+    fn counters(&self) -> Arc<TransportCounters> {
+        self.counters.clone()
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.frames.set_wire_format(format);
+    }
+}
+
+impl UdpClientConnection {
+    fn stop_tasks(&mut self) {
+        self.reader.abort();
+        if let Some(register) = self.register.take() {
+            register.abort();
+        }
+    }
+}
+
+impl Drop for UdpClientConnection {
+    fn drop(&mut self) {
+        self.stop_tasks();
+    }
+}
+
+fn spawn_register(socket: Arc<UdpSocket>, fingerprint: &str) -> Result<JoinHandle<()>> {
+    // Sent before the handshake settles on a format, so always JSON
+    let register = encode_datagram(
+        &NetworkMessage::UdpRegister {
+            fingerprint: fingerprint.to_string(),
+        },
+        WireFormat::default(),
+    )?;
+
+    Ok(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(UDP_REGISTER_INTERVAL_MS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = socket.send(&register).await {
+                log::debug!("Failed to register UDP address: {}", e);
+            }
+        }
+    }))
+}
+
+// Motion only ever needs the latest position, so datagrams overtaken by a newer one are dropped
+fn spawn_motion_reader(
+    socket: Arc<UdpSocket>,
+    counters: Arc<TransportCounters>,
+    motion: mpsc::Sender<NetworkMessage>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut sequencer = MotionSequencer::new();
+        let mut datagram = vec![0u8; MAX_DATAGRAM_LEN];
+        loop {
+            match socket.recv(&mut datagram).await {
+                Ok(len) => match decode_datagram(&datagram[..len]) {
+                    Ok(NetworkMessage::Motion { seq, event }) => {
+                        counters.record_datagram_received(MessageSize::new(&datagram[..len], len));
+                        if !sequencer.accept(seq) {
+                            counters.record_stale_datagram();
+                            continue;
+                        }
+                        counters.set_lost_datagrams(sequencer.lost());
+                        if motion
+                            .send(NetworkMessage::MouseEvent(event))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Ok(other) => log::debug!("Ignoring datagram from server: {:?}", other),
+                    Err(e) => log::debug!("Dropping malformed datagram: {}", e),
+                },
+                // ICMP port unreachable surfaces here while the server is restarting
+                Err(e) => log::debug!("Failed to receive datagram: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::MotionSequencer;
+    use tokio::time::timeout;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn motion(positioning: Positioning, x: i32, y: i32) -> NetworkMessage {
+        NetworkMessage::MouseEvent(MouseEvent {
+            positioning,
+            ..MouseEvent::relative(x, y)
+        })
+    }
+
+    // The listener is handed back too, since dropping it stops the registrations
+    async fn registered() -> (UdpListener, Box<dyn Connection>, UdpServerConnection) {
+        let mut listener = UdpListener::bind("127.0.0.1:0".parse().unwrap(), 4096)
+            .await
+            .unwrap();
+        let mut client = UdpTransport
+            .connect(&listener.local_addr().to_string(), WAIT)
+            .await
+            .unwrap();
+        client
+            .send(&NetworkMessage::ResumeRequest {
+                token: "token".to_string(),
+                received: 0,
+                fingerprint: "laptop".to_string(),
+            })
+            .await
+            .unwrap();
+        let mut server = timeout(WAIT, listener.accept_session())
+            .await
+            .unwrap()
+            .unwrap();
+        timeout(WAIT, server.receive()).await.unwrap().unwrap();
+
+        timeout(WAIT, async {
+            while server.datagram_target().is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("client never registered its datagram address");
+        (listener, client, server)
+    }
+
+    async fn next_event(connection: &mut dyn Connection) -> MouseEvent {
+        match timeout(WAIT, connection.receive()).await.unwrap().unwrap() {
+            Some(NetworkMessage::MouseEvent(event)) => event,
+            other => panic!("expected a mouse event, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sequencer_discards_stale_and_repeated_motion() {
+        let mut sequencer = MotionSequencer::new();
+        assert!(sequencer.accept(1));
+        assert!(sequencer.accept(4));
+        assert_eq!(sequencer.lost(), 2);
+
+        // 3 was overtaken by 4, and 4 arrived twice
+        assert!(!sequencer.accept(3));
+        assert!(!sequencer.accept(4));
+        assert!(sequencer.accept(5));
+        assert_eq!(sequencer.dropped(), 2);
+        assert_eq!(sequencer.lost(), 2);
+    }
+
+    #[tokio::test]
+    async fn sends_motion_as_absolute_datagrams() {
+        let (_listener, mut client, mut server) = registered().await;
+        server
+            .send(&motion(Positioning::Absolute, 100, 100))
+            .await
+            .unwrap();
+        server
+            .send(&motion(Positioning::Relative, 5, -3))
+            .await
+            .unwrap();
+        assert_eq!(server.stats().datagrams_sent, 2);
+
+        next_event(client.as_mut()).await;
+        let moved = next_event(client.as_mut()).await;
+        assert_eq!(moved.positioning, Positioning::Absolute);
+        assert_eq!((moved.x, moved.y), (105, 97));
+        assert_eq!(client.stats().datagrams_received, 2);
+    }
+
+    #[tokio::test]
+    async fn keeps_buttons_and_unplaced_motion_on_tcp() {
+        let (_listener, mut client, mut server) = registered().await;
+        // Nothing has placed the pointer yet, so there is no position to send
+        server
+            .send(&motion(Positioning::Relative, 5, -3))
+            .await
+            .unwrap();
+        server
+            .send(&NetworkMessage::MouseEvent(MouseEvent {
+                button: Some("left".to_string()),
+                pressed: true,
+                ..MouseEvent::relative(0, 0)
+            }))
+            .await
+            .unwrap();
+        assert_eq!(server.stats().datagrams_sent, 0);
+
+        let moved = next_event(client.as_mut()).await;
+        assert!(moved.is_relative());
+        assert!(next_event(client.as_mut()).await.pressed);
+        assert_eq!(client.stats().datagrams_received, 0);
+    }
+}
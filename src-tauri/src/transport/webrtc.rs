@@ -0,0 +1,737 @@
+use super::{Connection, Listener, MessageSize, Transport, TransportCounters, TransportStats};
+use crate::codec::WireFormat;
+use crate::config::SignalingConfig;
+use crate::network::{
+    self, decode_datagram, encode_datagram, write_frame, MotionSequencer, NetworkMessage,
+    DEFAULT_MAX_MESSAGE_LEN,
+};
+use crate::queue::Priority;
+use crate::signaling::{self, SignalLink, SignalMessage, SignalRole, SignalingHandle};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::api::{APIBuilder, API};
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+const CONTROL_LABEL: &str = "control";
+const MOTION_LABEL: &str = "motion";
+const BULK_LABEL: &str = "bulk";
+// Reliable channels carry framed bytes split into messages no bigger than this, which every
+// SCTP implementation accepts
+const CHUNK_LEN: usize = 16 * 1024;
+// Offer, answer, ICE and DTLS together must finish within this once signaling has started
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Bulk frames waiting for the bulk channel; send() waits once this many are queued
+const BULK_QUEUE: usize = 16;
+
+// Three data channels on one DTLS-encrypted peer connection: pointer motion on an unordered
+// channel with no retransmits, the handshake, heartbeats, buttons and keys on an ordered
+// reliable one, and clipboard payloads on a reliable channel of their own so they never hold up
+// input. SDP is exchanged through a signaling server, by default one the listener runs on its
+// own port; after that the peers talk directly.
+pub struct WebRtcTransport {
+    signaling: SignalingConfig,
+}
+
+impl WebRtcTransport {
+    pub fn new(signaling: SignalingConfig) -> Self {
+        Self { signaling }
+    }
+
+    fn api(&self) -> API {
+        let mut settings = SettingEngine::default();
+        // Lets two peers on the same machine find each other
+        settings.set_include_loopback_candidate(true);
+        APIBuilder::new().with_setting_engine(settings).build()
+    }
+
+    fn rtc_config(&self) -> RTCConfiguration {
+        RTCConfiguration {
+            ice_servers: if self.signaling.stun_servers.is_empty() {
+                Vec::new()
+            } else {
+                vec![RTCIceServer {
+                    urls: self.signaling.stun_servers.clone(),
+                    ..Default::default()
+                }]
+            },
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for WebRtcTransport {
+    fn name(&self) -> &'static str {
+        "webrtc"
+    }
+
+    async fn listen_with_limit(
+        &self,
+        address: SocketAddr,
+        max_message_len: usize,
+    ) -> Result<Box<dyn Listener>> {
+        let (server, link, local_addr) = match &self.signaling.server {
+            Some(remote) => {
+                let (link, _) = signaling::dial(remote, HANDSHAKE_TIMEOUT).await?;
+                (None, link, address)
+            }
+            None => {
+                let server = signaling::start_signaling_server(address).await?;
+                let link = server.connect_local();
+                let local_addr = server.local_addr();
+                (Some(server), link, local_addr)
+            }
+        };
+        link.join(&self.signaling.room, SignalRole::Answerer).await?;
+
+        let (accepted_tx, accepted) = mpsc::channel(16);
+        Ok(Box::new(WebRtcListener {
+            local_addr,
+            accepted,
+            answerer: spawn_answerer(
+                Arc::new(self.api()),
+                self.rtc_config(),
+                link,
+                accepted_tx,
+                max_message_len,
+            ),
+            _signaling: server,
+        }))
+    }
+
+    async fn connect(&self, address: &str, timeout: Duration) -> Result<Box<dyn Connection>> {
+        let signaling_address = self.signaling.server.as_deref().unwrap_or(address);
+        let (mut link, signaling_peer) = signaling::dial(signaling_address, timeout).await?;
+        link.join(&self.signaling.room, SignalRole::Offerer).await?;
+
+        let peer_connection = Arc::new(self.api().new_peer_connection(self.rtc_config()).await?);
+        let (inbound, incoming) = Inbound::new(DEFAULT_MAX_MESSAGE_LEN);
+        let mut opened = Vec::new();
+        let control = peer_connection
+            .create_data_channel(CONTROL_LABEL, None)
+            .await?;
+        opened.push(inbound.attach(&control));
+        let motion = peer_connection
+            .create_data_channel(
+                MOTION_LABEL,
+                Some(RTCDataChannelInit {
+                    ordered: Some(false),
+                    max_retransmits: Some(0),
+                    ..Default::default()
+                }),
+            )
+            .await?;
+        opened.push(inbound.attach(&motion));
+        let bulk = peer_connection.create_data_channel(BULK_LABEL, None).await?;
+        opened.push(inbound.attach(&bulk));
+
+        let negotiate = async {
+            let offer = peer_connection.create_offer(None).await?;
+            let local_sdp = describe(&peer_connection, offer).await?;
+            link.send(SignalMessage::Offer {
+                session: 0,
+                sdp: local_sdp,
+                from: None,
+            })
+            .await?;
+
+            let remote_sdp = loop {
+                match link.recv().await {
+                    Some(SignalMessage::Answer { sdp, .. }) => break sdp,
+                    Some(SignalMessage::Error { reason }) => {
+                        return Err(anyhow::anyhow!("Signaling failed: {}", reason))
+                    }
+                    Some(other) => log::debug!("Ignoring signaling message: {:?}", other),
+                    None => {
+                        return Err(anyhow::anyhow!(
+                            "{} closed the signaling connection",
+                            signaling_address
+                        ))
+                    }
+                }
+            };
+            peer_connection
+                .set_remote_description(RTCSessionDescription::answer(remote_sdp.clone())?)
+                .await?;
+            for open in opened {
+                open.await?;
+            }
+            anyhow::Ok(remote_sdp)
+        };
+
+        let remote_sdp = match tokio::time::timeout(timeout.max(HANDSHAKE_TIMEOUT), negotiate).await
+        {
+            Ok(Ok(remote_sdp)) => remote_sdp,
+            Ok(Err(e)) => {
+                let _ = peer_connection.close().await;
+                return Err(e);
+            }
+            Err(_) => {
+                let _ = peer_connection.close().await;
+                return Err(anyhow::anyhow!(
+                    "Timed out negotiating a WebRTC connection with {}",
+                    address
+                ));
+            }
+        };
+
+        let peer = candidate_address(&remote_sdp).unwrap_or(signaling_peer);
+        Ok(Box::new(WebRtcConnection::new(
+            peer_connection,
+            Channels {
+                control,
+                motion,
+                bulk,
+            },
+            inbound,
+            incoming,
+            peer,
+        )))
+    }
+}
+
+// Waits for ICE gathering so the SDP carries every candidate and no trickling is needed
+async fn describe(
+    peer_connection: &RTCPeerConnection,
+    description: RTCSessionDescription,
+) -> Result<String> {
+    let mut gathered = peer_connection.gathering_complete_promise().await;
+    peer_connection.set_local_description(description).await?;
+    let _ = gathered.recv().await;
+    peer_connection
+        .local_description()
+        .await
+        .map(|description| description.sdp)
+        .ok_or_else(|| anyhow::anyhow!("No local session description"))
+}
+
+// The highest-priority UDP candidate the peer offered, which on one LAN is the address it ends
+// up talking from. The peer writes its own SDP, so this only names the server in a client's
+// logs and link stats; servers go by the address the offer was signaled from.
+fn candidate_address(sdp: &str) -> Option<SocketAddr> {
+    sdp.lines()
+        .filter_map(|line| line.strip_prefix("a=candidate:"))
+        .filter_map(|candidate| {
+            // foundation component transport priority address port "typ" type ...
+            let fields: Vec<&str> = candidate.split_whitespace().collect();
+            if fields.len() < 6 || !fields[2].eq_ignore_ascii_case("udp") {
+                return None;
+            }
+            let priority: u32 = fields[3].parse().ok()?;
+            let ip: IpAddr = fields[4].parse().ok()?;
+            let port: u16 = fields[5].parse().ok()?;
+            Some((priority, SocketAddr::new(ip, port)))
+        })
+        .max_by_key(|(priority, _)| *priority)
+        .map(|(_, address)| address)
+}
+
+struct WebRtcListener {
+    local_addr: SocketAddr,
+    accepted: mpsc::Receiver<WebRtcConnection>,
+    answerer: JoinHandle<()>,
+    // Embedded signaling server; None when offers come through a standalone one
+    _signaling: Option<SignalingHandle>,
+}
+
+#[async_trait]
+impl Listener for WebRtcListener {
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn accept(&mut self) -> Result<Box<dyn Connection>> {
+        match self.accepted.recv().await {
+            Some(connection) => Ok(Box::new(connection)),
+            None => Err(anyhow::anyhow!("Listener on {} closed", self.local_addr)),
+        }
+    }
+}
+
+impl Drop for WebRtcListener {
+    fn drop(&mut self) {
+        self.answerer.abort();
+    }
+}
+
+// Each offer is answered on its own task so one slow peer can't hold up the others, and so
+// accept() stays cancel-safe
+fn spawn_answerer(
+    api: Arc<API>,
+    rtc_config: RTCConfiguration,
+    mut link: SignalLink,
+    accepted: mpsc::Sender<WebRtcConnection>,
+    max_message_len: usize,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(message) = link.recv().await {
+            let (session, sdp, peer) = match message {
+                SignalMessage::Offer {
+                    session,
+                    sdp,
+                    from: Some(from),
+                } => (session, sdp, from),
+                SignalMessage::Offer { session, .. } => {
+                    log::warn!("Ignoring offer {} with no source address", session);
+                    continue;
+                }
+                SignalMessage::Error { reason } => {
+                    log::warn!("Signaling server refused us: {}", reason);
+                    break;
+                }
+                other => {
+                    log::debug!("Ignoring signaling message: {:?}", other);
+                    continue;
+                }
+            };
+            let api = api.clone();
+            let rtc_config = rtc_config.clone();
+            let answers = link.sender();
+            let accepted = accepted.clone();
+
+            tokio::spawn(async move {
+                let answer = answer_offer(
+                    &api,
+                    rtc_config,
+                    session,
+                    sdp,
+                    peer,
+                    answers,
+                    max_message_len,
+                );
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, answer).await {
+                    Ok(Ok(connection)) => {
+                        let _ = accepted.send(connection).await;
+                    }
+                    Ok(Err(e)) => log::debug!("WebRTC session {} failed: {}", session, e),
+                    Err(_) => log::debug!("WebRTC session {} timed out", session),
+                }
+            });
+        }
+        log::info!("Signaling connection closed, no longer accepting WebRTC peers");
+    })
+}
+
+async fn answer_offer(
+    api: &API,
+    rtc_config: RTCConfiguration,
+    session: u64,
+    remote_sdp: String,
+    peer: SocketAddr,
+    answers: mpsc::Sender<SignalMessage>,
+    max_message_len: usize,
+) -> Result<WebRtcConnection> {
+    let peer_connection = Arc::new(api.new_peer_connection(rtc_config).await?);
+    let (inbound, incoming) = Inbound::new(max_message_len);
+
+    // Channels are opened by the offerer; handlers go on as each one is announced
+    let (channels_tx, mut channels_rx) = mpsc::channel(3);
+    let channel_inbound = inbound.clone();
+    peer_connection.on_data_channel(Box::new(move |channel: Arc<RTCDataChannel>| {
+        let opened = channel_inbound.attach(&channel);
+        let channels_tx = channels_tx.clone();
+        Box::pin(async move {
+            let _ = channels_tx.send((channel, opened)).await;
+        })
+    }));
+
+    let result = async {
+        peer_connection
+            .set_remote_description(RTCSessionDescription::offer(remote_sdp)?)
+            .await?;
+        let answer = peer_connection.create_answer(None).await?;
+        let local_sdp = describe(&peer_connection, answer).await?;
+        answers
+            .send(SignalMessage::Answer {
+                session,
+                sdp: local_sdp,
+            })
+            .await
+            .map_err(|_| anyhow::anyhow!("Signaling connection closed"))?;
+
+        let (mut control, mut motion, mut bulk) = (None, None, None);
+        while control.is_none() || motion.is_none() || bulk.is_none() {
+            let (channel, opened): (Arc<RTCDataChannel>, oneshot::Receiver<()>) = channels_rx
+                .recv()
+                .await
+                .ok_or_else(|| anyhow::anyhow!("Peer connection closed"))?;
+            opened.await?;
+            match channel.label() {
+                CONTROL_LABEL => control = Some(channel),
+                MOTION_LABEL => motion = Some(channel),
+                BULK_LABEL => bulk = Some(channel),
+                label => log::debug!("Ignoring data channel {}", label),
+            }
+        }
+        anyhow::Ok(Channels {
+            control: control.unwrap(),
+            motion: motion.unwrap(),
+            bulk: bulk.unwrap(),
+        })
+    }
+    .await;
+
+    match result {
+        Ok(channels) => Ok(WebRtcConnection::new(
+            peer_connection,
+            channels,
+            inbound,
+            incoming,
+            peer,
+        )),
+        Err(e) => {
+            let _ = peer_connection.close().await;
+            Err(e)
+        }
+    }
+}
+
+struct Channels {
+    control: Arc<RTCDataChannel>,
+    motion: Arc<RTCDataChannel>,
+    bulk: Arc<RTCDataChannel>,
+}
+
+// Everything the data channel callbacks feed, shared by all three channels
+#[derive(Clone)]
+struct Inbound {
+    tx: mpsc::Sender<NetworkMessage>,
+    counters: Arc<TransportCounters>,
+    closed: Arc<watch::Sender<bool>>,
+    sequencer: Arc<Mutex<MotionSequencer>>,
+    max_message_len: usize,
+}
+
+impl Inbound {
+    fn new(max_message_len: usize) -> (Self, mpsc::Receiver<NetworkMessage>) {
+        let (tx, incoming) = mpsc::channel(64);
+        let inbound = Self {
+            tx,
+            counters: Arc::new(TransportCounters::default()),
+            closed: Arc::new(watch::channel(false).0),
+            sequencer: Arc::new(Mutex::new(MotionSequencer::new())),
+            max_message_len,
+        };
+        (inbound, incoming)
+    }
+
+    // Installs the message handlers; the returned receiver fires once the channel is open
+    fn attach(&self, channel: &Arc<RTCDataChannel>) -> oneshot::Receiver<()> {
+        let (opened_tx, opened) = oneshot::channel();
+        channel.on_open(Box::new(move || {
+            let _ = opened_tx.send(());
+            Box::pin(async {})
+        }));
+
+        let closed = self.closed.clone();
+        if channel.label() == CONTROL_LABEL {
+            channel.on_close(Box::new(move || {
+                closed.send_replace(true);
+                Box::pin(async {})
+            }));
+        }
+
+        let inbound = self.clone();
+        let motion = channel.label() == MOTION_LABEL;
+        let frames = Arc::new(Mutex::new(FrameBuffer::default()));
+        channel.on_message(Box::new(move |message: DataChannelMessage| {
+            let inbound = inbound.clone();
+            let frames = frames.clone();
+            Box::pin(async move {
+                if motion {
+                    inbound.receive_motion(&message.data).await;
+                } else {
+                    inbound.receive_chunk(&frames, &message.data).await;
+                }
+            })
+        }));
+
+        opened
+    }
+
+    // Motion only ever needs the latest position, so anything overtaken by a newer one is dropped
+    async fn receive_motion(&self, datagram: &[u8]) {
+        match decode_datagram(datagram) {
+            Ok(NetworkMessage::Motion { seq, event }) => {
+                self.counters
+                    .record_datagram_received(MessageSize::new(datagram, datagram.len()));
+                let mut sequencer = self.sequencer.lock().await;
+                if !sequencer.accept(seq) {
+                    self.counters.record_stale_datagram();
+                    return;
+                }
+                self.counters.set_lost_datagrams(sequencer.lost());
+                drop(sequencer);
+                let _ = self.tx.send(NetworkMessage::MouseEvent(event)).await;
+            }
+            Ok(other) => log::debug!("Ignoring motion message: {:?}", other),
+            Err(e) => log::debug!("Dropping malformed motion message: {}", e),
+        }
+    }
+
+    async fn receive_chunk(&self, frames: &Mutex<FrameBuffer>, chunk: &[u8]) {
+        let mut frames = frames.lock().await;
+        frames.extend(chunk);
+        loop {
+            match frames.next_frame(self.max_message_len) {
+                Ok(Some((message, size))) => {
+                    self.counters.record_received(message.kind(), size);
+                    let _ = self.tx.send(message).await;
+                }
+                Ok(None) => break,
+                // A reliable channel that loses framing can't recover, so end the connection
+                Err(e) => {
+                    log::warn!("Failed to read frame: {}", e);
+                    self.closed.send_replace(true);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Reassembles length-prefixed frames from chunks, the same framing the TCP transport uses
+#[derive(Default)]
+struct FrameBuffer {
+    buffer: Vec<u8>,
+}
+
+impl FrameBuffer {
+    fn extend(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    fn next_frame(
+        &mut self,
+        max_message_len: usize,
+    ) -> Result<Option<(NetworkMessage, MessageSize)>> {
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([
+            self.buffer[0],
+            self.buffer[1],
+            self.buffer[2],
+            self.buffer[3],
+        ]) as usize;
+        network::check_frame_len(len, max_message_len)?;
+        if self.buffer.len() < len + 4 {
+            return Ok(None);
+        }
+
+        let payload = &self.buffer[4..len + 4];
+        let size = MessageSize::new(payload, len + 4);
+        let message = network::decode_frame(payload, max_message_len);
+        self.buffer.drain(..len + 4);
+        Ok(Some((message?, size)))
+    }
+}
+
+struct WebRtcConnection {
+    peer_connection: Arc<RTCPeerConnection>,
+    channels: Channels,
+    incoming: mpsc::Receiver<NetworkMessage>,
+    closed: watch::Receiver<bool>,
+    peer: SocketAddr,
+    bulk: mpsc::Sender<BulkFrame>,
+    bulk_writer: JoinHandle<()>,
+    // Why the bulk channel gave up, once it has
+    bulk_failure: Arc<Mutex<Option<String>>>,
+    motion_seq: u64,
+    counters: Arc<TransportCounters>,
+    format: WireFormat,
+}
+
+impl WebRtcConnection {
+    fn new(
+        peer_connection: Arc<RTCPeerConnection>,
+        channels: Channels,
+        inbound: Inbound,
+        incoming: mpsc::Receiver<NetworkMessage>,
+        peer: SocketAddr,
+    ) -> Self {
+        let closed = inbound.closed.subscribe();
+        let state_closed = inbound.closed.clone();
+        peer_connection.on_peer_connection_state_change(Box::new(
+            move |state: RTCPeerConnectionState| {
+                if matches!(
+                    state,
+                    RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed
+                ) {
+                    state_closed.send_replace(true);
+                }
+                Box::pin(async {})
+            },
+        ));
+        let bulk_failure = Arc::new(Mutex::new(None));
+        let (bulk, bulk_writer) = spawn_bulk_writer(
+            channels.bulk.clone(),
+            inbound.counters.clone(),
+            bulk_failure.clone(),
+        );
+
+        Self {
+            peer_connection,
+            channels,
+            incoming,
+            closed,
+            peer,
+            bulk,
+            bulk_writer,
+            bulk_failure,
+            motion_seq: 0,
+            counters: inbound.counters,
+            format: WireFormat::default(),
+        }
+    }
+
+    async fn send_motion(&mut self, message: &NetworkMessage) -> Result<bool> {
+        let NetworkMessage::MouseEvent(event) = message else {
+            return Ok(false);
+        };
+        self.motion_seq += 1;
+        let datagram = encode_datagram(
+            &NetworkMessage::Motion {
+                seq: self.motion_seq,
+                event: event.clone(),
+            },
+            self.format,
+        )?;
+
+        let size = MessageSize::new(&datagram, datagram.len());
+        match self.channels.motion.send(&Bytes::from(datagram)).await {
+            Ok(_) => self.counters.record_datagram_sent(size),
+            Err(e) => log::debug!("Failed to send motion message: {}", e),
+        }
+        Ok(true)
+    }
+
+    // Queued for the bulk writer, so input keeps flowing while it transfers. A frame that
+    // fails to go out is reported by the send after it.
+    async fn send_bulk(&self, frame: BulkFrame) -> Result<()> {
+        if self.bulk.send(frame).await.is_ok() {
+            return Ok(());
+        }
+        let failure = self.bulk_failure.lock().await.clone();
+        Err(anyhow::anyhow!(
+            "Bulk channel to {} failed: {}",
+            self.peer,
+            failure.unwrap_or_else(|| "closed".to_string())
+        ))
+    }
+}
+
+// A framed payload waiting for the bulk writer, with what to count once it has gone out
+type BulkFrame = (Vec<u8>, &'static str, MessageSize);
+
+// Sends bulk frames one after another, so the chunks of two payloads never interleave on the
+// channel. The first failure ends the task, which closes the queue and fails every send after it.
+fn spawn_bulk_writer(
+    channel: Arc<RTCDataChannel>,
+    counters: Arc<TransportCounters>,
+    failure: Arc<Mutex<Option<String>>>,
+) -> (mpsc::Sender<BulkFrame>, JoinHandle<()>) {
+    let (tx, mut queue) = mpsc::channel::<BulkFrame>(BULK_QUEUE);
+
+    let writer = tokio::spawn(async move {
+        while let Some((frame, kind, size)) = queue.recv().await {
+            if let Err(e) = send_chunked(&channel, frame).await {
+                log::warn!("Failed to send bulk payload: {}", e);
+                *failure.lock().await = Some(e.to_string());
+                break;
+            }
+            counters.record_sent(kind, size);
+        }
+    });
+    (tx, writer)
+}
+
+async fn send_chunked(channel: &RTCDataChannel, frame: Vec<u8>) -> Result<()> {
+    let frame = Bytes::from(frame);
+    for start in (0..frame.len()).step_by(CHUNK_LEN) {
+        let end = (start + CHUNK_LEN).min(frame.len());
+        channel.send(&frame.slice(start..end)).await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Connection for WebRtcConnection {
+    fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()> {
+        let priority = Priority::of(message);
+        if priority == Priority::Motion && self.send_motion(message).await? {
+            return Ok(());
+        }
+
+        let mut frame = Vec::new();
+        let size = write_frame(&mut frame, message, self.format).await?;
+        if priority == Priority::Bulk {
+            self.send_bulk((frame, message.kind(), size)).await?;
+        } else {
+            send_chunked(&self.channels.control, frame).await?;
+            self.counters.record_sent(message.kind(), size);
+        }
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>> {
+        // Messages that arrived before the channels closed are still handed out first
+        tokio::select! {
+            biased;
+            message = self.incoming.recv() => Ok(message),
+            _ = self.closed.wait_for(|closed| *closed) => Ok(None),
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.incoming.close();
+        self.bulk_writer.abort();
+        // The peer may already be gone, which is what we wanted anyway
+        let _ = self.peer_connection.close().await;
+        Ok(())
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+
+    fn counters(&self) -> Arc<TransportCounters> {
+        self.counters.clone()
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.format = format;
+    }
+}
+
+impl Drop for WebRtcConnection {
+    fn drop(&mut self) {
+        self.bulk_writer.abort();
+        // Dropped outside a runtime, say as one shuts down, there is nothing to close it on
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let peer_connection = self.peer_connection.clone();
+        runtime.spawn(async move {
+            let _ = peer_connection.close().await;
+        });
+    }
+}
@@ -0,0 +1,256 @@
+use super::{Connection, Listener, MessageSize, Transport, TransportCounters, TransportStats};
+use crate::codec::{self, WireFormat};
+use crate::network::{self, NetworkMessage, DEFAULT_MAX_MESSAGE_LEN, MAX_FRAME_LEN};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+// A client that opens the socket but never finishes the upgrade is dropped after this
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// One JSON-encoded NetworkMessage per text message, the same JSON the TCP framing carries, so a
+// web page or a short script can connect with nothing more than a WebSocket library. Clients
+// that advertise the binary codec or compression get binary messages for what those cover.
+pub struct WebSocketTransport;
+
+#[async_trait]
+impl Transport for WebSocketTransport {
+    fn name(&self) -> &'static str {
+        "websocket"
+    }
+
+    async fn listen_with_limit(
+        &self,
+        address: SocketAddr,
+        max_message_len: usize,
+    ) -> Result<Box<dyn Listener>> {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("Failed to listen on {}", address))?;
+        let local_addr = listener.local_addr()?;
+        let (upgraded_tx, upgraded) = mpsc::channel(16);
+
+        Ok(Box::new(WebSocketListener {
+            local_addr,
+            upgraded,
+            acceptor: spawn_acceptor(listener, upgraded_tx, max_message_len),
+        }))
+    }
+
+    async fn connect(&self, address: &str, timeout: Duration) -> Result<Box<dyn Connection>> {
+        let stream = tokio::time::timeout(timeout, TcpStream::connect(address))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out connecting to {}", address))?
+            .with_context(|| format!("Failed to connect to {}", address))?;
+        stream.set_nodelay(true)?;
+        let peer = stream.peer_addr()?;
+
+        let url = format!("ws://{}/", address);
+        let (socket, _) = tokio::time::timeout(
+            timeout,
+            tokio_tungstenite::client_async_with_config(
+                url,
+                stream,
+                Some(socket_config(DEFAULT_MAX_MESSAGE_LEN)),
+            ),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out upgrading connection to {}", address))?
+        .with_context(|| format!("WebSocket upgrade with {} failed", address))?;
+
+        Ok(Box::new(WebSocketConnection::new(
+            socket,
+            peer,
+            DEFAULT_MAX_MESSAGE_LEN,
+        )))
+    }
+}
+
+// The library refuses an oversized message from its header, before buffering it
+fn socket_config(max_message_len: usize) -> WebSocketConfig {
+    let max_frame_len = MAX_FRAME_LEN.min(max_message_len);
+    WebSocketConfig {
+        max_message_size: Some(max_frame_len),
+        max_frame_size: Some(max_frame_len),
+        ..Default::default()
+    }
+}
+
+struct WebSocketListener {
+    local_addr: SocketAddr,
+    upgraded: mpsc::Receiver<WebSocketConnection>,
+    acceptor: JoinHandle<()>,
+}
+
+#[async_trait]
+impl Listener for WebSocketListener {
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    async fn accept(&mut self) -> Result<Box<dyn Connection>> {
+        match self.upgraded.recv().await {
+            Some(connection) => Ok(Box::new(connection)),
+            None => Err(anyhow::anyhow!("Listener on {} closed", self.local_addr)),
+        }
+    }
+}
+
+impl Drop for WebSocketListener {
+    fn drop(&mut self) {
+        self.acceptor.abort();
+    }
+}
+
+// Upgrades run on their own tasks so one slow client can't hold up the others, and so accept()
+// stays cancel-safe
+fn spawn_acceptor(
+    listener: TcpListener,
+    upgraded: mpsc::Sender<WebSocketConnection>,
+    max_message_len: usize,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            let upgraded = upgraded.clone();
+
+            tokio::spawn(async move {
+                let _ = stream.set_nodelay(true);
+                let upgrade = tokio_tungstenite::accept_async_with_config(
+                    stream,
+                    Some(socket_config(max_message_len)),
+                );
+                match tokio::time::timeout(UPGRADE_TIMEOUT, upgrade).await {
+                    Ok(Ok(socket)) => {
+                        let connection = WebSocketConnection::new(socket, peer, max_message_len);
+                        let _ = upgraded.send(connection).await;
+                    }
+                    Ok(Err(e)) => log::debug!("WebSocket upgrade from {} failed: {}", peer, e),
+                    Err(_) => log::debug!("WebSocket upgrade from {} timed out", peer),
+                }
+            });
+        }
+    })
+}
+
+struct WebSocketConnection {
+    sink: SplitSink<WebSocketStream<TcpStream>, Message>,
+    incoming: mpsc::Receiver<NetworkMessage>,
+    peer: SocketAddr,
+    counters: Arc<TransportCounters>,
+    format: WireFormat,
+}
+
+impl WebSocketConnection {
+    fn new(socket: WebSocketStream<TcpStream>, peer: SocketAddr, max_message_len: usize) -> Self {
+        let counters = Arc::new(TransportCounters::default());
+        let (sink, stream) = socket.split();
+
+        Self {
+            sink,
+            incoming: spawn_message_reader(stream, peer, counters.clone(), max_message_len),
+            peer,
+            counters,
+            format: WireFormat::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Connection for WebSocketConnection {
+    fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    async fn send(&mut self, message: &NetworkMessage) -> Result<()> {
+        // Plain JSON goes out as a text message; binary-coded and compressed payloads as binary
+        let payload = codec::encode(message, self.format)?;
+        let size = MessageSize::new(&payload, payload.len());
+        let frame = if codec::is_binary(&payload) {
+            Message::Binary(payload)
+        } else {
+            Message::Text(String::from_utf8(payload)?)
+        };
+        self.sink.send(frame).await?;
+        self.counters.record_sent(message.kind(), size);
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Option<NetworkMessage>> {
+        Ok(self.incoming.recv().await)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.incoming.close();
+        // The peer may already be gone, which is what we wanted anyway
+        let _ = self.sink.close().await;
+        Ok(())
+    }
+
+    fn stats(&self) -> TransportStats {
+        self.counters.snapshot()
+    }
+
+    fn counters(&self) -> Arc<TransportCounters> {
+        self.counters.clone()
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.format = format;
+    }
+}
+
+// Browsers may send JSON as text or binary, and binary may also hold the compact codec; all of
+// it decodes the same way. Pings are answered by the library itself.
+fn spawn_message_reader(
+    mut stream: SplitStream<WebSocketStream<TcpStream>>,
+    peer: SocketAddr,
+    counters: Arc<TransportCounters>,
+    max_message_len: usize,
+) -> mpsc::Receiver<NetworkMessage> {
+    let (tx, rx) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        while let Some(received) = stream.next().await {
+            let payload = match received {
+                Ok(Message::Text(text)) => text.into_bytes(),
+                Ok(Message::Binary(bytes)) => bytes,
+                Ok(Message::Close(_)) => break,
+                Ok(_) => continue,
+                Err(e) => {
+                    log::warn!("Failed to read WebSocket message from {}: {}", peer, e);
+                    break;
+                }
+            };
+            match network::decode_frame(&payload, max_message_len) {
+                Ok(message) => {
+                    counters
+                        .record_received(message.kind(), MessageSize::new(&payload, payload.len()));
+                    if tx.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                // Message boundaries survive a bad message here, unlike on a raw stream
+                Err(e) => log::warn!("Dropping malformed message from {}: {}", peer, e),
+            }
+        }
+    });
+
+    rx
+}
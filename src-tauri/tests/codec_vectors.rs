@@ -0,0 +1,74 @@
+use mousebridge_lib::codec::{self, WireFormat, CODEC_VERSION};
+use mousebridge_lib::network::NetworkMessage;
+use serde_json::Value;
+
+const VECTORS: &str = include_str!("codec_vectors.json");
+
+const BINARY: WireFormat = WireFormat {
+    binary: true,
+    compression: None,
+};
+
+fn vectors() -> Value {
+    serde_json::from_str(VECTORS).expect("codec_vectors.json is not valid JSON")
+}
+
+fn entries<'a>(vectors: &'a Value, key: &str) -> &'a Vec<Value> {
+    vectors[key].as_array().expect("missing vector list")
+}
+
+fn bytes(entry: &Value) -> Vec<u8> {
+    hex::decode(entry["encoded"].as_str().unwrap()).unwrap()
+}
+
+// NetworkMessage has no PartialEq; its JSON form is what the vectors are written in anyway
+fn as_json(message: &NetworkMessage) -> Value {
+    serde_json::to_value(message).unwrap()
+}
+
+#[test]
+fn vectors_match_codec_version() {
+    assert_eq!(vectors()["codec_version"], u64::from(CODEC_VERSION));
+}
+
+#[test]
+fn encodes_and_decodes_golden_vectors() {
+    let vectors = vectors();
+    for entry in entries(&vectors, "vectors") {
+        let name = &entry["name"];
+        let message: NetworkMessage = serde_json::from_value(entry["message"].clone()).unwrap();
+
+        let encoded = codec::encode(&message, BINARY).unwrap();
+        assert_eq!(hex::encode(&encoded), entry["encoded"], "encoding {}", name);
+
+        let decoded = codec::decode(&bytes(entry)).unwrap();
+        assert_eq!(as_json(&decoded), entry["message"], "decoding {}", name);
+    }
+}
+
+#[test]
+fn decodes_decode_only_vectors() {
+    let vectors = vectors();
+    for entry in entries(&vectors, "decode_only") {
+        let decoded = codec::decode(&bytes(entry))
+            .unwrap_or_else(|e| panic!("decoding {}: {}", entry["name"], e));
+        assert_eq!(
+            as_json(&decoded),
+            entry["message"],
+            "decoding {}",
+            entry["name"]
+        );
+    }
+}
+
+#[test]
+fn rejects_invalid_vectors() {
+    let vectors = vectors();
+    for entry in entries(&vectors, "invalid") {
+        assert!(
+            codec::decode(&bytes(entry)).is_err(),
+            "{} should not decode",
+            entry["name"]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use mousebridge_lib::config::{ConnectionConfig, Protocol};
+use mousebridge_lib::input::InputManager;
+use mousebridge_lib::network::{self, Server};
+use mousebridge_lib::transport::QuicTransport;
+use std::sync::Arc;
+
+async fn probe(protocol: Protocol) -> network::ConnectivityReport {
+    let mut config = ConnectionConfig {
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        protocol,
+        ..ConnectionConfig::default()
+    };
+    let mut server = Server::new(
+        config.clone(),
+        Arc::new(InputManager::new()),
+        "server".to_string(),
+    )
+    .await
+    .unwrap();
+    // QUIC clients only accept the certificate they were told about
+    if matches!(config.protocol, Protocol::QUIC) {
+        let quic = QuicTransport::server().unwrap();
+        config.quic_cert_sha256 = quic.certificate_sha256();
+        server = server.with_transport(Arc::new(quic));
+    }
+    let handle = server.listen().await.unwrap();
+
+    let config = ConnectionConfig {
+        port: handle.local_addr().port(),
+        ..config
+    };
+    let report = network::test_connectivity(&config, true).await.unwrap();
+    handle.stop().await.unwrap();
+    report
+}
+
+#[tokio::test]
+async fn probes_a_websocket_server() {
+    let report = probe(Protocol::WebSocket).await;
+    assert!(report.success, "{:?}", report);
+    assert_eq!(report.remote_fingerprint.as_deref(), Some("server"));
+}
+
+#[tokio::test]
+async fn probes_a_quic_server() {
+    let report = probe(Protocol::QUIC).await;
+    assert!(report.success, "{:?}", report);
+    assert_eq!(report.remote_fingerprint.as_deref(), Some("server"));
+}
+
+#[tokio::test]
+async fn reports_a_refused_connection() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    let config = ConnectionConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        protocol: Protocol::TCP,
+        ..ConnectionConfig::default()
+    };
+    let report = network::test_connectivity(&config, true).await.unwrap();
+    assert!(!report.success);
+    assert_eq!(report.failure, Some(network::ConnectivityFailure::Refused));
+}
@@ -0,0 +1,47 @@
+use mousebridge_lib::bridge::ServerInfo;
+use mousebridge_lib::config::DiscoveryConfig;
+use mousebridge_lib::discovery;
+use mousebridge_lib::protocol;
+use std::net::{Ipv4Addr, UdpSocket};
+use tokio::time::Duration;
+
+// Beacons also go to loopback, so this finds the server even where broadcast is filtered
+#[tokio::test]
+async fn finds_a_beaconing_server_on_loopback() {
+    let port = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let info = ServerInfo {
+        hostname: "desk".to_string(),
+        ip: "127.0.0.1".to_string(),
+        port: 4242,
+        fingerprint: "server".to_string(),
+        quic_cert_sha256: Some("ab".repeat(32)),
+    };
+    let config = DiscoveryConfig {
+        enabled: true,
+        port,
+        interval_ms: 100,
+        mdns: false,
+    };
+    let beacon = discovery::start_beacon(&info, &config, Ipv4Addr::LOCALHOST.into())
+        .await
+        .unwrap();
+
+    let servers = discovery::discover_servers_on(port, Duration::from_millis(500))
+        .await
+        .unwrap();
+    beacon.stop().await.unwrap();
+
+    assert_eq!(servers.len(), 1, "{:?}", servers);
+    let server = &servers[0];
+    assert_eq!(server.hostname, "desk");
+    assert_eq!(server.ip, "127.0.0.1");
+    assert_eq!(server.port, 4242);
+    assert_eq!(server.fingerprint, "server");
+    assert_eq!(server.protocol_version, protocol::PROTOCOL_VERSION);
+    assert!(server.compatible);
+    assert_eq!(server.quic_cert_sha256, Some("ab".repeat(32)));
+}
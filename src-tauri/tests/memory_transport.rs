@@ -0,0 +1,504 @@
+use mousebridge_lib::config::{BucketConfig, ConnectionConfig, RateLimitConfig, ReconnectConfig};
+use mousebridge_lib::input::{InputManager, MouseEvent, Positioning};
+use mousebridge_lib::network::{
+    Client, ClientHandle, LinkState, NetworkMessage, Server, ServerHandle,
+};
+use mousebridge_lib::protocol::{self, Capabilities};
+use mousebridge_lib::transport::{Connection, FaultConfig, MemoryTransport, Transport};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout, Duration};
+
+// Fixed so that jitter, loss and reordering make the same choices on every run
+const SEED: u64 = 14;
+const WAIT: Duration = Duration::from_secs(5);
+
+struct Link {
+    transport: MemoryTransport,
+    server: ServerHandle,
+    client: ClientHandle,
+    events: mpsc::Receiver<MouseEvent>,
+    // The client's fingerprint, for ServerHandle::send_to
+    fingerprint: String,
+}
+
+fn reconnect(enabled: bool) -> ReconnectConfig {
+    ReconnectConfig {
+        enabled,
+        initial_delay_ms: 20,
+        max_delay_ms: 100,
+        ..ReconnectConfig::default()
+    }
+}
+
+async fn connect(faults: FaultConfig, reconnect: ReconnectConfig) -> Link {
+    let transport = MemoryTransport::with_faults(faults);
+    let config = ConnectionConfig {
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        reconnect,
+        ..ConnectionConfig::default()
+    };
+
+    let server = Server::new(
+        config.clone(),
+        Arc::new(InputManager::new()),
+        "server".to_string(),
+    )
+    .await
+    .unwrap()
+    .with_transport(Arc::new(transport.clone()));
+    let server = server.listen().await.unwrap();
+
+    let config = ConnectionConfig {
+        port: server.local_addr().port(),
+        ..config
+    };
+    let (events_tx, events) = mpsc::channel(4096);
+    let client = Client::new(config, Arc::new(InputManager::new()))
+        .await
+        .unwrap()
+        .with_transport(Arc::new(transport.clone()))
+        .connect_with_sink(events_tx)
+        .await
+        .unwrap();
+
+    // The server places the client just after answering its handshake
+    let fingerprint = timeout(WAIT, async {
+        loop {
+            if let Some(client) = server.clients().await.pop() {
+                return client.fingerprint;
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("client never showed up on the server");
+
+    Link {
+        transport,
+        server,
+        client,
+        events,
+        fingerprint,
+    }
+}
+
+fn motion(x: i32, y: i32) -> NetworkMessage {
+    NetworkMessage::MouseEvent(MouseEvent {
+        positioning: Positioning::Absolute,
+        ..MouseEvent::relative(x, y)
+    })
+}
+
+fn left_button(pressed: bool) -> NetworkMessage {
+    NetworkMessage::MouseEvent(MouseEvent {
+        x: 100,
+        y: 100,
+        button: Some("left".to_string()),
+        pressed,
+        positioning: Positioning::Absolute,
+        ..MouseEvent::relative(0, 0)
+    })
+}
+
+async fn next_event(events: &mut mpsc::Receiver<MouseEvent>) -> MouseEvent {
+    timeout(WAIT, events.recv())
+        .await
+        .expect("no event in time")
+        .expect("client went away")
+}
+
+// Server-sent button events are absolute; the client's own releases are zero-length deltas
+fn is_server_release(event: &MouseEvent) -> bool {
+    event.button.as_deref() == Some("left") && !event.pressed && !event.is_relative()
+}
+
+async fn wait_until_connected(client: &ClientHandle) {
+    timeout(WAIT, async {
+        while !matches!(client.state().await, LinkState::Connected) {
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("client never reconnected");
+}
+
+#[tokio::test]
+async fn resumes_a_severed_link_without_releasing_held_buttons() {
+    let mut link = connect(
+        FaultConfig {
+            seed: SEED,
+            ..FaultConfig::default()
+        },
+        reconnect(true),
+    )
+    .await;
+
+    link.server
+        .send_to(&link.fingerprint, left_button(true))
+        .await
+        .unwrap();
+    let pressed = next_event(&mut link.events).await;
+    assert!(pressed.pressed);
+
+    link.transport.sever();
+    // Waits in the parked session's queue, or is replayed if it went out on the dead link
+    link.server
+        .send_to(&link.fingerprint, left_button(false))
+        .await
+        .unwrap();
+
+    let released = next_event(&mut link.events).await;
+    assert!(is_server_release(&released), "{:?}", released);
+    wait_until_connected(&link.client).await;
+    assert_eq!(link.server.clients().await.len(), 1);
+
+    sleep(Duration::from_millis(100)).await;
+    assert!(link.events.try_recv().is_err(), "release delivered twice");
+}
+
+#[tokio::test]
+async fn replays_input_lost_with_the_link() {
+    let mut link = connect(
+        FaultConfig {
+            latency: Duration::from_millis(50),
+            seed: SEED,
+            ..FaultConfig::default()
+        },
+        reconnect(true),
+    )
+    .await;
+
+    // Still on its way when the link goes, so it only arrives by being replayed
+    link.server
+        .send_to(&link.fingerprint, left_button(true))
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(10)).await;
+    link.transport.sever();
+
+    let replayed = next_event(&mut link.events).await;
+    assert_eq!(replayed.button.as_deref(), Some("left"));
+    assert!(replayed.pressed);
+    wait_until_connected(&link.client).await;
+
+    link.server
+        .send_to(&link.fingerprint, left_button(false))
+        .await
+        .unwrap();
+    let released = next_event(&mut link.events).await;
+    assert!(is_server_release(&released), "{:?}", released);
+}
+
+#[tokio::test]
+async fn releases_held_buttons_when_the_link_dies() {
+    let mut link = connect(
+        FaultConfig {
+            seed: SEED,
+            ..FaultConfig::default()
+        },
+        reconnect(false),
+    )
+    .await;
+
+    // Past the handshake already, so the next message through is the last
+    link.transport.set_faults(FaultConfig {
+        disconnect_after: Some(1),
+        seed: SEED,
+        ..FaultConfig::default()
+    });
+    link.server
+        .send_to(&link.fingerprint, left_button(true))
+        .await
+        .unwrap();
+    assert!(next_event(&mut link.events).await.pressed);
+
+    let released = next_event(&mut link.events).await;
+    assert_eq!(released.button.as_deref(), Some("left"));
+    assert!(!released.pressed);
+    assert!(released.is_relative() && released.x == 0 && released.y == 0);
+
+    timeout(WAIT, async {
+        while !matches!(link.client.state().await, LinkState::Disconnected) {
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("client never gave up");
+}
+
+#[tokio::test]
+async fn coalesces_motion_on_a_slow_link() {
+    const MOVES: i32 = 2000;
+
+    let mut link = connect(
+        FaultConfig {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(10),
+            seed: SEED,
+            ..FaultConfig::default()
+        },
+        reconnect(true),
+    )
+    .await;
+
+    for x in 1..=MOVES {
+        link.server
+            .send_to(&link.fingerprint, motion(x, 0))
+            .await
+            .unwrap();
+        tokio::task::yield_now().await;
+    }
+
+    let mut received = Vec::new();
+    while received.last() != Some(&MOVES) {
+        let event = next_event(&mut link.events).await;
+        assert!(event.is_motion());
+        received.push(event.x);
+    }
+
+    // Merged moves keep the latest position, and jitter never reorders a stream
+    assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(received.len() < MOVES as usize, "nothing was coalesced");
+    let stats = link.server.queue_stats(&link.fingerprint).await.unwrap();
+    assert!(stats.coalesced_motion > 0);
+    assert_eq!(
+        stats.coalesced_motion + stats.dropped_motion + received.len() as u64,
+        MOVES as u64
+    );
+}
+
+async fn receive(connection: &mut dyn Connection) -> NetworkMessage {
+    timeout(WAIT, connection.receive())
+        .await
+        .expect("nothing received in time")
+        .unwrap()
+        .expect("connection closed")
+}
+
+// Speaks the handshake by hand, so a later resume can claim any fingerprint. Returns the
+// connection with its resume token.
+async fn handshake(
+    transport: &MemoryTransport,
+    address: &str,
+    fingerprint: &str,
+) -> (Box<dyn Connection>, String) {
+    let mut connection = transport.connect(address, WAIT).await.unwrap();
+    connection
+        .send(&NetworkMessage::ConnectionRequest {
+            fingerprint: fingerprint.to_string(),
+            device_name: "test".to_string(),
+            min_version: protocol::MIN_PROTOCOL_VERSION,
+            max_version: protocol::PROTOCOL_VERSION,
+            capabilities: Capabilities::default(),
+            probe: false,
+            screen: None,
+        })
+        .await
+        .unwrap();
+    match receive(connection.as_mut()).await {
+        NetworkMessage::ConnectionResponse {
+            accepted: true,
+            resume_token: Some(token),
+            ..
+        } => (connection, token),
+        other => panic!("expected a resume token, got {:?}", other),
+    }
+}
+
+async fn try_resume(
+    transport: &MemoryTransport,
+    address: &str,
+    token: &str,
+    fingerprint: &str,
+) -> bool {
+    let mut connection = transport.connect(address, WAIT).await.unwrap();
+    connection
+        .send(&NetworkMessage::ResumeRequest {
+            token: token.to_string(),
+            received: 0,
+            fingerprint: fingerprint.to_string(),
+        })
+        .await
+        .unwrap();
+    match receive(connection.as_mut()).await {
+        NetworkMessage::ResumeResponse { accepted, .. } => accepted,
+        other => panic!("expected a resume response, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn resumes_only_under_the_sessions_fingerprint() {
+    let transport = MemoryTransport::with_faults(FaultConfig {
+        seed: SEED,
+        ..FaultConfig::default()
+    });
+    let server = Server::new(
+        ConnectionConfig {
+            port: 0,
+            ..ConnectionConfig::default()
+        },
+        Arc::new(InputManager::new()),
+        "server".to_string(),
+    )
+    .await
+    .unwrap()
+    .with_transport(Arc::new(transport.clone()));
+    let server = server.listen().await.unwrap();
+    let address = format!("127.0.0.1:{}", server.local_addr().port());
+
+    let (connection, token) = handshake(&transport, &address, "laptop").await;
+    drop(connection);
+    // Parked once the server has noticed the connection is gone
+    timeout(WAIT, async {
+        while !server.link_stats().await.is_empty() {
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .unwrap();
+    sleep(Duration::from_millis(20)).await;
+
+    assert!(!try_resume(&transport, &address, &token, "intruder").await);
+    assert!(try_resume(&transport, &address, &token, "laptop").await);
+    assert_eq!(server.clients().await.len(), 1);
+}
+
+#[tokio::test]
+async fn ends_the_session_of_a_banned_client() {
+    let transport = MemoryTransport::with_faults(FaultConfig {
+        seed: SEED,
+        ..FaultConfig::default()
+    });
+    let server = Server::new(
+        ConnectionConfig {
+            port: 0,
+            rate_limits: RateLimitConfig {
+                control: BucketConfig {
+                    per_second: 0.0,
+                    burst: 2,
+                },
+                violations_before_ban: 1,
+                ..RateLimitConfig::default()
+            },
+            ..ConnectionConfig::default()
+        },
+        Arc::new(InputManager::new()),
+        "server".to_string(),
+    )
+    .await
+    .unwrap()
+    .with_transport(Arc::new(transport.clone()));
+    let server = server.listen().await.unwrap();
+    let address = format!("127.0.0.1:{}", server.local_addr().port());
+
+    let (mut connection, _) = handshake(&transport, &address, "laptop").await;
+    assert_eq!(server.clients().await.len(), 1);
+    for received in 0..3 {
+        let _ = connection
+            .send(&NetworkMessage::InputAck { received })
+            .await;
+    }
+
+    // A parked session would keep its place in the layout for the whole resume grace
+    timeout(WAIT, async {
+        while !server.clients().await.is_empty() {
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("banned client's session was kept for a resume");
+}
+
+async fn resuming_server(transport: &MemoryTransport, resume_grace_ms: u64) -> ServerHandle {
+    let server = Server::new(
+        ConnectionConfig {
+            port: 0,
+            reconnect: ReconnectConfig {
+                resume_grace_ms,
+                ..ReconnectConfig::default()
+            },
+            ..ConnectionConfig::default()
+        },
+        Arc::new(InputManager::new()),
+        "server".to_string(),
+    )
+    .await
+    .unwrap()
+    .with_transport(Arc::new(transport.clone()));
+    server.listen().await.unwrap()
+}
+
+// Skips heartbeats and anything else the server sends along the way
+async fn next_mouse_event(connection: &mut dyn Connection) -> MouseEvent {
+    loop {
+        if let NetworkMessage::MouseEvent(event) = receive(connection).await {
+            return event;
+        }
+    }
+}
+
+#[tokio::test]
+async fn an_expiring_session_leaves_the_next_one_in_place() {
+    const GRACE_MS: u64 = 100;
+
+    let transport = MemoryTransport::with_faults(FaultConfig {
+        seed: SEED,
+        ..FaultConfig::default()
+    });
+    let server = resuming_server(&transport, GRACE_MS).await;
+    let address = format!("127.0.0.1:{}", server.local_addr().port());
+
+    let (connection, _) = handshake(&transport, &address, "laptop").await;
+    drop(connection);
+    timeout(WAIT, async {
+        while !server.link_stats().await.is_empty() {
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // A full handshake instead of a resume, while the old session is still parked
+    let (mut connection, _) = handshake(&transport, &address, "laptop").await;
+    sleep(Duration::from_millis(GRACE_MS * 3)).await;
+
+    assert_eq!(server.clients().await.len(), 1);
+    server.send_to("laptop", left_button(true)).await.unwrap();
+    let pressed = next_mouse_event(connection.as_mut()).await;
+    assert_eq!(pressed.button.as_deref(), Some("left"));
+    assert!(pressed.pressed);
+}
+
+#[tokio::test]
+async fn resumes_a_session_the_server_still_thinks_is_connected() {
+    let transport = MemoryTransport::with_faults(FaultConfig {
+        seed: SEED,
+        ..FaultConfig::default()
+    });
+    let server = resuming_server(&transport, 10000).await;
+    let address = format!("127.0.0.1:{}", server.local_addr().port());
+
+    // Kept open, so the session is still streaming to it when the resume comes in
+    let (_stale, token) = handshake(&transport, &address, "laptop").await;
+
+    assert!(!try_resume(&transport, &address, &token, "intruder").await);
+    let mut connection = transport.connect(&address, WAIT).await.unwrap();
+    connection
+        .send(&NetworkMessage::ResumeRequest {
+            token,
+            received: 0,
+            fingerprint: "laptop".to_string(),
+        })
+        .await
+        .unwrap();
+    match receive(connection.as_mut()).await {
+        NetworkMessage::ResumeResponse { accepted, .. } => assert!(accepted),
+        other => panic!("expected a resume response, got {:?}", other),
+    }
+
+    assert_eq!(server.clients().await.len(), 1);
+    server.send_to("laptop", left_button(true)).await.unwrap();
+    assert!(next_mouse_event(connection.as_mut()).await.pressed);
+}
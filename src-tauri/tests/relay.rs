@@ -0,0 +1,236 @@
+use mousebridge_lib::config::RelayConfig;
+use mousebridge_lib::network::NetworkMessage;
+use mousebridge_lib::relay::{self, RelayHandle, RelayMessage, RelayRole};
+use mousebridge_lib::transport::{Listener, RelayTransport, Transport};
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+
+const WAIT: Duration = Duration::from_secs(5);
+// The random seed each peer sends before its first frame
+const SEED_LEN: usize = 32;
+
+async fn assert_hung_up(stream: &mut TcpStream) {
+    let mut buffer = [0u8; 16];
+    let read = timeout(WAIT, stream.read(&mut buffer))
+        .await
+        .expect("relay kept the connection open");
+    assert!(matches!(read, Ok(0) | Err(_)), "{:?}", read);
+}
+
+async fn wait_for_waiting_server(handle: &RelayHandle) {
+    timeout(WAIT, async {
+        while handle.stats().await.waiting_servers == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("server never started waiting");
+}
+
+#[tokio::test]
+async fn stopping_the_relay_hangs_up_on_every_peer() {
+    let handle = relay::start_relay_server("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let address = handle.local_addr().to_string();
+    let session = relay::session_id("secret");
+
+    let waiting = tokio::spawn({
+        let address = address.clone();
+        let session = session.clone();
+        async move { relay::join(&address, &session, RelayRole::Server, WAIT).await }
+    });
+    // The server has to be waiting before a client can pair with it
+    wait_for_waiting_server(&handle).await;
+    let mut client = relay::join(&address, &session, RelayRole::Client, WAIT)
+        .await
+        .unwrap();
+    let mut server = waiting.await.unwrap().unwrap();
+
+    client.write_all(b"ping").await.unwrap();
+    let mut buffer = [0u8; 4];
+    server.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"ping");
+
+    // A second server still waiting for its client when the relay goes away
+    let mut parked = TcpStream::connect(&address).await.unwrap();
+    relay::write_relay_message(
+        &mut parked,
+        &relay::RelayMessage::Join {
+            session: relay::session_id("other"),
+            role: RelayRole::Server,
+        },
+    )
+    .await
+    .unwrap();
+    wait_for_waiting_server(&handle).await;
+
+    handle.stop().await.unwrap();
+    assert_hung_up(&mut client).await;
+    assert_hung_up(&mut server).await;
+    assert_hung_up(&mut parked).await;
+}
+
+#[derive(Clone, Copy)]
+enum Meddling {
+    None,
+    // Flips a bit in the client's first frame
+    Tamper,
+    // Forwards the client's second frame ahead of its first
+    Reorder,
+}
+
+async fn read_sealed_frame<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let len = reader.read_u32().await? as usize;
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+// Stands in for the relay: pairs the first server and client to join, whatever their sessions,
+// and meddles with what the client sends
+async fn start_meddling_relay(meddling: Meddling) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (mut server, mut client) = (None, None);
+        while server.is_none() || client.is_none() {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            match relay::read_relay_message(&mut stream).await.unwrap() {
+                RelayMessage::Join {
+                    role: RelayRole::Server,
+                    ..
+                } => server = Some(stream),
+                RelayMessage::Join {
+                    role: RelayRole::Client,
+                    ..
+                } => client = Some(stream),
+                other => panic!("expected a join, got {:?}", other),
+            }
+        }
+        let (mut server, mut client) = (server.unwrap(), client.unwrap());
+        for stream in [&mut server, &mut client] {
+            relay::write_relay_message(stream, &RelayMessage::Paired)
+                .await
+                .unwrap();
+        }
+
+        let (mut server_read, mut server_write) = server.into_split();
+        let (mut client_read, mut client_write) = client.into_split();
+        tokio::spawn(async move {
+            let _ = tokio::io::copy(&mut server_read, &mut client_write).await;
+        });
+
+        let mut seed = [0u8; SEED_LEN];
+        client_read.read_exact(&mut seed).await.unwrap();
+        server_write.write_all(&seed).await.unwrap();
+        let mut held = None;
+        let mut frames = 0;
+        while let Ok(mut frame) = read_sealed_frame(&mut client_read).await {
+            frames += 1;
+            let mut forward = vec![];
+            match meddling {
+                Meddling::Tamper if frames == 1 => {
+                    frame[0] ^= 1;
+                    forward.push(frame);
+                }
+                Meddling::Reorder if frames == 1 => held = Some(frame),
+                Meddling::Reorder if frames == 2 => {
+                    forward.push(frame);
+                    forward.extend(held.take());
+                }
+                _ => forward.push(frame),
+            }
+            for frame in forward {
+                server_write.write_u32(frame.len() as u32).await.unwrap();
+                server_write.write_all(&frame).await.unwrap();
+            }
+        }
+    });
+
+    address
+}
+
+fn relay_transport(address: SocketAddr, secret: &str) -> RelayTransport {
+    RelayTransport::new(RelayConfig {
+        address: address.to_string(),
+        secret: secret.to_string(),
+    })
+}
+
+// Sends two acks from the client and returns what the server made of them. The listener is
+// handed back too, so the server end isn't dropped with it.
+async fn relay_two_acks(
+    server: &RelayTransport,
+    client: &RelayTransport,
+) -> (Box<dyn Listener>, Vec<Option<NetworkMessage>>) {
+    let mut listener = server.listen("127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let mut client = client.connect("unused", WAIT).await.unwrap();
+    let mut server = timeout(WAIT, listener.accept()).await.unwrap().unwrap();
+
+    for received in 1..=2 {
+        client
+            .send(&NetworkMessage::InputAck { received })
+            .await
+            .unwrap();
+    }
+    let mut seen = Vec::new();
+    for _ in 0..2 {
+        let message = timeout(WAIT, server.receive())
+            .await
+            .expect("server neither got a message nor hung up")
+            .unwrap();
+        seen.push(message);
+    }
+    (listener, seen)
+}
+
+#[tokio::test]
+async fn passes_frames_the_relay_left_alone() {
+    let address = start_meddling_relay(Meddling::None).await;
+    let transport = relay_transport(address, "correct horse battery staple");
+
+    let (_listener, seen) = relay_two_acks(&transport, &transport).await;
+    assert!(
+        matches!(
+            seen[..],
+            [
+                Some(NetworkMessage::InputAck { received: 1 }),
+                Some(NetworkMessage::InputAck { received: 2 })
+            ]
+        ),
+        "{:?}",
+        seen
+    );
+}
+
+#[tokio::test]
+async fn a_peer_with_the_wrong_secret_fails_authentication() {
+    let address = start_meddling_relay(Meddling::None).await;
+    let server = relay_transport(address, "correct horse battery staple");
+    let client = relay_transport(address, "incorrect horse battery staple");
+
+    let (_listener, seen) = relay_two_acks(&server, &client).await;
+    assert!(seen.iter().all(Option::is_none), "{:?}", seen);
+}
+
+#[tokio::test]
+async fn a_tampered_frame_ends_the_link() {
+    let address = start_meddling_relay(Meddling::Tamper).await;
+    let transport = relay_transport(address, "correct horse battery staple");
+
+    let (_listener, seen) = relay_two_acks(&transport, &transport).await;
+    assert!(seen.iter().all(Option::is_none), "{:?}", seen);
+}
+
+#[tokio::test]
+async fn a_reordered_frame_ends_the_link() {
+    let address = start_meddling_relay(Meddling::Reorder).await;
+    let transport = relay_transport(address, "correct horse battery staple");
+
+    let (_listener, seen) = relay_two_acks(&transport, &transport).await;
+    assert!(seen.iter().all(Option::is_none), "{:?}", seen);
+}
@@ -0,0 +1,355 @@
+use mousebridge_lib::codec::{Compression, WireFormat};
+use mousebridge_lib::config::{ConnectionConfig, RelayConfig, SignalingConfig};
+use mousebridge_lib::input::{InputManager, MouseEvent, Positioning};
+use mousebridge_lib::network::{Client, NetworkMessage, Server};
+use mousebridge_lib::relay;
+use mousebridge_lib::transport::{
+    Connection, Listener, QuicTransport, RelayTransport, TcpTransport, Transport, UdpTransport,
+    WebRtcTransport,
+};
+use mousebridge_lib::ClipboardData;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout, Duration};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+// The listener is handed back too: dropping it takes its connections down on some transports
+async fn pair(
+    transport: &dyn Transport,
+) -> (Box<dyn Listener>, Box<dyn Connection>, Box<dyn Connection>) {
+    pair_with(transport, transport).await
+}
+
+// For transports whose two ends are set up differently
+async fn pair_with(
+    server: &dyn Transport,
+    client: &dyn Transport,
+) -> (Box<dyn Listener>, Box<dyn Connection>, Box<dyn Connection>) {
+    let mut listener = server.listen("127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let address = listener.local_addr().to_string();
+    let mut client = client.connect(&address, WAIT).await.unwrap();
+
+    // Some transports only tell the listener about a connection once something is sent on it
+    client
+        .send(&NetworkMessage::InputAck { received: 0 })
+        .await
+        .unwrap();
+    let mut server = timeout(WAIT, listener.accept()).await.unwrap().unwrap();
+    match receive(server.as_mut()).await {
+        NetworkMessage::InputAck { received: 0 } => {}
+        other => panic!("expected the opening ack, got {:?}", other),
+    }
+    (listener, client, server)
+}
+
+async fn receive(connection: &mut dyn Connection) -> NetworkMessage {
+    timeout(WAIT, connection.receive())
+        .await
+        .expect("nothing received in time")
+        .unwrap()
+        .expect("connection closed")
+}
+
+fn motion(x: i32, y: i32) -> NetworkMessage {
+    NetworkMessage::MouseEvent(MouseEvent {
+        positioning: Positioning::Absolute,
+        ..MouseEvent::relative(x, y)
+    })
+}
+
+fn clipboard(text: String) -> NetworkMessage {
+    NetworkMessage::Clipboard(ClipboardData {
+        text: Some(text),
+        image: None,
+        files: None,
+    })
+}
+
+// Control, motion and bulk each travel their own way, so order only holds within each kind.
+// The large payload goes first to catch a later one overtaking it.
+async fn round_trip(sender: &mut dyn Connection, receiver: &mut dyn Connection) {
+    let texts = [
+        "x".repeat(256 * 1024),
+        "second".to_string(),
+        "third".to_string(),
+    ];
+    sender
+        .send(&NetworkMessage::InputAck { received: 1 })
+        .await
+        .unwrap();
+    sender.send(&motion(5, 7)).await.unwrap();
+    for text in &texts {
+        sender.send(&clipboard(text.clone())).await.unwrap();
+    }
+    sender
+        .send(&NetworkMessage::InputAck { received: 2 })
+        .await
+        .unwrap();
+
+    let mut acks = Vec::new();
+    let mut moves = Vec::new();
+    let mut pasted = Vec::new();
+    while acks.len() + moves.len() + pasted.len() < 6 {
+        match receive(receiver).await {
+            NetworkMessage::InputAck { received } => acks.push(received),
+            NetworkMessage::MouseEvent(event) => moves.push((event.x, event.y)),
+            NetworkMessage::Clipboard(data) => pasted.push(data.text.unwrap()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    assert_eq!(acks, [1, 2]);
+    assert_eq!(moves, [(5, 7)]);
+    assert_eq!(pasted, texts);
+}
+
+// A real server and client over loopback TCP, from the handshake to the client saying goodbye
+#[tokio::test]
+async fn tcp_server_streams_input_to_a_client() {
+    let config = ConnectionConfig {
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        ..ConnectionConfig::default()
+    };
+    let server = Server::new(
+        config.clone(),
+        Arc::new(InputManager::new()),
+        "server".to_string(),
+    )
+    .await
+    .unwrap()
+    .with_transport(Arc::new(TcpTransport))
+    .listen()
+    .await
+    .unwrap();
+
+    let config = ConnectionConfig {
+        port: server.local_addr().port(),
+        ..config
+    };
+    let (events_tx, mut events) = mpsc::channel(16);
+    let client = Client::new(config, Arc::new(InputManager::new()))
+        .await
+        .unwrap()
+        .with_transport(Arc::new(TcpTransport))
+        .connect_with_sink(events_tx)
+        .await
+        .unwrap();
+    assert_eq!(client.server_fingerprint().await, "server");
+    assert_eq!(
+        client.remote_address().await.port(),
+        server.local_addr().port()
+    );
+
+    let fingerprint = timeout(WAIT, async {
+        loop {
+            if let Some(client) = server.clients().await.pop() {
+                return client.fingerprint;
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("client never showed up on the server");
+    server.send_to(&fingerprint, motion(5, 7)).await.unwrap();
+    let event = timeout(WAIT, events.recv()).await.unwrap().unwrap();
+    assert_eq!((event.x, event.y), (5, 7));
+
+    // A goodbye ends the session outright rather than holding it for a resume
+    client.disconnect().await.unwrap();
+    timeout(WAIT, async {
+        while !server.clients().await.is_empty() {
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("server kept the client after it disconnected");
+}
+
+// A server transport and a client pinned to its certificate
+fn quic_pair() -> (QuicTransport, QuicTransport) {
+    let server = QuicTransport::server().unwrap();
+    let client = QuicTransport::new(server.certificate_sha256());
+    (server, client)
+}
+
+#[tokio::test]
+async fn quic_round_trips_every_kind_of_message() {
+    let (server, client) = quic_pair();
+    let (_listener, mut client, mut server) = pair_with(&server, &client).await;
+    round_trip(client.as_mut(), server.as_mut()).await;
+    round_trip(server.as_mut(), client.as_mut()).await;
+
+    let stats = client.stats();
+    assert!(stats.datagrams_sent > 0 && stats.datagrams_received > 0);
+}
+
+#[tokio::test]
+async fn quic_reports_bulk_sends_after_the_connection_closes() {
+    let (server, client) = quic_pair();
+    let (_listener, mut client, mut server) = pair_with(&server, &client).await;
+    server.close().await.unwrap();
+    // Closing takes a moment to reach the client
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let mut failed = false;
+    for _ in 0..3 {
+        if client.send(&clipboard("lost".to_string())).await.is_err() {
+            failed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(failed, "bulk sends on a closed connection kept succeeding");
+}
+
+#[tokio::test]
+async fn quic_refuses_a_server_certificate_it_was_not_pinned_to() {
+    let (server, _) = quic_pair();
+    let listener = server.listen("127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let address = listener.local_addr().to_string();
+
+    let impostor = QuicTransport::server().unwrap();
+    let client = QuicTransport::new(impostor.certificate_sha256());
+    assert!(client.connect(&address, WAIT).await.is_err());
+    assert!(QuicTransport::new(None)
+        .connect(&address, WAIT)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn relay_round_trips_every_kind_of_message() {
+    let handle = relay::start_relay_server("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    // Both ends share the one transport, and with it the slow stretching of the secret
+    let transport = RelayTransport::new(RelayConfig {
+        address: handle.local_addr().to_string(),
+        secret: "correct horse battery staple".to_string(),
+    });
+    let mut listener = transport
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    // A client that gets to the relay before the server is turned away
+    timeout(WAIT, async {
+        while handle.stats().await.waiting_servers == 0 {
+            sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("server never started waiting");
+
+    let mut client = transport.connect("unused", WAIT).await.unwrap();
+    let mut server = timeout(WAIT, listener.accept()).await.unwrap().unwrap();
+    round_trip(client.as_mut(), server.as_mut()).await;
+    round_trip(server.as_mut(), client.as_mut()).await;
+}
+
+// Signals through the server the listener embeds on its own port
+#[tokio::test]
+async fn webrtc_round_trips_every_kind_of_message() {
+    let transport = WebRtcTransport::new(SignalingConfig::default());
+    let (_listener, mut client, mut server) = pair(&transport).await;
+    round_trip(client.as_mut(), server.as_mut()).await;
+    round_trip(server.as_mut(), client.as_mut()).await;
+
+    let stats = client.stats();
+    assert!(stats.datagrams_sent > 0 && stats.datagrams_received > 0);
+}
+
+// A resumed UDP session registers its datagram address just like a new one
+#[tokio::test]
+async fn udp_resumed_sessions_send_motion_as_datagrams() {
+    let transport = UdpTransport;
+    let mut listener = transport
+        .listen("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let address = listener.local_addr().to_string();
+    let mut client = transport.connect(&address, WAIT).await.unwrap();
+    client
+        .send(&NetworkMessage::ResumeRequest {
+            token: "token".to_string(),
+            received: 0,
+            fingerprint: "laptop".to_string(),
+        })
+        .await
+        .unwrap();
+    let mut server = timeout(WAIT, listener.accept()).await.unwrap().unwrap();
+    receive(server.as_mut()).await;
+
+    // Motion goes over TCP until the client's first registration has arrived
+    let moved = timeout(WAIT, async {
+        loop {
+            server.send(&motion(5, 7)).await.unwrap();
+            if server.stats().datagrams_sent > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert!(moved.is_ok(), "motion never moved to datagrams");
+}
+
+// Both ends size a compressed payload by what actually crossed the wire
+#[tokio::test]
+async fn counts_compressed_payloads_as_they_were_sent() {
+    let (_listener, mut client, mut server) = pair(&TcpTransport).await;
+    client.set_wire_format(WireFormat {
+        binary: true,
+        compression: Some(Compression::Lz4),
+    });
+    client
+        .send(&clipboard("x".repeat(64 * 1024)))
+        .await
+        .unwrap();
+    receive(server.as_mut()).await;
+
+    for connection in [&client, &server] {
+        let stats = connection.stats();
+        assert_eq!(stats.compressed_messages, 1);
+        assert!(stats.compressible_bytes > 64 * 1024);
+        assert!(stats.compressed_bytes < stats.compressible_bytes / 10);
+    }
+    let sent = client.counters().message_counts()["Clipboard"];
+    let received = server.counters().message_counts()["Clipboard"];
+    assert_eq!(sent.bytes_sent, client.stats().compressed_bytes);
+    assert_eq!(sent.bytes_sent, received.bytes_received);
+}
+
+// Refused from the length prefix, or for a compressed payload from what it says it expands to
+async fn refuses_over_the_listeners_limit(format: WireFormat) {
+    let transport = TcpTransport;
+    let mut listener = transport
+        .listen_with_limit("127.0.0.1:0".parse().unwrap(), 4096)
+        .await
+        .unwrap();
+    let address = listener.local_addr().to_string();
+    let mut client = transport.connect(&address, WAIT).await.unwrap();
+    client.set_wire_format(format);
+    client
+        .send(&clipboard("x".repeat(64 * 1024)))
+        .await
+        .unwrap();
+
+    let mut server = timeout(WAIT, listener.accept()).await.unwrap().unwrap();
+    let received = timeout(WAIT, server.receive()).await.unwrap().unwrap();
+    assert!(received.is_none(), "{:?}", received);
+}
+
+#[tokio::test]
+async fn refuses_frames_over_the_listeners_limit() {
+    refuses_over_the_listeners_limit(WireFormat::default()).await;
+}
+
+#[tokio::test]
+async fn refuses_compressed_messages_that_expand_past_the_limit() {
+    refuses_over_the_listeners_limit(WireFormat {
+        binary: true,
+        compression: Some(Compression::Lz4),
+    })
+    .await;
+}
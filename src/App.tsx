@@ -12,8 +12,14 @@ import AnalyticsPanel from './components/AnalyticsPanel';
 interface ConnectionStatus {
   connected: boolean;
   mode: string;
+  state: 'Idle' | 'Listening' | 'Connecting' | 'Handshaking' | 'AwaitingApproval' | 'Active' | 'Reconnecting' | { Error: string };
   remote_address?: string;
   latency_ms?: number;
+  jitter_ms?: number;
+  last_seen_ms?: number;
+  link_state?: 'Connected' | 'Disconnected' | { Reconnecting: { attempt: number; next_retry_at: string } };
+  clock_offset_ms?: number;
+  input_latency?: { samples: number; p50_ms: number; p95_ms: number; p99_ms: number; max_ms: number };
 }
 
 interface PlatformInfo {
@@ -71,11 +77,29 @@ function App() {
                   {connectionStatus.remote_address && (
                     <span className="text-xs text-gray-500">({connectionStatus.remote_address})</span>
                   )}
+                  {connectionStatus.latency_ms != null && (
+                    <span className="text-xs text-gray-500">{connectionStatus.latency_ms} ms</span>
+                  )}
+                  {connectionStatus.input_latency && (
+                    <span className="text-xs text-gray-500">
+                      input p50 {connectionStatus.input_latency.p50_ms.toFixed(1)} / p99 {connectionStatus.input_latency.p99_ms.toFixed(1)} ms
+                    </span>
+                  )}
+                </div>
+              ) : typeof connectionStatus?.link_state === 'object' ? (
+                <div className="flex items-center space-x-2 text-yellow-600">
+                  <Wifi className="h-4 w-4" />
+                  <span className="text-sm font-medium">
+                    Reconnecting (attempt {connectionStatus.link_state.Reconnecting.attempt})
+                  </span>
                 </div>
               ) : (
                 <div className="flex items-center space-x-2 text-gray-500">
                   <WifiOff className="h-4 w-4" />
                   <span className="text-sm">Disconnected</span>
+                  {typeof connectionStatus?.state === 'object' && (
+                    <span className="text-xs text-red-500">{connectionStatus.state.Error}</span>
+                  )}
                 </div>
               )}
             </div>
@@ -1,14 +1,27 @@
 import React, { useState, useEffect } from 'react';
 import { invoke } from '@tauri-apps/api/tauri';
-import { Play, Square, Monitor, Shield, AlertCircle } from 'lucide-react';
+import { Play, Square, Monitor, Shield, AlertCircle, Search } from 'lucide-react';
+
+interface DiscoveredServer {
+  hostname: string;
+  ip: string;
+  port: number;
+  fingerprint: string;
+  protocol_version: number;
+  compatible: boolean;
+  quic_cert_sha256?: string;
+}
 
 function ClientMode() {
   const [isConnected, setIsConnected] = useState(false);
   const [host, setHost] = useState('');
   const [port, setPort] = useState(4242);
   const [fingerprint, setFingerprint] = useState('');
+  const [quicCertSha256, setQuicCertSha256] = useState<string | undefined>();
   const [loading, setLoading] = useState(false);
   const [error, setError] = useState('');
+  const [servers, setServers] = useState<DiscoveredServer[]>([]);
+  const [discovering, setDiscovering] = useState(false);
 
   useEffect(() => {
     // Check if client is already connected
@@ -40,6 +53,7 @@ function ClientMode() {
           port: port,
           protocol: 'WebRTC',
           timeout_ms: 5000,
+          quic_cert_sha256: quicCertSha256,
         },
       });
       setIsConnected(true);
@@ -51,6 +65,30 @@ function ClientMode() {
     }
   };
 
+  const discoverServers = async () => {
+    setDiscovering(true);
+    setError('');
+    try {
+      const found = await invoke<DiscoveredServer[]>('discover_servers', { timeoutMs: 2000 });
+      setServers(found);
+      if (found.length === 0) {
+        setError('No servers found on the local network');
+      }
+    } catch (error) {
+      console.error('Failed to discover servers:', error);
+      setError('Failed to discover servers: ' + error);
+    } finally {
+      setDiscovering(false);
+    }
+  };
+
+  const selectServer = (server: DiscoveredServer) => {
+    setHost(server.ip);
+    setPort(server.port);
+    setFingerprint(server.fingerprint);
+    setQuicCertSha256(server.quic_cert_sha256);
+  };
+
   const disconnectFromServer = async () => {
     setLoading(true);
     try {
@@ -82,6 +120,35 @@ function ClientMode() {
 
         {!isConnected ? (
           <div className="space-y-4">
+            <div>
+              <button
+                onClick={discoverServers}
+                disabled={discovering}
+                className="btn btn-secondary flex items-center space-x-2"
+              >
+                <Search className="h-4 w-4" />
+                <span>{discovering ? 'Searching...' : 'Find Servers'}</span>
+              </button>
+              {servers.length > 0 && (
+                <div className="mt-2 space-y-1">
+                  {servers.map((server) => (
+                    <button
+                      key={server.fingerprint}
+                      onClick={() => selectServer(server)}
+                      disabled={!server.compatible}
+                      className="w-full text-left p-2 rounded-lg border border-gray-200 hover:bg-gray-50 disabled:opacity-50"
+                    >
+                      <span className="text-sm font-medium text-gray-900">{server.hostname}</span>
+                      <span className="text-xs text-gray-500 ml-2">
+                        {server.ip}:{server.port}
+                        {!server.compatible && ' (incompatible version)'}
+                      </span>
+                    </button>
+                  ))}
+                </div>
+              )}
+            </div>
+
             <div>
               <label htmlFor="host" className="block text-sm font-medium text-gray-700 mb-2">
                 Server Address
@@ -9,17 +9,72 @@ interface ServerInfo {
   fingerprint: string;
 }
 
+type ScreenPlacement = 'LeftOf' | 'RightOf' | 'Above' | 'Below' | { Custom: { x: number; y: number } };
+
+interface ClientScreen {
+  fingerprint: string;
+  device_name: string;
+  placement: ScreenPlacement;
+}
+
+interface NetworkInterface {
+  name: string;
+  ipv4: { address: string }[];
+  ipv6: { address: string }[];
+  is_up: boolean;
+  is_loopback: boolean;
+}
+
 function ServerMode() {
   const [isRunning, setIsRunning] = useState(false);
   const [serverInfo, setServerInfo] = useState<ServerInfo | null>(null);
   const [port, setPort] = useState(4242);
   const [loading, setLoading] = useState(false);
+  const [interfaces, setInterfaces] = useState<NetworkInterface[]>([]);
+  const [bind, setBind] = useState('');
+  const [clients, setClients] = useState<ClientScreen[]>([]);
+
+  useEffect(() => {
+    if (!isRunning) {
+      setClients([]);
+      return;
+    }
+    const refresh = async () => {
+      try {
+        setClients(await invoke<ClientScreen[]>('get_connected_clients'));
+      } catch (error) {
+        console.error('Failed to load connected clients:', error);
+      }
+    };
+    refresh();
+    const timer = setInterval(refresh, 2000);
+    return () => clearInterval(timer);
+  }, [isRunning]);
+
+  const placeClient = async (fingerprint: string, placement: ScreenPlacement) => {
+    try {
+      await invoke('set_client_placement', { fingerprint, placement });
+      setClients(await invoke<ClientScreen[]>('get_connected_clients'));
+    } catch (error) {
+      console.error('Failed to place client:', error);
+    }
+  };
 
   useEffect(() => {
     // Check if server is already running
     checkServerStatus();
+    loadInterfaces();
   }, []);
 
+  const loadInterfaces = async () => {
+    try {
+      const found = await invoke<NetworkInterface[]>('get_network_interfaces');
+      setInterfaces(found.filter((iface) => iface.is_up));
+    } catch (error) {
+      console.error('Failed to load network interfaces:', error);
+    }
+  };
+
   const checkServerStatus = async () => {
     try {
       const status = await invoke<{ connected: boolean; mode: string }>('get_connection_status');
@@ -43,6 +98,7 @@ function ServerMode() {
           port: port,
           protocol: 'WebRTC',
           timeout_ms: 5000,
+          bind: bind || null,
         },
       });
       setIsRunning(true);
@@ -104,6 +160,26 @@ function ServerMode() {
                 max="65535"
               />
             </div>
+
+            <div>
+              <label htmlFor="bind" className="block text-sm font-medium text-gray-700 mb-2">
+                Listen On
+              </label>
+              <select
+                id="bind"
+                value={bind}
+                onChange={(e) => setBind(e.target.value)}
+                className="input"
+              >
+                <option value="">All interfaces</option>
+                {interfaces.map((iface) => (
+                  <option key={iface.name} value={iface.name}>
+                    {iface.name}
+                    {iface.ipv4.length > 0 && ` (${iface.ipv4.map((addr) => addr.address).join(', ')})`}
+                  </option>
+                ))}
+              </select>
+            </div>
             
             <button
               onClick={startServer}
@@ -204,6 +280,33 @@ function ServerMode() {
                 </div>
               </div>
             )}
+
+            <div>
+              <label className="block text-sm font-medium text-gray-700 mb-1">
+                Connected Clients ({clients.length})
+              </label>
+              {clients.map((client) => (
+                <div key={client.fingerprint} className="flex items-center justify-between py-1">
+                  <span className="text-sm text-gray-900">{client.device_name}</span>
+                  {typeof client.placement === 'string' ? (
+                    <select
+                      value={client.placement}
+                      onChange={(e) => placeClient(client.fingerprint, e.target.value as ScreenPlacement)}
+                      className="input w-40"
+                    >
+                      <option value="LeftOf">Left of this screen</option>
+                      <option value="RightOf">Right of this screen</option>
+                      <option value="Above">Above this screen</option>
+                      <option value="Below">Below this screen</option>
+                    </select>
+                  ) : (
+                    <span className="text-xs text-gray-500">
+                      Custom ({client.placement.Custom.x}, {client.placement.Custom.y})
+                    </span>
+                  )}
+                </div>
+              ))}
+            </div>
             
             <button
               onClick={stopServer}
@@ -151,6 +151,8 @@ function SettingsPanel({ platformInfo }: SettingsPanelProps) {
               <option value="WebRTC">WebRTC</option>
               <option value="UDP">UDP</option>
               <option value="TCP">TCP</option>
+              <option value="WebSocket">WebSocket</option>
+              <option value="QUIC">QUIC</option>
             </select>
           </div>
         </div>